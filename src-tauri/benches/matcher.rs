@@ -0,0 +1,99 @@
+// Feeds synthetic keystroke streams through the engine's typing buffer, the
+// path every keystroke takes before a trigger is found, and reports the
+// per-keystroke cost. Run with `cargo bench --bench matcher`.
+#[path = "../src/matcher.rs"]
+#[allow(dead_code)]
+mod matcher;
+
+use matcher::{fold_case, TriggerAutomaton, TypingBuffer};
+use std::hint::black_box;
+use std::time::Instant;
+
+const KEYSTROKES: usize = 2_000_000;
+const WORDS: &[&str] = &[
+    "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "thanks", "regards",
+    "ticket", "customer", "/path/to/file", "json", "send", "hello", "world",
+];
+
+// Small LCG so streams are reproducible without extra dependencies
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize
+    }
+}
+
+fn triggers(count: usize) -> Vec<String> {
+    let prefixes = ["/", ";", ":", "@@", ""];
+    (0..count)
+        .map(|i| format!("{}{}{}", prefixes[i % prefixes.len()], WORDS[i % WORDS.len()], i))
+        .collect()
+}
+
+// Words start with a capital now and then, and some typos are corrected
+// with Backspace, as when typing for real
+fn keystrokes(triggers: &[String], rng: &mut Lcg) -> Vec<char> {
+    let mut stream = Vec::with_capacity(KEYSTROKES);
+    while stream.len() < KEYSTROKES {
+        // Roughly one in twenty words is a trigger
+        let word = if rng.next() % 20 == 0 {
+            triggers[rng.next() % triggers.len()].as_str()
+        } else {
+            WORDS[rng.next() % WORDS.len()]
+        };
+        for (i, character) in word.chars().enumerate() {
+            if rng.next() % 50 == 0 {
+                stream.extend(['x', '\x08']);
+            }
+            stream.push(if i == 0 && rng.next() % 10 == 0 { character.to_ascii_uppercase() } else { character });
+        }
+        stream.push(' ');
+    }
+    stream.truncate(KEYSTROKES);
+    stream
+}
+
+fn run(trigger_count: usize) {
+    let triggers = triggers(trigger_count);
+
+    // Keys are stored case-folded, as the engine's index does
+    let started = Instant::now();
+    let folded: Vec<String> = triggers.iter().map(|t| t.chars().map(fold_case).collect()).collect();
+    let automaton = TriggerAutomaton::build(folded.iter().enumerate().map(|(i, t)| (t.as_str(), i)));
+    let build_time = started.elapsed();
+
+    let mut rng = Lcg(trigger_count as u64);
+    let stream = keystrokes(&triggers, &mut rng);
+
+    let started = Instant::now();
+    let mut buffer = TypingBuffer::new();
+    let mut matches = 0usize;
+    for &character in &stream {
+        buffer.push(black_box(character), &automaton);
+        // Literal triggers complete on the character before a delimiter
+        let skip = if character == ' ' { 1 } else { 0 };
+        let found = automaton.matches(buffer.state(skip)).count();
+        if found > 0 {
+            // An expansion starts over with an empty buffer
+            buffer.clear();
+            matches += found;
+        }
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{:>6} triggers: build {:>8.2?}, {:>6.1} ns/keystroke, {} matches",
+        trigger_count,
+        build_time,
+        elapsed.as_nanos() as f64 / stream.len() as f64,
+        black_box(matches),
+    );
+}
+
+fn main() {
+    for count in [10, 100, 1_000, 5_000, 20_000] {
+        run(count);
+    }
+}
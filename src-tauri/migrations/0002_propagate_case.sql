@@ -0,0 +1 @@
+ALTER TABLE snippets ADD COLUMN propagate_case INTEGER DEFAULT 0;
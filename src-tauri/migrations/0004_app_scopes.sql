@@ -0,0 +1,28 @@
+-- A shortcut is now unique per app scope rather than globally. SQLite cannot
+-- drop a column's UNIQUE constraint, so the table is rebuilt.
+CREATE TABLE snippets_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    folder_id INTEGER,
+    name TEXT NOT NULL,
+    shortcut TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    is_active INTEGER DEFAULT 1,
+    match_type TEXT DEFAULT 'exact',
+    propagate_case INTEGER DEFAULT 0,
+    app_include TEXT NOT NULL DEFAULT '[]',
+    app_exclude TEXT NOT NULL DEFAULT '[]',
+    UNIQUE (shortcut, app_include, app_exclude),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (folder_id) REFERENCES folders (id)
+);
+
+INSERT INTO snippets_new (id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case)
+SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case
+FROM snippets;
+
+DROP TABLE snippets;
+
+ALTER TABLE snippets_new RENAME TO snippets;
@@ -0,0 +1,3 @@
+-- Existing snippets were written or imported before commands existed, so
+-- none of them is trusted to run one yet
+ALTER TABLE snippets ADD COLUMN trusted INTEGER NOT NULL DEFAULT 0;
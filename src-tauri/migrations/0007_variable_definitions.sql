@@ -0,0 +1,2 @@
+ALTER TABLE variables ADD COLUMN options TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE variables ADD COLUMN validation TEXT;
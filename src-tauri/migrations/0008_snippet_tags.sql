@@ -0,0 +1 @@
+ALTER TABLE snippets ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
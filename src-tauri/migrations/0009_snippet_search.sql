@@ -0,0 +1,30 @@
+-- Full-text index over the snippets table, which keeps the text itself
+CREATE VIRTUAL TABLE snippets_fts USING fts5(
+    name,
+    shortcut,
+    body,
+    content = 'snippets',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- External content tables are updated by hand; deleting needs the old values
+CREATE TRIGGER snippets_fts_insert AFTER INSERT ON snippets BEGIN
+    INSERT INTO snippets_fts (rowid, name, shortcut, body)
+    VALUES (new.id, new.name, new.shortcut, new.body);
+END;
+
+CREATE TRIGGER snippets_fts_delete AFTER DELETE ON snippets BEGIN
+    INSERT INTO snippets_fts (snippets_fts, rowid, name, shortcut, body)
+    VALUES ('delete', old.id, old.name, old.shortcut, old.body);
+END;
+
+CREATE TRIGGER snippets_fts_update AFTER UPDATE OF name, shortcut, body ON snippets BEGIN
+    INSERT INTO snippets_fts (snippets_fts, rowid, name, shortcut, body)
+    VALUES ('delete', old.id, old.name, old.shortcut, old.body);
+    INSERT INTO snippets_fts (rowid, name, shortcut, body)
+    VALUES (new.id, new.name, new.shortcut, new.body);
+END;
+
+-- Index the snippets that already exist
+INSERT INTO snippets_fts (snippets_fts) VALUES ('rebuild');
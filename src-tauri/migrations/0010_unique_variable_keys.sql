@@ -0,0 +1,6 @@
+-- A snippet's form has one field per name, so each key is defined once per
+-- snippet. Where a key was added twice before, the first definition stays.
+DELETE FROM variables
+WHERE id NOT IN (SELECT MIN(id) FROM variables GROUP BY snippet_id, key);
+
+CREATE UNIQUE INDEX variables_snippet_key ON variables (snippet_id, key);
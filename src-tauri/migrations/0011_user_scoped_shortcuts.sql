@@ -0,0 +1,48 @@
+-- Each user has shortcuts of their own: the same shortcut and app scope may
+-- be taken by another user. The table is rebuilt to change its UNIQUE
+-- constraint, which drops the search triggers on it, so they are made again.
+CREATE TABLE snippets_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    folder_id INTEGER,
+    name TEXT NOT NULL,
+    shortcut TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    is_active INTEGER DEFAULT 1,
+    match_type TEXT DEFAULT 'exact',
+    propagate_case INTEGER DEFAULT 0,
+    app_include TEXT NOT NULL DEFAULT '[]',
+    app_exclude TEXT NOT NULL DEFAULT '[]',
+    trusted INTEGER NOT NULL DEFAULT 0,
+    tags TEXT NOT NULL DEFAULT '[]',
+    UNIQUE (user_id, shortcut, app_include, app_exclude),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (folder_id) REFERENCES folders (id)
+);
+
+INSERT INTO snippets_new (id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include, app_exclude, trusted, tags)
+SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include, app_exclude, trusted, tags
+FROM snippets;
+
+DROP TABLE snippets;
+
+ALTER TABLE snippets_new RENAME TO snippets;
+
+CREATE TRIGGER snippets_fts_insert AFTER INSERT ON snippets BEGIN
+    INSERT INTO snippets_fts (rowid, name, shortcut, body)
+    VALUES (new.id, new.name, new.shortcut, new.body);
+END;
+
+CREATE TRIGGER snippets_fts_delete AFTER DELETE ON snippets BEGIN
+    INSERT INTO snippets_fts (snippets_fts, rowid, name, shortcut, body)
+    VALUES ('delete', old.id, old.name, old.shortcut, old.body);
+END;
+
+CREATE TRIGGER snippets_fts_update AFTER UPDATE OF name, shortcut, body ON snippets BEGIN
+    INSERT INTO snippets_fts (snippets_fts, rowid, name, shortcut, body)
+    VALUES ('delete', old.id, old.name, old.shortcut, old.body);
+    INSERT INTO snippets_fts (rowid, name, shortcut, body)
+    VALUES (new.id, new.name, new.shortcut, new.body);
+END;
@@ -1,5 +1,15 @@
-use crate::database::{User, Folder, Snippet};
+use crate::database::{User, Folder, Snippet, Variable, VariableDefinition};
+use sqlx::types::Json;
+use crate::importers::backup::RestoreOptions;
+use crate::importers::csv::{CsvColumns, CsvImportOptions};
+use crate::importers::espanso::{EspansoExport, EspansoFile};
+use crate::importers::ImportReport;
+use crate::forms::SnippetForm;
+use crate::search::{SearchRequest, SearchResult};
+use crate::vars::FormValues;
+use crate::settings::Settings;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tauri::State;
 use crate::AppState;
 
@@ -21,6 +31,11 @@ pub struct CreateSnippetRequest {
     shortcut: String,
     body: String,
     folder_id: Option<i64>,
+    match_type: Option<String>,
+    propagate_case: Option<bool>,
+    app_include: Option<Vec<String>>,
+    app_exclude: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,13 +45,11 @@ pub struct UpdateSnippetRequest {
     body: Option<String>,
     folder_id: Option<i64>,
     is_active: Option<bool>,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct Settings {
-    pub expand_enabled: bool,
-    pub global_hotkey: String,
-    pub excluded_apps: Vec<String>,
+    match_type: Option<String>,
+    propagate_case: Option<bool>,
+    app_include: Option<Vec<String>>,
+    app_exclude: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
 }
 
 #[tauri::command]
@@ -144,19 +157,27 @@ pub async fn create_folder(
     })
 }
 
+// A snippet as the snippet list shows it, with its variable definitions
+#[derive(Serialize)]
+pub struct SnippetWithVariables {
+    #[serde(flatten)]
+    pub snippet: Snippet,
+    pub variables: Vec<Variable>,
+}
+
 #[tauri::command]
 pub async fn list_snippets(
     user_id: i64,
     folder_id: Option<i64>,
     state: State<'_, AppState>,
-) -> Result<Vec<Snippet>, String> {
+) -> Result<Vec<SnippetWithVariables>, String> {
     let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
     let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
     
     let snippets = if let Some(folder_id) = folder_id {
         sqlx::query_as!(
             Snippet,
-            "SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type FROM snippets WHERE user_id = ? AND folder_id = ?",
+            "SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include as \"app_include: sqlx::types::Json<Vec<String>>\", app_exclude as \"app_exclude: sqlx::types::Json<Vec<String>>\", trusted, tags as \"tags: sqlx::types::Json<Vec<String>>\" FROM snippets WHERE user_id = ? AND folder_id = ?",
             user_id,
             folder_id
         )
@@ -165,7 +186,7 @@ pub async fn list_snippets(
     } else {
         sqlx::query_as!(
             Snippet,
-            "SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type FROM snippets WHERE user_id = ?",
+            "SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include as \"app_include: sqlx::types::Json<Vec<String>>\", app_exclude as \"app_exclude: sqlx::types::Json<Vec<String>>\", trusted, tags as \"tags: sqlx::types::Json<Vec<String>>\" FROM snippets WHERE user_id = ?",
             user_id
         )
         .fetch_all(db)
@@ -173,7 +194,41 @@ pub async fn list_snippets(
     }
     .map_err(|e| format!("Database error: {}", e))?;
     
-    Ok(snippets)
+    let variables = sqlx::query_as!(
+        Variable,
+        "SELECT v.id, v.snippet_id, v.key, v.type as var_type, v.default_value, v.options as \"options: sqlx::types::Json<Vec<String>>\", v.validation FROM variables v JOIN snippets s ON s.id = v.snippet_id WHERE s.user_id = ? ORDER BY v.id",
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+    
+    let mut by_snippet: HashMap<i64, Vec<Variable>> = HashMap::new();
+    for variable in variables {
+        by_snippet.entry(variable.snippet_id).or_default().push(variable);
+    }
+    
+    Ok(snippets
+        .into_iter()
+        .map(|snippet| SnippetWithVariables {
+            variables: by_snippet.remove(&snippet.id).unwrap_or_default(),
+            snippet,
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn search_snippets(
+    user_id: i64,
+    request: SearchRequest,
+    state: State<'_, AppState>,
+) -> Result<Vec<SearchResult>, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::search::search(db, user_id, &request)
+        .await
+        .map_err(|e| format!("Database error: {}", e))
 }
 
 #[tauri::command]
@@ -185,21 +240,41 @@ pub async fn create_snippet(
     let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
     let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
     
+    let match_type = request.match_type.unwrap_or_else(|| "exact".to_string());
+    crate::engine::validate_trigger(&request.shortcut, &match_type)?;
+    validate_body(&request.body)?;
+    let propagate_case = request.propagate_case.unwrap_or(false);
+    let app_include = Json(validate_app_patterns(request.app_include.unwrap_or_default())?);
+    let app_exclude = Json(validate_app_patterns(request.app_exclude.unwrap_or_default())?);
+    let tags = Json(validate_tags(request.tags.unwrap_or_default())?);
+    // Commands only run once the user approves them, see approve_snippet
+    let trusted = !crate::vars::runs_commands(&request.body);
+    
     let now = chrono::Utc::now();
     let result = sqlx::query!(
-        "INSERT INTO snippets (user_id, folder_id, name, shortcut, body, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        "INSERT INTO snippets (user_id, folder_id, name, shortcut, body, created_at, updated_at, match_type, propagate_case, app_include, app_exclude, trusted, tags) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         user_id,
         request.folder_id,
         request.name,
         request.shortcut,
         request.body,
         now,
-        now
+        now,
+        match_type,
+        propagate_case,
+        app_include,
+        app_exclude,
+        trusted,
+        tags
     )
     .execute(db)
     .await
     .map_err(|e| format!("Database error: {}", e))?;
     
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
     Ok(Snippet {
         id: result.last_insert_rowid(),
         user_id,
@@ -210,7 +285,12 @@ pub async fn create_snippet(
         created_at: now,
         updated_at: now,
         is_active: true,
-        match_type: "exact".to_string(),
+        match_type,
+        propagate_case,
+        app_include,
+        app_exclude,
+        trusted,
+        tags,
     })
 }
 
@@ -223,45 +303,140 @@ pub async fn update_snippet(
     let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
     let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
     
+    if request.shortcut.is_some() || request.match_type.is_some() {
+        let current = sqlx::query!(
+            "SELECT shortcut, match_type FROM snippets WHERE id = ?",
+            snippet_id
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?
+        .ok_or("Snippet not found")?;
+        
+        let shortcut = request.shortcut.as_deref().unwrap_or(&current.shortcut);
+        let match_type = request
+            .match_type
+            .as_deref()
+            .or(current.match_type.as_deref())
+            .unwrap_or("exact");
+        crate::engine::validate_trigger(shortcut, match_type)?;
+    }
+    
     let now = chrono::Utc::now();
     
     // Build dynamic query based on provided fields
-    let mut query = "UPDATE snippets SET updated_at = ?".to_string();
-    let mut params: Vec<Box<dyn sqlx::Encode<'_, sqlx::Sqlite> + Send + 'static>> = vec![];
+    let mut query = sqlx::QueryBuilder::<sqlx::Sqlite>::new("UPDATE snippets SET updated_at = ");
+    query.push_bind(now);
     
     if let Some(name) = request.name {
-        query.push_str(", name = ?");
-        params.push(Box::new(name));
+        query.push(", name = ").push_bind(name);
     }
     if let Some(shortcut) = request.shortcut {
-        query.push_str(", shortcut = ?");
-        params.push(Box::new(shortcut));
+        query.push(", shortcut = ").push_bind(shortcut);
     }
     if let Some(body) = request.body {
-        query.push_str(", body = ?");
-        params.push(Box::new(body));
+        validate_body(&body)?;
+        let current = sqlx::query!("SELECT body FROM snippets WHERE id = ?", snippet_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or("Snippet not found")?;
+        // New or changed commands need approving again
+        if crate::vars::adds_commands(&current.body, &body) {
+            query.push(", trusted = 0");
+        }
+        query.push(", body = ").push_bind(body);
     }
     if let Some(folder_id) = request.folder_id {
-        query.push_str(", folder_id = ?");
-        params.push(Box::new(folder_id));
+        query.push(", folder_id = ").push_bind(folder_id);
     }
     if let Some(is_active) = request.is_active {
-        query.push_str(", is_active = ?");
-        params.push(Box::new(is_active as i32));
+        query.push(", is_active = ").push_bind(is_active as i32);
+    }
+    if let Some(match_type) = request.match_type {
+        query.push(", match_type = ").push_bind(match_type);
+    }
+    if let Some(propagate_case) = request.propagate_case {
+        query.push(", propagate_case = ").push_bind(propagate_case as i32);
+    }
+    if let Some(app_include) = request.app_include {
+        query.push(", app_include = ").push_bind(Json(validate_app_patterns(app_include)?));
     }
+    if let Some(app_exclude) = request.app_exclude {
+        query.push(", app_exclude = ").push_bind(Json(validate_app_patterns(app_exclude)?));
+    }
+    if let Some(tags) = request.tags {
+        query.push(", tags = ").push_bind(Json(validate_tags(tags)?));
+    }
+    
+    query.push(" WHERE id = ").push_bind(snippet_id);
     
-    query.push_str(" WHERE id = ?");
+    query
+        .build()
+        .execute(db)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(())
+}
+
+// Lets a snippet run its {{shell}} and {{script}} commands, or stops it.
+// Editing a snippet never approves it; this is the only way.
+#[tauri::command]
+pub async fn approve_snippet(
+    snippet_id: i64,
+    approved: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
     
-    sqlx::query(&query)
-        .bind(now)
-        .bind(snippet_id)
+    let result = sqlx::query!("UPDATE snippets SET trusted = ? WHERE id = ?", approved, snippet_id)
         .execute(db)
         .await
         .map_err(|e| format!("Database error: {}", e))?;
+    if result.rows_affected() == 0 {
+        return Err("Snippet not found".to_string());
+    }
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
     
     Ok(())
 }
 
+fn validate_body(body: &str) -> Result<(), String> {
+    crate::vars::validate(body).map_err(|e| format!("Invalid snippet body: {}", e))
+}
+
+fn validate_app_patterns(patterns: Vec<String>) -> Result<Vec<String>, String> {
+    let patterns: Vec<String> = patterns.into_iter().map(|p| p.trim().to_string()).collect();
+    if patterns.iter().any(|p| p.is_empty()) {
+        return Err("App patterns cannot be empty".to_string());
+    }
+    Ok(patterns)
+}
+
+// Trimmed, and each tag once in the order first given
+fn validate_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut unique: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim().to_string();
+        if tag.is_empty() {
+            return Err("Tags cannot be empty".to_string());
+        }
+        if !unique.contains(&tag) {
+            unique.push(tag);
+        }
+    }
+    Ok(unique)
+}
+
 #[tauri::command]
 pub async fn delete_snippet(
     snippet_id: i64,
@@ -270,41 +445,334 @@ pub async fn delete_snippet(
     let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
     let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
     
+    // Both or neither, so no variables outlive their snippet
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    
+    sqlx::query!("DELETE FROM variables WHERE snippet_id = ?", snippet_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    
     sqlx::query!("DELETE FROM snippets WHERE id = ?", snippet_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    
+    tx.commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_variables(
+    snippet_id: i64,
+    state: State<'_, AppState>,
+) -> Result<Vec<Variable>, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    sqlx::query_as!(
+        Variable,
+        "SELECT id, snippet_id, key, type as var_type, default_value, options as \"options: sqlx::types::Json<Vec<String>>\", validation FROM variables WHERE snippet_id = ? ORDER BY id",
+        snippet_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_variable(
+    user_id: i64,
+    snippet_id: i64,
+    request: VariableDefinition,
+    state: State<'_, AppState>,
+) -> Result<Variable, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let request = clean_variable(request)?;
+    let options = Json(request.options);
+    // Only ever attached to a snippet of the same user
+    let result = sqlx::query!(
+        "INSERT INTO variables (snippet_id, key, type, default_value, options, validation) SELECT id, ?, ?, ?, ?, ? FROM snippets WHERE id = ? AND user_id = ?",
+        request.key,
+        request.var_type,
+        request.default_value,
+        options,
+        request.validation,
+        snippet_id,
+        user_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| variable_error(&request.key, e))?;
+    
+    if result.rows_affected() == 0 {
+        return Err("Snippet not found".to_string());
+    }
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(Variable {
+        id: result.last_insert_rowid(),
+        snippet_id,
+        key: request.key,
+        var_type: request.var_type,
+        default_value: request.default_value,
+        options,
+        validation: request.validation,
+    })
+}
+
+#[tauri::command]
+pub async fn update_variable(
+    variable_id: i64,
+    request: VariableDefinition,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let request = clean_variable(request)?;
+    let options = Json(request.options);
+    let result = sqlx::query!(
+        "UPDATE variables SET key = ?, type = ?, default_value = ?, options = ?, validation = ? WHERE id = ?",
+        request.key,
+        request.var_type,
+        request.default_value,
+        options,
+        request.validation,
+        variable_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| variable_error(&request.key, e))?;
+    
+    if result.rows_affected() == 0 {
+        return Err("Variable not found".to_string());
+    }
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_variable(
+    variable_id: i64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    sqlx::query!("DELETE FROM variables WHERE id = ?", variable_id)
         .execute(db)
         .await
         .map_err(|e| format!("Database error: {}", e))?;
     
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
     Ok(())
 }
 
+// A key is defined once per snippet, see migration 0010
+fn variable_error(key: &str, error: sqlx::Error) -> String {
+    match error {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            format!("This snippet already has a variable named {:?}", key)
+        }
+        e => format!("Database error: {}", e),
+    }
+}
+
+// Trims the key and options and drops empty ones, then checks the result
+fn clean_variable(mut variable: VariableDefinition) -> Result<VariableDefinition, String> {
+    variable.key = variable.key.trim().to_string();
+    variable.options = variable
+        .options
+        .into_iter()
+        .map(|option| option.trim().to_string())
+        .filter(|option| !option.is_empty())
+        .collect();
+    variable.validation = variable.validation.filter(|pattern| !pattern.is_empty());
+    crate::vars::validate_variable(&variable)?;
+    Ok(variable)
+}
+
 #[tauri::command]
 pub async fn import_textblaze(
     user_id: i64,
     json_data: String,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let report = crate::importers::textblaze::import(db, user_id, &json_data).await?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn import_csv(
+    user_id: i64,
+    csv_data: String,
+    options: Option<CsvImportOptions>,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let options = options.unwrap_or_default();
+    let report = crate::importers::csv::import(db, user_id, &csv_data, &options).await?;
+    
+    if !report.dry_run {
+        crate::engine::reload_snippets(db)
+            .await
+            .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    }
+    
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn export_csv(
+    user_id: i64,
+    columns: Option<CsvColumns>,
+    delimiter: Option<char>,
+    state: State<'_, AppState>,
 ) -> Result<String, String> {
-    // Parse JSON and import snippets
-    // This is a simplified implementation
-    Ok("Import completed".to_string())
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::importers::csv::export(db, user_id, &columns.unwrap_or_default(), delimiter).await
+}
+
+#[tauri::command]
+pub async fn import_espanso(
+    user_id: i64,
+    files: Vec<EspansoFile>,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let report = crate::importers::espanso::import(db, user_id, &files).await?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn export_espanso(
+    user_id: i64,
+    state: State<'_, AppState>,
+) -> Result<EspansoExport, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::importers::espanso::export(db, user_id).await
+}
+
+#[tauri::command]
+pub async fn export_backup(
+    user_id: i64,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::importers::backup::export(db, user_id).await
+}
+
+#[tauri::command]
+pub async fn import_backup(
+    user_id: i64,
+    json_data: String,
+    options: Option<RestoreOptions>,
+    state: State<'_, AppState>,
+) -> Result<ImportReport, String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    let options = options.unwrap_or_default();
+    let report = crate::importers::backup::restore(db, user_id, &json_data, &options).await?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn get_snippet_form(id: u64) -> Result<SnippetForm, String> {
+    crate::forms::get(id).ok_or_else(|| "Form is no longer open".to_string())
+}
+
+#[tauri::command]
+pub async fn submit_snippet_form(
+    id: u64,
+    values: Option<FormValues>,
+) -> Result<(), String> {
+    match values {
+        Some(values) => crate::forms::submit(id, values),
+        None => {
+            crate::forms::finish(id, None);
+            Ok(())
+        }
+    }
 }
 
 #[tauri::command]
 pub async fn get_settings() -> Result<Settings, String> {
-    Ok(Settings {
-        expand_enabled: true,
-        global_hotkey: "Ctrl+Alt+Space".to_string(),
-        excluded_apps: vec![],
-    })
+    Ok(crate::settings::current())
 }
 
 #[tauri::command]
-pub async fn update_settings(settings: Settings) -> Result<(), String> {
-    // Save settings to config file or database
+pub async fn update_settings(
+    settings: Settings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::settings::save(db, &settings).await?;
+    crate::settings::apply(settings);
     Ok(())
 }
 
 #[tauri::command]
-pub async fn reload_engine() -> Result<(), String> {
-    crate::expander::reload();
+pub async fn reload_engine(state: State<'_, AppState>) -> Result<(), String> {
+    let state_lock = state.lock().map_err(|_| "Failed to lock state")?;
+    let db = state_lock.db.as_ref().ok_or("Database not initialized")?;
+    
+    crate::engine::reload_snippets(db)
+        .await
+        .map_err(|e| format!("Failed to reload snippets: {}", e))?;
+    
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,427 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// Time left to collect output a command wrote just before its deadline
+const OUTPUT_GRACE: Duration = Duration::from_millis(100);
+// Output beyond this is almost certainly a mistake, not something to paste
+const MAX_OUTPUT_BYTES: u64 = 64 * 1024;
+// Shell commands read nested placeholder values from SPARK_VALUE_1, _2, ...
+const VALUE_VARIABLE_PREFIX: &str = "SPARK_VALUE_";
+
+// A command line as written in a snippet: its own text, and the values of
+// placeholders nested in it. Values are never parsed as part of the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandPart {
+    Text(String),
+    Value(String),
+}
+
+// Settings for {{shell:...}} and {{script:...}}, written as their second
+// argument: `timeout=10,cwd=~/src,env=LANG=C,trim=false`. `env` may repeat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOptions {
+    pub timeout: Duration,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    // Strip leading and trailing whitespace, including the final newline
+    pub trim: bool,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        CommandOptions {
+            timeout: DEFAULT_TIMEOUT,
+            cwd: None,
+            env: Vec::new(),
+            trim: true,
+        }
+    }
+}
+
+impl CommandOptions {
+    pub fn parse<'a>(options: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut parsed = CommandOptions::default();
+        for option in options.into_iter().map(str::trim).filter(|option| !option.is_empty()) {
+            let Some((key, value)) = option.split_once('=') else {
+                return Err(format!("option {:?} is not key=value", option));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "timeout" => {
+                    let seconds: f64 = value
+                        .trim_end_matches('s')
+                        .parse()
+                        .map_err(|_| format!("timeout {:?} is not a number of seconds", value))?;
+                    if !(seconds > 0.0 && seconds <= MAX_TIMEOUT.as_secs_f64()) {
+                        return Err(format!("timeout must be more than 0 and at most {} seconds", MAX_TIMEOUT.as_secs()));
+                    }
+                    parsed.timeout = Duration::from_secs_f64(seconds);
+                }
+                "cwd" => parsed.cwd = Some(expand_home(value).into()),
+                "env" => match value.split_once('=') {
+                    Some((name, env_value)) if !name.trim().is_empty() => {
+                        parsed.env.push((name.trim().to_string(), env_value.to_string()));
+                    }
+                    _ => return Err(format!("env {:?} is not NAME=value", value)),
+                },
+                "trim" => {
+                    parsed.trim = value
+                        .parse()
+                        .map_err(|_| format!("trim must be true or false, not {:?}", value))?;
+                }
+                other => return Err(format!("unknown option {:?}", other)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+// Runs a command line through the system shell, so pipes and variables work.
+// Each value goes to the shell in an environment variable and the command
+// line only names that variable, so a value like `'; rm -rf ~` stays data.
+// The template parser makes sure values sit inside single quotes, where
+// `'"$NAME"'` can stand in for them; cmd has no such quotes and keeps them
+// as typed.
+pub fn run_shell(parts: &[CommandPart], options: &CommandOptions) -> Result<String, String> {
+    let mut command_line = String::new();
+    let mut values = Vec::new();
+    for part in parts {
+        match part {
+            CommandPart::Text(text) => command_line.push_str(text),
+            CommandPart::Value(value) => {
+                let name = format!("{}{}", VALUE_VARIABLE_PREFIX, values.len() + 1);
+                if cfg!(target_os = "windows") {
+                    command_line.push_str(&format!("!{}!", name));
+                } else {
+                    command_line.push_str(&format!("'\"${}\"'", name));
+                }
+                values.push((name, value));
+            }
+        }
+    }
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        // Delayed expansion reads the variables after the line is parsed. It
+        // also gives `!` a meaning, so only when there are values to read.
+        if !values.is_empty() {
+            command.arg("/V:ON");
+        }
+        command.arg("/C").arg(command_line.trim());
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(command_line.trim());
+        command
+    };
+    command.envs(values);
+    run(&mut command, options)
+}
+
+// Runs a program directly, without a shell. Arguments split on whitespace
+// unless quoted, and a leading `~` means the home directory. Values become
+// part of the word they are in, whatever they contain.
+pub fn run_script(parts: &[CommandPart], options: &CommandOptions) -> Result<String, String> {
+    let words = split_words(parts)?;
+    let Some((program, args)) = words.split_first() else {
+        return Err("command is empty".to_string());
+    };
+    let mut command = Command::new(expand_home(program));
+    command.args(args.iter().map(|arg| expand_home(arg)));
+    run(&mut command, options)
+}
+
+fn run(command: &mut Command, options: &CommandOptions) -> Result<String, String> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .envs(options.env.iter().map(|(name, value)| (name, value)));
+    if let Some(cwd) = &options.cwd {
+        command.current_dir(cwd);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW, so no console flashes up while expanding
+        command.creation_flags(0x0800_0000);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let deadline = Instant::now() + options.timeout;
+    let mut child = command.spawn().map_err(|e| format!("failed to start: {}", e))?;
+    let tree = ProcessTree::new(&child);
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let timed_out = || format!("timed out after {} seconds", options.timeout.as_secs_f64());
+    let status = match wait_until(&mut child, deadline) {
+        Ok(Some(status)) => Ok(status),
+        Ok(None) => Err(timed_out()),
+        Err(e) => Err(e),
+    };
+    // Whatever the command left running in the background would keep its
+    // output open, and us waiting on it
+    tree.kill();
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+
+    let stdout = receive_until(&stdout, deadline).ok_or_else(timed_out)?;
+    let stderr = receive_until(&stderr, deadline).ok_or_else(timed_out)?;
+    if stdout.len() as u64 > MAX_OUTPUT_BYTES {
+        return Err(format!("output is larger than {} KB", MAX_OUTPUT_BYTES / 1024));
+    }
+    if !status.success() {
+        let message = String::from_utf8_lossy(&stderr);
+        let message = message.trim().lines().next().unwrap_or_default();
+        return Err(match status.code() {
+            Some(code) if message.is_empty() => format!("exited with status {}", code),
+            Some(code) => format!("exited with status {}: {}", code, message),
+            None => "was terminated".to_string(),
+        });
+    }
+
+    let output = String::from_utf8_lossy(&stdout);
+    Ok(if options.trim { output.trim().to_string() } else { output.into_owned() })
+}
+
+// Reads a pipe to its end on another thread, so a chatty child cannot block
+// on a full pipe while we wait for it to exit. Stops one byte past the limit
+// so oversized output is detected without being buffered.
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(pipe) = pipe {
+            let _ = pipe.take(MAX_OUTPUT_BYTES + 1).read_to_end(&mut output);
+        }
+        let _ = sender.send(output);
+    });
+    receiver
+}
+
+// A pipe's output once it closes, or None if it is still open at the
+// deadline. A process that left the command's group can hold it open for
+// good; its reader thread is then left behind rather than waited on.
+fn receive_until(output: &Receiver<Vec<u8>>, deadline: Instant) -> Option<Vec<u8>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    output.recv_timeout(remaining.max(OUTPUT_GRACE)).ok()
+}
+
+// The child's exit status, or None if it is still running at the deadline
+fn wait_until(child: &mut Child, deadline: Instant) -> Result<Option<std::process::ExitStatus>, String> {
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(Some(status)),
+            Ok(None) if Instant::now() >= deadline => return Ok(None),
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("failed to wait: {}", e)),
+        }
+    }
+}
+
+// A command and every process it starts, so that all of them can be
+// stopped together: its process group on unix, a job object on Windows
+struct ProcessTree {
+    #[cfg(unix)]
+    group: libc::pid_t,
+    #[cfg(target_os = "windows")]
+    job: winapi::um::winnt::HANDLE,
+}
+
+impl ProcessTree {
+    // The child leads a process group of its own, see `run`
+    #[cfg(unix)]
+    fn new(child: &Child) -> Self {
+        ProcessTree { group: child.id() as libc::pid_t }
+    }
+
+    // Processes the command starts before it is added to the job escape it
+    #[cfg(target_os = "windows")]
+    fn new(child: &Child) -> Self {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+        use winapi::um::winnt::{
+            JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if !job.is_null() {
+                let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                SetInformationJobObject(
+                    job,
+                    JobObjectExtendedLimitInformation,
+                    &mut limits as *mut _ as *mut _,
+                    std::mem::size_of_val(&limits) as u32,
+                );
+                AssignProcessToJobObject(job, child.as_raw_handle() as _);
+            }
+            ProcessTree { job }
+        }
+    }
+
+    fn kill(&self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::killpg(self.group, libc::SIGKILL);
+        }
+        #[cfg(target_os = "windows")]
+        unsafe {
+            if !self.job.is_null() {
+                winapi::um::jobapi2::TerminateJobObject(self.job, 1);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        if !self.job.is_null() {
+            unsafe {
+                winapi::um::handleapi::CloseHandle(self.job);
+            }
+        }
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => rest,
+        _ => return path.to_string(),
+    };
+    match dirs::home_dir() {
+        Some(home) => format!("{}{}", home.to_string_lossy(), rest),
+        None => path.to_string(),
+    }
+}
+
+// Whitespace-separated words; single or double quotes group words and are
+// removed. Only text parts are split.
+fn split_words(parts: &[CommandPart]) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+
+    for part in parts {
+        let text = match part {
+            CommandPart::Text(text) => text,
+            CommandPart::Value(value) => {
+                word.get_or_insert_with(String::new).push_str(value);
+                continue;
+            }
+        };
+
+        for character in text.chars() {
+            match quote {
+                Some(open) if character == open => quote = None,
+                Some(_) => word.get_or_insert_with(String::new).push(character),
+                None if character == '"' || character == '\'' => {
+                    quote = Some(character);
+                    word.get_or_insert_with(String::new);
+                }
+                None if character.is_whitespace() => words.extend(word.take()),
+                None => word.get_or_insert_with(String::new).push(character),
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err("command has an unclosed quote".to_string());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> CommandPart {
+        CommandPart::Text(text.to_string())
+    }
+
+    fn value(value: &str) -> CommandPart {
+        CommandPart::Value(value.to_string())
+    }
+
+    #[test]
+    fn splits_text_but_not_values() {
+        assert_eq!(split_words(&[text(" ls  -l 'My Files' \"a b\"c ")]).unwrap(), ["ls", "-l", "My Files", "a bc"]);
+        assert_eq!(split_words(&[text("echo ''")]).unwrap(), ["echo", ""]);
+        assert_eq!(
+            split_words(&[text("grep -e "), value("a b' \"c"), text(" 'in "), value("x y"), text("'")]).unwrap(),
+            ["grep", "-e", "a b' \"c", "in x y"]
+        );
+        assert!(split_words(&[text("echo 'oops")]).is_err());
+        assert!(split_words(&[]).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_values_are_data() {
+        let options = CommandOptions::default();
+        let injected = "'; echo injected; echo '";
+        let output = run_shell(&[text("printf %s '"), value(injected), text("'")], &options).unwrap();
+        assert_eq!(output, injected);
+
+        let output = run_shell(&[text("echo 'a' '"), value("$HOME `id` $(id)"), text("-"), value("2"), text("'")], &options);
+        assert_eq!(output.unwrap(), "a $HOME `id` $(id)-2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn script_values_stay_one_argument() {
+        let options = CommandOptions::default();
+        let output = run_script(&[text("printf [%s] "), value("a b"), text(" c")], &options).unwrap();
+        assert_eq!(output, "[a b][c]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn background_processes_do_not_outlive_the_command() {
+        let options = CommandOptions::parse(["timeout=1"]).unwrap();
+        let started = Instant::now();
+        let output = run_shell(&[text("sleep 30 & echo x")], &options);
+        assert_eq!(output.unwrap(), "x");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let started = Instant::now();
+        let output = run_shell(&[text("sleep 30 & sleep 30")], &options);
+        assert_eq!(output.unwrap_err(), "timed out after 1 seconds");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = CommandOptions::parse(["timeout=1.5s", " env=LANG=C ", "env=X=a=b", "trim=false", ""]).unwrap();
+        assert_eq!(options.timeout, Duration::from_millis(1500));
+        assert_eq!(options.env, [("LANG".to_string(), "C".to_string()), ("X".to_string(), "a=b".to_string())]);
+        assert!(!options.trim);
+
+        assert!(CommandOptions::parse(["timeout=0"]).is_err());
+        assert!(CommandOptions::parse(["timeout=61"]).is_err());
+        assert!(CommandOptions::parse(["env==x"]).is_err());
+        assert!(CommandOptions::parse(["verbose"]).is_err());
+        assert!(CommandOptions::parse(["colour=red"]).is_err());
+    }
+}
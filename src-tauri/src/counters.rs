@@ -0,0 +1,27 @@
+use sqlx::SqlitePool;
+use std::sync::OnceLock;
+
+// Rendering is synchronous and runs outside the async runtime, so counters
+// keep their own handle to the database rather than going through AppState
+static DB: OnceLock<SqlitePool> = OnceLock::new();
+
+pub fn init(db: SqlitePool) {
+    let _ = DB.set(db);
+}
+
+// Increments the named counter and returns the new value, starting at 1.
+// A single statement, so two expansions can never get the same value.
+// The value is taken while rendering, before anything is typed, so an
+// expansion that fails or cannot be injected afterwards leaves a gap.
+// That is intended: numbers are unique, not contiguous, and holding one back
+// until injection would let two expansions in flight hand out the same one.
+pub fn next(name: &str) -> Result<i64, String> {
+    let db = DB.get().ok_or("Database not initialized")?;
+    let query = sqlx::query_scalar::<_, i64>(
+        "INSERT INTO counters (name, value) VALUES (?, 1) ON CONFLICT (name) DO UPDATE SET value = value + 1 RETURNING value",
+    )
+    .bind(name)
+    .fetch_one(db);
+
+    tauri::async_runtime::block_on(query).map_err(|e| format!("Database error: {}", e))
+}
@@ -1,4 +1,5 @@
 use sqlx::{SqlitePool, Row};
+use sqlx::types::Json;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
@@ -18,7 +19,7 @@ pub struct Folder {
     pub parent_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
 pub struct Snippet {
     pub id: i64,
     pub user_id: i64,
@@ -30,15 +31,43 @@ pub struct Snippet {
     pub updated_at: DateTime<Utc>,
     pub is_active: bool,
     pub match_type: String,
+    pub propagate_case: bool,
+    // Window class or process name patterns; empty means every app
+    pub app_include: Json<Vec<String>>,
+    pub app_exclude: Json<Vec<String>>,
+    // Approved to run {{shell}} and {{script}} commands. Snippets start
+    // trusted only without commands; imported ones, and edits that add a
+    // command, wait for approval.
+    pub trusted: bool,
+    pub tags: Json<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Variable {
     pub id: i64,
     pub snippet_id: i64,
+    // Name of the form field it describes
+    pub key: String,
+    pub var_type: String,
+    pub default_value: Option<String>,
+    // Choices for select and multiselect fields
+    pub options: Json<Vec<String>>,
+    // Regex the whole submitted value must match
+    pub validation: Option<String>,
+}
+
+// A variable as written by the user or read from an import, before it has
+// an id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VariableDefinition {
     pub key: String,
+    #[serde(rename = "type")]
     pub var_type: String,
     pub default_value: Option<String>,
+    #[serde(default)]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub validation: Option<String>,
 }
 
 pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
@@ -47,71 +76,28 @@ pub async fn init_db() -> Result<SqlitePool, sqlx::Error> {
     
     let pool = SqlitePool::connect(&format!("sqlite://{}", db_path)).await?;
     
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            email TEXT UNIQUE NOT NULL,
-            password_hash TEXT NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-        )
-        "#,
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS folders (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL,
-            name TEXT NOT NULL,
-            parent_id INTEGER,
-            FOREIGN KEY (user_id) REFERENCES users (id),
-            FOREIGN KEY (parent_id) REFERENCES folders (id)
-        )
-        "#,
-    )
-    .execute(&pool)
-    .await?;
+    crate::migrations::run(&pool, Some(std::path::Path::new(&db_path))).await?;
+    
+    Ok(pool)
+}
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS snippets (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL,
-            folder_id INTEGER,
-            name TEXT NOT NULL,
-            shortcut TEXT UNIQUE NOT NULL,
-            body TEXT NOT NULL,
-            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-            is_active INTEGER DEFAULT 1,
-            match_type TEXT DEFAULT 'exact',
-            FOREIGN KEY (user_id) REFERENCES users (id),
-            FOREIGN KEY (folder_id) REFERENCES folders (id)
-        )
-        "#,
+pub async fn load_active_snippets(pool: &SqlitePool) -> Result<Vec<Snippet>, sqlx::Error> {
+    sqlx::query_as!(
+        Snippet,
+        "SELECT id, user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include as \"app_include: sqlx::types::Json<Vec<String>>\", app_exclude as \"app_exclude: sqlx::types::Json<Vec<String>>\", trusted, tags as \"tags: sqlx::types::Json<Vec<String>>\" FROM snippets WHERE is_active = 1"
     )
-    .execute(&pool)
-    .await?;
+    .fetch_all(pool)
+    .await
+}
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS variables (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            snippet_id INTEGER NOT NULL,
-            key TEXT NOT NULL,
-            type TEXT NOT NULL,
-            default_value TEXT,
-            FOREIGN KEY (snippet_id) REFERENCES snippets (id)
-        )
-        "#,
+// Variables of every active snippet, for the engine's in-memory index
+pub async fn load_active_variables(pool: &SqlitePool) -> Result<Vec<Variable>, sqlx::Error> {
+    sqlx::query_as!(
+        Variable,
+        "SELECT v.id, v.snippet_id, v.key, v.type as var_type, v.default_value, v.options as \"options: sqlx::types::Json<Vec<String>>\", v.validation FROM variables v JOIN snippets s ON s.id = v.snippet_id WHERE s.is_active = 1 ORDER BY v.id"
     )
-    .execute(&pool)
-    .await?;
-
-    Ok(pool)
+    .fetch_all(pool)
+    .await
 }
 
 fn get_db_path() -> String {
@@ -0,0 +1,351 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Days, Duration, Local, Locale, Months, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use std::fmt::{Display, Write};
+
+// Renders the argument of {{date:...}} and {{time:...}}: a strftime format
+// followed by `|`-separated modifiers, e.g. `%A %-d %B|+3bd|tz=Europe/Berlin|locale=de_DE`.
+// Shifts apply left to right in the chosen time zone (local by default).
+// `now` is passed in rather than read here so a fixed clock gives a fixed
+// result.
+pub fn format(spec: &str, now: DateTime<Utc>) -> Result<String, String> {
+    let mut parts = spec.split('|');
+    let format = parts.next().unwrap_or_default();
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid format {:?}", format));
+    }
+
+    let mut zone = None;
+    let mut locale = None;
+    let mut shifts = Vec::new();
+    for part in parts.map(str::trim).filter(|part| !part.is_empty()) {
+        if let Some(name) = part.strip_prefix("tz=") {
+            let name = name.trim();
+            zone = Some(name.parse::<Tz>().map_err(|_| format!("unknown time zone {:?}", name))?);
+        } else if let Some(name) = part.strip_prefix("locale=") {
+            locale = Some(parse_locale(name.trim())?);
+        } else {
+            shifts.push(parse_shift(part)?);
+        }
+    }
+
+    match zone {
+        Some(zone) => render(now.with_timezone(&zone), &shifts, format, locale),
+        None => render(now.with_timezone(&Local), &shifts, format, locale),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shift {
+    // Elapsed time, unaffected by daylight saving changes
+    Seconds(i64),
+    // Calendar steps that keep the wall-clock time
+    Days(i64),
+    BusinessDays(i64),
+    Months(i64),
+    // The closest such weekday strictly after or before
+    Next(Weekday),
+    Last(Weekday),
+    StartOf(Period),
+    EndOf(Period),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Period {
+    // Weeks start on Monday
+    Week,
+    Month,
+    Year,
+}
+
+// `+3d`, `-2 w`, `next monday`, `end of month`. Units: s, min, h, d, bd
+// (business days), w, mo, y.
+fn parse_shift(text: &str) -> Result<Shift, String> {
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let unknown = || format!("unknown date modifier {:?}", text);
+
+    match words.as_slice() {
+        ["next", day] => return day.parse().map(Shift::Next).map_err(|_| unknown()),
+        ["last", day] => return day.parse().map(Shift::Last).map_err(|_| unknown()),
+        ["start", "of", period] => return parse_period(period).map(Shift::StartOf).ok_or_else(unknown),
+        ["end", "of", period] => return parse_period(period).map(Shift::EndOf).ok_or_else(unknown),
+        _ => {}
+    }
+
+    let sign = match lower.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(unknown()),
+    };
+    let rest = lower[1..].trim_start();
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    // Small enough that no unit below can overflow
+    let amount = rest[..digits].parse::<i32>().map_err(|_| unknown())? as i64 * sign;
+
+    Ok(match rest[digits..].trim() {
+        "s" => Shift::Seconds(amount),
+        "min" => Shift::Seconds(amount * 60),
+        "h" => Shift::Seconds(amount * 3600),
+        "d" => Shift::Days(amount),
+        "bd" => Shift::BusinessDays(amount),
+        "w" => Shift::Days(amount * 7),
+        "mo" => Shift::Months(amount),
+        "y" => Shift::Months(amount * 12),
+        _ => return Err(unknown()),
+    })
+}
+
+fn parse_period(text: &str) -> Option<Period> {
+    match text {
+        "week" => Some(Period::Week),
+        "month" => Some(Period::Month),
+        "year" => Some(Period::Year),
+        _ => None,
+    }
+}
+
+// Accepts `fr_FR` as well as `fr-FR`
+fn parse_locale(name: &str) -> Result<Locale, String> {
+    Locale::try_from(name.replace('-', "_").as_str()).map_err(|_| format!("unknown locale {:?}", name))
+}
+
+fn render<Z: TimeZone>(now: DateTime<Z>, shifts: &[Shift], format: &str, locale: Option<Locale>) -> Result<String, String>
+where
+    Z::Offset: Display,
+{
+    let mut time = now;
+    for shift in shifts {
+        time = apply(time, *shift).ok_or_else(|| "date is out of range".to_string())?;
+    }
+
+    let mut output = String::new();
+    let written = match locale {
+        Some(locale) => write!(output, "{}", time.format_localized(format, locale)),
+        None => write!(output, "{}", time.format(format)),
+    };
+    written.map_err(|_| format!("invalid format {:?}", format))?;
+    Ok(output)
+}
+
+fn apply<Z: TimeZone>(time: DateTime<Z>, shift: Shift) -> Option<DateTime<Z>> {
+    let local = time.naive_local();
+    let shifted = match shift {
+        Shift::Seconds(seconds) => return time.checked_add_signed(Duration::seconds(seconds)),
+        Shift::Days(days) => add_days(local, days)?,
+        Shift::BusinessDays(days) => add_business_days(local, days)?,
+        Shift::Months(months) => {
+            let steps = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+            if months < 0 {
+                local.checked_sub_months(steps)?
+            } else {
+                local.checked_add_months(steps)?
+            }
+        }
+        Shift::Next(day) => {
+            let ahead = (7 + day.num_days_from_monday() - local.weekday().num_days_from_monday()) % 7;
+            add_days(local, if ahead == 0 { 7 } else { ahead as i64 })?
+        }
+        Shift::Last(day) => {
+            let behind = (7 + local.weekday().num_days_from_monday() - day.num_days_from_monday()) % 7;
+            add_days(local, -(if behind == 0 { 7 } else { behind as i64 }))?
+        }
+        Shift::StartOf(period) => start_of(local.date(), period)?.and_time(local.time()),
+        Shift::EndOf(period) => end_of(local.date(), period)?.and_time(local.time()),
+    };
+
+    // A wall-clock time skipped by a daylight saving change moves an hour on
+    let zone = time.timezone();
+    zone.from_local_datetime(&shifted)
+        .earliest()
+        .or_else(|| zone.from_local_datetime(&(shifted + Duration::hours(1))).earliest())
+}
+
+fn add_days(time: NaiveDateTime, days: i64) -> Option<NaiveDateTime> {
+    if days < 0 {
+        time.checked_sub_days(Days::new(days.unsigned_abs()))
+    } else {
+        time.checked_add_days(Days::new(days as u64))
+    }
+}
+
+// Counts only Monday to Friday, so +1bd from a Friday or Saturday is Monday
+fn add_business_days(time: NaiveDateTime, days: i64) -> Option<NaiveDateTime> {
+    let step = days.signum();
+    let mut remaining = days.unsigned_abs();
+    if remaining == 0 {
+        return Some(time);
+    }
+
+    // Any seven days in a row hold five business days, so whole weeks are
+    // skipped at once. At least one day is left over, so the count still
+    // ends on a business day rather than a weekend after the last week.
+    let weeks = (remaining - 1) / 5;
+    let mut time = add_days(time, i64::try_from(weeks).ok()?.checked_mul(7 * step)?)?;
+    remaining -= weeks * 5;
+
+    while remaining > 0 {
+        time = add_days(time, step)?;
+        if !is_weekend(time.weekday()) {
+            remaining -= 1;
+        }
+    }
+    Some(time)
+}
+
+fn is_weekend(day: Weekday) -> bool {
+    matches!(day, Weekday::Sat | Weekday::Sun)
+}
+
+fn start_of(date: NaiveDate, period: Period) -> Option<NaiveDate> {
+    match period {
+        Period::Week => date.checked_sub_days(Days::new(date.weekday().num_days_from_monday() as u64)),
+        Period::Month => date.with_day(1),
+        Period::Year => date.with_ordinal(1),
+    }
+}
+
+fn end_of(date: NaiveDate, period: Period) -> Option<NaiveDate> {
+    match period {
+        Period::Week => date.checked_add_days(Days::new(6 - date.weekday().num_days_from_monday() as u64)),
+        Period::Month => start_of(date, Period::Month)?
+            .checked_add_months(Months::new(1))?
+            .pred_opt(),
+        Period::Year => NaiveDate::from_ymd_opt(date.year(), 12, 31),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Wednesday
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 31, 9, 30, 0).unwrap()
+    }
+
+    fn utc(spec: &str) -> String {
+        format(&format!("{}|tz=UTC", spec), now()).unwrap()
+    }
+
+    #[test]
+    fn formats_the_given_time() {
+        assert_eq!(utc("%Y-%m-%d %H:%M"), "2024-01-31 09:30");
+        assert_eq!(utc("%A %-d %B"), "Wednesday 31 January");
+        assert!(format("%Y", now()).is_ok());
+    }
+
+    #[test]
+    fn applies_offsets_in_order() {
+        assert_eq!(utc("%F|+1d"), "2024-02-01");
+        assert_eq!(utc("%F|-2 w"), "2024-01-17");
+        assert_eq!(utc("%H:%M|+3h"), "12:30");
+        assert_eq!(utc("%H:%M|+90min"), "11:00");
+        assert_eq!(utc("%T|-15s"), "09:29:45");
+        assert_eq!(utc("%F|+1y"), "2025-01-31");
+        // Months keep the day where it exists and clamp where it does not
+        assert_eq!(utc("%F|+1mo"), "2024-02-29");
+        assert_eq!(utc("%F|-1mo"), "2023-12-31");
+        assert_eq!(utc("%F|+1mo|+1mo"), "2024-03-29");
+        assert_eq!(utc("%F|+1d|+1mo"), "2024-03-01");
+    }
+
+    #[test]
+    fn counts_business_days() {
+        assert_eq!(utc("%a %F|+1bd"), "Thu 2024-02-01");
+        assert_eq!(utc("%a %F|+3bd"), "Mon 2024-02-05");
+        assert_eq!(utc("%a %F|+10bd"), "Wed 2024-02-14");
+        assert_eq!(utc("%a %F|-3bd"), "Fri 2024-01-26");
+        // From a Saturday the next business day is Monday
+        assert_eq!(utc("%a %F|+3d|+1bd"), "Mon 2024-02-05");
+        assert_eq!(utc("%a %F|+3d|-1bd"), "Fri 2024-02-02");
+        assert_eq!(utc("%a %F|+0bd"), "Wed 2024-01-31");
+        // Whole weeks from a weekend day still end on a weekday
+        assert_eq!(utc("%a %F|+3d|+5bd"), "Fri 2024-02-09");
+        assert_eq!(utc("%a %F|+3d|+6bd"), "Mon 2024-02-12");
+        assert_eq!(utc("%a %F|+4d|-5bd"), "Mon 2024-01-29");
+        assert_eq!(utc("%a %F|-5bd"), "Wed 2024-01-24");
+        assert_eq!(utc("%a %F|+260bd"), "Wed 2025-01-29");
+    }
+
+    #[test]
+    fn skips_weeks_like_counting_day_by_day() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 0, 0).unwrap();
+        for offset in 0..7 {
+            let from = start + Duration::days(offset);
+            for days in -40..=40_i64 {
+                let mut expected = from;
+                for _ in 0..days.abs() {
+                    expected += Duration::days(days.signum());
+                    while is_weekend(expected.weekday()) {
+                        expected += Duration::days(days.signum());
+                    }
+                }
+                assert_eq!(add_business_days(from, days), Some(expected), "{} {:+}bd", from, days);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_next_and_last_weekdays() {
+        assert_eq!(utc("%a %F|next monday"), "Mon 2024-02-05");
+        assert_eq!(utc("%a %F|next Thursday"), "Thu 2024-02-01");
+        // Strictly after or before, never today
+        assert_eq!(utc("%a %F|next wednesday"), "Wed 2024-02-07");
+        assert_eq!(utc("%a %F|last wednesday"), "Wed 2024-01-24");
+        assert_eq!(utc("%a %F|last fri"), "Fri 2024-01-26");
+    }
+
+    #[test]
+    fn moves_to_the_start_or_end_of_a_period() {
+        assert_eq!(utc("%F %H:%M|end of month"), "2024-01-31 09:30");
+        assert_eq!(utc("%F|+1mo|end of month"), "2024-02-29");
+        assert_eq!(utc("%F|start of month"), "2024-01-01");
+        assert_eq!(utc("%a %F|start of week"), "Mon 2024-01-29");
+        assert_eq!(utc("%a %F|end of week"), "Sun 2024-02-04");
+        assert_eq!(utc("%F|start of year"), "2024-01-01");
+        assert_eq!(utc("%F|end of year"), "2024-12-31");
+    }
+
+    #[test]
+    fn renders_in_the_chosen_time_zone() {
+        assert_eq!(format("%H:%M %Z|tz=Europe/Berlin", now()).unwrap(), "10:30 CET");
+        assert_eq!(format("%F %H:%M|tz=America/New_York", now()).unwrap(), "2024-01-31 04:30");
+        // The zone applies before any shift, whatever the order
+        assert_eq!(format("%F|end of month|tz=Pacific/Auckland", now()).unwrap(), "2024-01-31");
+
+        // Days keep the wall-clock time across a daylight saving change,
+        // hours do not
+        let before_dst = Utc.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap();
+        assert_eq!(format("%F %H:%M %Z|tz=Europe/Berlin|+1d", before_dst).unwrap(), "2024-03-31 13:00 CEST");
+        assert_eq!(format("%F %H:%M %Z|tz=Europe/Berlin|+24h", before_dst).unwrap(), "2024-03-31 14:00 CEST");
+    }
+
+    #[test]
+    fn translates_names_for_a_locale() {
+        assert_eq!(utc("%A %-d %B|locale=de_DE"), "Mittwoch 31 Januar");
+        assert_eq!(utc("%A|locale=fr-FR"), "mercredi");
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert_eq!(format("%Q", now()).unwrap_err(), "invalid format \"%Q\"");
+        assert_eq!(format("%F|+3x", now()).unwrap_err(), "unknown date modifier \"+3x\"");
+        assert_eq!(format("%F|next monthday", now()).unwrap_err(), "unknown date modifier \"next monthday\"");
+        assert_eq!(format("%F|tz=Mars/Base", now()).unwrap_err(), "unknown time zone \"Mars/Base\"");
+        assert_eq!(format("%F|locale=xx_YY", now()).unwrap_err(), "unknown locale \"xx_YY\"");
+    }
+
+    #[test]
+    fn reports_dates_out_of_range() {
+        let out_of_range = Err("date is out of range".to_string());
+        // 1073741825 years is 2^32 * 3 + 12 months, which must not wrap
+        // around to a single year
+        assert_eq!(format("%F|tz=UTC|+1073741825y", now()), out_of_range);
+        assert_eq!(format("%F|tz=UTC|-1073741825y", now()), out_of_range);
+        assert_eq!(format("%F|tz=UTC|+2000000000y", now()), out_of_range);
+        assert_eq!(format("%F|tz=UTC|-2000000000y", now()), out_of_range);
+        assert_eq!(format("%F|tz=UTC|+2000000000d", now()), out_of_range);
+        assert_eq!(format("%F|tz=UTC|+2000000000bd", now()), out_of_range);
+    }
+}
@@ -1,84 +1,690 @@
 use regex::Regex;
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use chrono::Utc;
 use copypasta::{ClipboardContext, ClipboardProvider};
+use sqlx::SqlitePool;
+use unicode_segmentation::UnicodeSegmentation;
 
-static BUFFER: Mutex<VecDeque<char>> = Mutex::new(VecDeque::new());
-const BUFFER_SIZE: usize = 100;
+use crate::database::{Snippet, Variable};
+use crate::expander::FocusedApp;
+use crate::matcher::{fold_case, TriggerAutomaton, TypingBuffer, MAX_TRIGGER_LEN};
+use crate::vars::{FormField, RenderStep};
+
+static BUFFER: Mutex<TypingBuffer> = Mutex::new(TypingBuffer::new());
+
+// Optional character every trigger must be typed with, e.g. ';' for ";sig"
+static TRIGGER_PREFIX: Mutex<Option<char>> = Mutex::new(None);
+
+// Backspace straight after an expansion restores the typed trigger
+static UNDO_ENABLED: AtomicBool = AtomicBool::new(true);
+static LAST_EXPANSION: Mutex<Option<LastExpansion>> = Mutex::new(None);
+
+// Our own injected keystrokes come back through the hooks; ignore them
+static INJECTING_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+const INJECTION_GRACE: Duration = Duration::from_millis(50);
+
+// Set while an expansion waits on another thread for its form window or a
+// command, so typing meanwhile (into the form, say) is not expanded
+static EXPANDING_IN_BACKGROUND: AtomicBool = AtomicBool::new(false);
+// Time for focus to return to the target app after the form closes
+const FORM_REFOCUS_DELAY: Duration = Duration::from_millis(150);
+
+// Remaining {{cursor:N}} stops of the last expansion, visited with Tab
+static TAB_STOPS: Mutex<Option<TabStops>> = Mutex::new(None);
+
+static BACKSPACE_DELAY_MS: AtomicU64 = AtomicU64::new(0);
+static CLIPBOARD_RESTORE_DELAY_MS: AtomicU64 = AtomicU64::new(100);
 
 lazy_static::lazy_static! {
-    static ref SHORTCUT_REGEX: Regex = Regex::new(r"/([a-zA-Z0-9_\-.]+)$").unwrap();
+    // Active snippets, so keystrokes never touch the database
+    static ref SNIPPET_INDEX: RwLock<SnippetIndex> = RwLock::new(SnippetIndex::default());
+    // Variable definitions of the active snippets, by snippet id
+    static ref VARIABLES: RwLock<HashMap<i64, Vec<Variable>>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchType {
+    // Shortcut is the last thing typed
+    Exact,
+    // Shortcut is the last thing typed and does not continue a word
+    Word,
+    // Shortcut followed by space, enter or tab
+    Delimiter,
+    // Shortcut is a regex matched against the end of the buffer
+    Regex,
+}
+
+impl MatchType {
+    pub fn parse(value: &str) -> Option<MatchType> {
+        match value {
+            "exact" => Some(MatchType::Exact),
+            "word" => Some(MatchType::Word),
+            "delimiter" => Some(MatchType::Delimiter),
+            "regex" => Some(MatchType::Regex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseStyle {
+    Unchanged,
+    Capitalized,
+    Upper,
+}
+
+#[derive(Default)]
+struct SnippetIndex {
+    snippets: Vec<Snippet>,
+    // Literal trigger text per snippet, with any trigger prefix applied
+    keys: Vec<String>,
+    // Case-folded literal triggers, valued by position in `groups`
+    triggers: TriggerAutomaton,
+    // Positions in `snippets` sharing a case-folded trigger
+    groups: Vec<Vec<usize>>,
+    regex_triggers: Vec<(Regex, usize)>,
+}
+
+struct LastExpansion {
+    // Exactly what the user typed, including a trailing delimiter
+    typed: String,
+    // Grapheme clusters the expansion put on screen
+    rendered_len: usize,
+}
+
+struct TabStops {
+    // Grapheme offsets into the expansion, in visiting order
+    offsets: Vec<usize>,
+    current: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NavigationKey {
+    Backspace,
+    Left,
+    Right,
+}
+
+struct TriggerMatch {
+    id: i64,
+    name: String,
+    body: String,
+    // Approved to run commands
+    trusted: bool,
+    variables: Vec<Variable>,
+    // Number of typed characters to erase, including a trailing delimiter
+    typed_len: usize,
+    delimiter: Option<char>,
+    captures: HashMap<String, String>,
+    case_style: CaseStyle,
+}
+
+pub async fn reload_snippets(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    let snippets = crate::database::load_active_snippets(db).await?;
+    let variables = crate::database::load_active_variables(db).await?;
+
+    let mut by_snippet: HashMap<i64, Vec<Variable>> = HashMap::new();
+    for variable in variables {
+        by_snippet.entry(variable.snippet_id).or_default().push(variable);
+    }
+    *VARIABLES.write().unwrap() = by_snippet;
+
+    rebuild_index(snippets);
+    Ok(())
+}
+
+fn snippet_variables(snippet_id: i64) -> Vec<Variable> {
+    VARIABLES.read().unwrap().get(&snippet_id).cloned().unwrap_or_default()
+}
+
+pub fn rebuild_index(snippets: Vec<Snippet>) {
+    *SNIPPET_INDEX.write().unwrap() = build_index(snippets, trigger_prefix());
+
+    // Buffered states belong to the previous automaton
+    BUFFER.lock().unwrap().clear();
+}
+
+fn build_index(mut snippets: Vec<Snippet>, prefix: Option<char>) -> SnippetIndex {
+    let mut index = SnippetIndex::default();
+    let mut group_by_key: HashMap<String, usize> = HashMap::new();
+
+    // Among equally good matches the first one wins, so keep the order
+    // snippets were created in whatever order they were loaded in
+    snippets.sort_by_key(|snippet| snippet.id);
+
+    for snippet in snippets.into_iter().filter(|snippet| snippet.is_active) {
+        let position = index.snippets.len();
+        let mut key = String::new();
+        match MatchType::parse(&snippet.match_type) {
+            Some(MatchType::Regex) => match compile_trigger_regex(&snippet.shortcut) {
+                Ok(regex) => index.regex_triggers.push((regex, position)),
+                Err(e) => {
+                    eprintln!("Skipping snippet {} with invalid regex trigger: {}", snippet.id, e);
+                    continue;
+                }
+            },
+            Some(_) => {
+                key = trigger_key(&snippet.shortcut, prefix);
+                let folded: String = key.chars().map(fold_case).collect();
+                let group = *group_by_key.entry(folded).or_insert_with(|| {
+                    index.groups.push(Vec::new());
+                    index.groups.len() - 1
+                });
+                index.groups[group].push(position);
+            }
+            None => {
+                eprintln!("Skipping snippet {} with unknown match type {:?}", snippet.id, snippet.match_type);
+                continue;
+            }
+        }
+        index.snippets.push(snippet);
+        index.keys.push(key);
+    }
+    index.triggers = TriggerAutomaton::build(group_by_key.iter().map(|(key, group)| (key.as_str(), *group)));
+    index
+}
+
+pub fn trigger_prefix() -> Option<char> {
+    *TRIGGER_PREFIX.lock().unwrap()
+}
+
+pub fn set_trigger_prefix(prefix: Option<char>) {
+    *TRIGGER_PREFIX.lock().unwrap() = prefix;
+
+    let snippets = SNIPPET_INDEX.read().unwrap().snippets.clone();
+    rebuild_index(snippets);
+}
+
+fn trigger_key(shortcut: &str, prefix: Option<char>) -> String {
+    match prefix {
+        Some(prefix) if !shortcut.starts_with(prefix) => format!("{}{}", prefix, shortcut),
+        _ => shortcut.to_string(),
+    }
+}
+
+// What a {{snippet:...}} include renders
+pub struct IncludedSnippet {
+    pub id: i64,
+    pub body: String,
+    pub trusted: bool,
+    pub variables: Vec<Variable>,
+}
+
+// Active snippet for {{snippet:...}} includes. `#12` names the snippet with
+// id 12, anything else a shortcut; when several snippets share the shortcut,
+// the one that would expand in the focused app wins.
+pub fn snippet_body(reference: &str) -> Option<IncludedSnippet> {
+    let index = SNIPPET_INDEX.read().unwrap();
+
+    let snippet = if let Some(id) = reference.strip_prefix('#').and_then(|id| id.parse::<i64>().ok()) {
+        index.snippets.iter().find(|snippet| snippet.id == id)
+    } else {
+        // The snippet that would expand for this shortcut in the focused app
+        let mut focused_app = crate::expander::focused_app();
+        let mut best: Option<(u8, &Snippet)> = None;
+        for snippet in index.snippets.iter().filter(|snippet| snippet.shortcut == reference) {
+            let Some(specificity) = app_specificity(snippet, &mut focused_app) else {
+                continue;
+            };
+            if best.map_or(true, |(score, _)| score < specificity) {
+                best = Some((specificity, snippet));
+            }
+        }
+        best.map(|(_, snippet)| snippet)
+    }?;
+
+    Some(IncludedSnippet {
+        id: snippet.id,
+        body: snippet.body.clone(),
+        trusted: snippet.trusted,
+        variables: snippet_variables(snippet.id),
+    })
+}
+
+pub fn validate_trigger(shortcut: &str, match_type: &str) -> Result<(), String> {
+    match MatchType::parse(match_type) {
+        Some(MatchType::Regex) => compile_trigger_regex(shortcut)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid regex trigger: {}", e)),
+        Some(_) if shortcut.chars().count() > MAX_TRIGGER_LEN => {
+            Err(format!("Trigger is longer than {} characters", MAX_TRIGGER_LEN))
+        }
+        Some(_) => Ok(()),
+        None => Err(format!("Unknown match type: {}", match_type)),
+    }
+}
+
+fn compile_trigger_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    // Triggers only ever fire on what was just typed
+    Regex::new(&format!("(?:{})$", pattern))
+}
+
+pub fn undo_enabled() -> bool {
+    UNDO_ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn set_undo_enabled(enabled: bool) {
+    UNDO_ENABLED.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        LAST_EXPANSION.lock().unwrap().take();
+    }
+}
+
+pub fn set_injection_delays(backspace_ms: u64, clipboard_restore_ms: u64) {
+    BACKSPACE_DELAY_MS.store(backspace_ms, Ordering::SeqCst);
+    CLIPBOARD_RESTORE_DELAY_MS.store(clipboard_restore_ms, Ordering::SeqCst);
 }
 
 pub fn on_key_typed(character: char) {
+    if is_injecting() || EXPANDING_IN_BACKGROUND.load(Ordering::SeqCst) {
+        return;
+    }
+
+    if character == '\t' {
+        if let Some(tab_stops) = TAB_STOPS.lock().unwrap().take() {
+            next_tab_stop(tab_stops);
+            return;
+        }
+    }
+
     let mut buffer = BUFFER.lock().unwrap();
-    
-    if character == '\x08' { // Backspace
-        buffer.pop_back();
+
+    if let Some(last_expansion) = take_undo(character) {
+        buffer.clear();
+        undo_expansion(&last_expansion);
+        return;
+    }
+
+    let mut focused_app = crate::expander::focused_app();
+    let trigger = {
+        let index = SNIPPET_INDEX.read().unwrap();
+        buffer.push(character, &index.triggers);
+        find_match(&mut buffer, &index, &mut focused_app)
+    };
+
+    if let Some(trigger) = trigger {
+        // Exclusions are looked up only now a trigger matched; what was
+        // typed in an excluded app is dropped without expanding
+        if crate::expander::is_excluded(&mut focused_app) {
+            buffer.clear();
+            return;
+        }
+
+        let typed_start = buffer.chars.len().saturating_sub(trigger.typed_len);
+        let typed: String = buffer.chars.range(typed_start..).collect();
+        buffer.clear();
+
+        // Forms and commands block until answered or finished, which must
+        // not hold up the keyboard hook
+        if crate::vars::may_block(trigger.id, &trigger.body, trigger.trusted) {
+            EXPANDING_IN_BACKGROUND.store(true, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                start_expansion(trigger, typed);
+                EXPANDING_IN_BACKGROUND.store(false, Ordering::SeqCst);
+            });
+        } else {
+            start_expansion(trigger, typed);
+        }
+    }
+}
+
+fn start_expansion(trigger: TriggerMatch, typed: String) {
+    match crate::vars::render_step(
+        trigger.id,
+        &trigger.body,
+        trigger.trusted,
+        &trigger.captures,
+        &trigger.variables,
+        None,
+        Utc::now(),
+    ) {
+        Ok(RenderStep::Done(rendered)) => expand_snippet(&trigger, rendered, typed),
+        Ok(RenderStep::NeedsInput(fields)) => expand_with_form(trigger, typed, fields),
+        // Leave the trigger as typed rather than paste a broken expansion
+        Err(e) => eprintln!("Failed to render snippet {}: {}", trigger.name, e),
+    }
+}
+
+// The expansion a keystroke undoes. Only the very next keystroke can undo
+// one, so any other key forgets it.
+fn take_undo(character: char) -> Option<LastExpansion> {
+    let last_expansion = LAST_EXPANSION.lock().unwrap().take();
+    last_expansion.filter(|_| character == '\x08' && undo_enabled())
+}
+
+fn remember_expansion(typed: String, rendered_len: usize) {
+    if undo_enabled() {
+        *LAST_EXPANSION.lock().unwrap() = Some(LastExpansion { typed, rendered_len });
+    }
+}
+
+// Erases the trigger, asks for the snippet's form values and expands with
+// them. Cancelling puts the typed trigger back instead.
+fn expand_with_form(trigger: TriggerMatch, typed: String, fields: Vec<FormField>) {
+    send_backspaces(trigger.typed_len);
+    finish_injecting();
+
+    let values = crate::forms::ask(&trigger.name, fields);
+    std::thread::sleep(FORM_REFOCUS_DELAY);
+
+    let Some(values) = values else {
+        paste_text(&typed);
+        finish_injecting();
+        return;
+    };
+
+    let reason = match crate::vars::render_step(
+        trigger.id,
+        &trigger.body,
+        trigger.trusted,
+        &trigger.captures,
+        &trigger.variables,
+        Some(&values),
+        Utc::now(),
+    ) {
+        Ok(RenderStep::Done(rendered)) => return inject_expansion(&trigger, rendered, typed),
+        Ok(RenderStep::NeedsInput(_)) => "it asked for input again after its form".to_string(),
+        Err(e) => e.to_string(),
+    };
+
+    // The trigger is already erased; give it back rather than lose it
+    eprintln!("Failed to render snippet {}: {}", trigger.name, reason);
+    paste_text(&typed);
+    finish_injecting();
+}
+
+// The Tab the user pressed was typed into the app; remove it and move on to
+// the next stop. Text typed at earlier stops shifts every later stop equally,
+// so the distance between stops still holds.
+fn next_tab_stop(mut tab_stops: TabStops) {
+    let from = tab_stops.offsets[tab_stops.current];
+    tab_stops.current += 1;
+    let to = tab_stops.offsets[tab_stops.current];
+
+    send_backspaces(1);
+    if to >= from {
+        send_keys(NavigationKey::Right, to - from);
     } else {
-        buffer.push_back(character);
-        if buffer.len() > BUFFER_SIZE {
-            buffer.pop_front();
+        send_keys(NavigationKey::Left, from - to);
+    }
+    finish_injecting();
+
+    if tab_stops.current + 1 < tab_stops.offsets.len() {
+        *TAB_STOPS.lock().unwrap() = Some(tab_stops);
+    }
+}
+
+fn is_injecting() -> bool {
+    INJECTING_UNTIL
+        .lock()
+        .unwrap()
+        .is_some_and(|until| Instant::now() < until)
+}
+
+fn finish_injecting() {
+    *INJECTING_UNTIL.lock().unwrap() = Some(Instant::now() + INJECTION_GRACE);
+}
+
+fn find_match(buffer: &mut TypingBuffer, index: &SnippetIndex, focused_app: &mut FocusedApp) -> Option<TriggerMatch> {
+    let last = *buffer.chars.back()?;
+    let delimiter = Some(last).filter(|c| is_delimiter(*c));
+
+    // Literal triggers complete on the character before a delimiter
+    let skip = delimiter.map_or(0, |_| 1);
+    let typed_len = buffer.chars.len() - skip;
+
+    for (key_len, group) in index.triggers.matches(buffer.state(skip)) {
+        // Triggers saved before their length was capped can outgrow what
+        // the buffer still holds; those cannot be erased, so never fire
+        let Some(start) = typed_len.checked_sub(key_len) else {
+            continue;
+        };
+        let preceding = start.checked_sub(1).and_then(|i| buffer.chars.get(i).copied());
+
+        // Snippets sharing a trigger compete on how specifically they target
+        // the focused app
+        let mut best: Option<(u8, TriggerMatch)> = None;
+
+        for &position in &index.groups[group] {
+            let snippet = &index.snippets[position];
+            let matched = match MatchType::parse(&snippet.match_type) {
+                Some(MatchType::Exact) => delimiter.is_none(),
+                Some(MatchType::Word) => delimiter.is_none() && !preceding.is_some_and(is_word_char),
+                Some(MatchType::Delimiter) => delimiter.is_some(),
+                _ => false,
+            };
+            if !matched {
+                continue;
+            }
+
+            // The automaton ignores case; only case-propagating snippets may
+            // be typed in a different case than they were stored
+            let typed = buffer.chars.range(start..typed_len);
+            let case_style = if snippet.propagate_case {
+                typed_case_style(typed)
+            } else if typed.copied().eq(index.keys[position].chars()) {
+                CaseStyle::Unchanged
+            } else {
+                continue;
+            };
+
+            let Some(specificity) = app_specificity(snippet, focused_app) else {
+                continue;
+            };
+            if best.as_ref().is_some_and(|(score, _)| *score >= specificity) {
+                continue;
+            }
+
+            best = Some((specificity, TriggerMatch {
+                id: snippet.id,
+                name: snippet.name.clone(),
+                body: snippet.body.clone(),
+                trusted: snippet.trusted,
+                variables: snippet_variables(snippet.id),
+                typed_len: key_len + skip,
+                delimiter,
+                captures: HashMap::new(),
+                case_style,
+            }));
+        }
+
+        if let Some((_, trigger)) = best {
+            return Some(trigger);
         }
     }
 
-    // Check for shortcut match
-    let buffer_str: String = buffer.iter().collect();
-    if let Some(captures) = SHORTCUT_REGEX.captures(&buffer_str) {
-        if let Some(shortcut_match) = captures.get(0) {
-            let shortcut = shortcut_match.as_str();
-            
-            // Check if this shortcut exists in our database
-            if let Some(snippet_body) = get_snippet_by_shortcut(shortcut) {
-                expand_snippet(shortcut, &snippet_body);
+    if index.regex_triggers.is_empty() {
+        return None;
+    }
+
+    let TypingBuffer { chars, text, .. } = buffer;
+    text.clear();
+    text.extend(chars.iter());
+
+    // Same ranking as literal triggers; among equally specific snippets the
+    // first one defined wins
+    let mut best: Option<(u8, TriggerMatch)> = None;
+
+    for (regex, position) in &index.regex_triggers {
+        let snippet = &index.snippets[*position];
+        let Some(specificity) = app_specificity(snippet, focused_app) else {
+            continue;
+        };
+        if best.as_ref().is_some_and(|(score, _)| *score >= specificity) {
+            continue;
+        }
+        if let Some(captures) = regex.captures(text) {
+            let whole = captures.get(0).unwrap();
+            if whole.as_str().is_empty() {
+                continue;
+            }
+
+            let mut values = HashMap::new();
+            for (i, name) in regex.capture_names().enumerate().skip(1) {
+                if let Some(value) = captures.get(i) {
+                    values.insert(i.to_string(), value.as_str().to_string());
+                    if let Some(name) = name {
+                        values.insert(name.to_string(), value.as_str().to_string());
+                    }
+                }
             }
+
+            best = Some((specificity, TriggerMatch {
+                id: snippet.id,
+                name: snippet.name.clone(),
+                body: snippet.body.clone(),
+                trusted: snippet.trusted,
+                variables: snippet_variables(snippet.id),
+                typed_len: whole.as_str().chars().count(),
+                delimiter: None,
+                captures: values,
+                case_style: CaseStyle::Unchanged,
+            }));
         }
     }
+
+    best.map(|(_, trigger)| trigger)
+}
+
+// None when the snippet does not apply to the focused app, otherwise higher
+// for more specific targeting
+fn app_specificity(snippet: &Snippet, focused_app: &mut FocusedApp) -> Option<u8> {
+    focused_app.specificity(&snippet.app_include.0, &snippet.app_exclude.0)
 }
 
-fn get_snippet_by_shortcut(shortcut: &str) -> Option<String> {
-    // This would query the database for the snippet
-    // For now, return a placeholder
-    match shortcut {
-        "/green" => Some("🟢 Green Color: #22c55e".to_string()),
-        "/json" => Some(r#"{"key": "value"}"#.to_string()),
-        "/send" => Some("Best regards,\nJohn Doe".to_string()),
-        "/drop" => Some("Let me know if you need anything else!".to_string()),
-        _ => None,
+// Same rules as espanso: a leading capital capitalizes the expansion, and a
+// trigger typed entirely in capitals upper-cases all of it
+fn typed_case_style<'a, I>(typed: I) -> CaseStyle
+where
+    I: Iterator<Item = &'a char>,
+{
+    let mut letters = typed.filter(|c| c.is_alphabetic());
+    let first_upper = match letters.next() {
+        Some(first) => first.is_uppercase(),
+        None => return CaseStyle::Unchanged,
+    };
+    if !first_upper {
+        return CaseStyle::Unchanged;
+    }
+
+    let mut rest = letters.peekable();
+    if rest.peek().is_some() && rest.all(|c| c.is_uppercase()) {
+        CaseStyle::Upper
+    } else {
+        CaseStyle::Capitalized
     }
 }
 
-fn expand_snippet(shortcut: &str, body: &str) {
+fn apply_case_style(text: &str, style: CaseStyle) -> String {
+    match style {
+        CaseStyle::Unchanged => text.to_string(),
+        CaseStyle::Upper => text.to_uppercase(),
+        CaseStyle::Capitalized => match text.char_indices().find(|(_, c)| c.is_alphabetic()) {
+            Some((i, first)) => format!("{}{}{}", &text[..i], first.to_uppercase(), &text[i + first.len_utf8()..]),
+            None => text.to_string(),
+        },
+    }
+}
+
+fn is_delimiter(character: char) -> bool {
+    matches!(character, ' ' | '\n' | '\r' | '\t')
+}
+
+fn is_word_char(character: char) -> bool {
+    character.is_alphanumeric() || character == '_'
+}
+
+fn expand_snippet(trigger: &TriggerMatch, rendered: String, typed: String) {
     // Send backspaces to delete the shortcut
-    send_backspaces(shortcut.len());
+    send_backspaces(trigger.typed_len);
+    
+    inject_expansion(trigger, rendered, typed);
+}
+
+// Pastes the rendered text and moves the caret back to its first cursor stop
+fn inject_expansion(trigger: &TriggerMatch, rendered: String, typed: String) {
+    let rendered = finish_rendering(trigger, rendered);
+    let (text, stops) = crate::vars::take_cursor_stops(&rendered);
+    // Arrow keys and backspace step over whole grapheme clusters, and a
+    // line break counts as one
+    let rendered_len = text.graphemes(true).count();
+    
+    paste_text(&text);
+    if let Some(&first) = stops.first() {
+        send_keys(NavigationKey::Left, rendered_len - first);
+    }
+    finish_injecting();
+    
+    // Undo erases from the end of the expansion, so only offer it while the
+    // caret is still there
+    if stops.is_empty() {
+        remember_expansion(typed, rendered_len);
+    }
     
-    // Render the snippet with variables
-    let rendered = crate::vars::render_snippet(body);
+    *TAB_STOPS.lock().unwrap() = if stops.len() > 1 {
+        Some(TabStops { offsets: stops, current: 0 })
+    } else {
+        None
+    };
+}
+
+fn finish_rendering(trigger: &TriggerMatch, rendered: String) -> String {
+    let mut rendered = apply_case_style(&rendered, trigger.case_style);
     
-    // Paste the rendered text
-    paste_text(&rendered);
+    // Give back the delimiter that completed the trigger
+    if let Some(delimiter) = trigger.delimiter {
+        rendered.push(delimiter);
+    }
+    rendered
+}
+
+fn undo_expansion(last_expansion: &LastExpansion) {
+    // The user's own backspace already removed the last character
+    send_backspaces(last_expansion.rendered_len.saturating_sub(1));
+    paste_text(&last_expansion.typed);
+    finish_injecting();
 }
 
 fn send_backspaces(count: usize) {
+    send_keys(NavigationKey::Backspace, count);
+}
+
+fn send_keys(key: NavigationKey, count: usize) {
     #[cfg(target_os = "macos")]
     {
         use core_graphics::event::{CGEvent, CGEventType, CGKeyCode};
         use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
         
+        let keycode: CGKeyCode = match key {
+            NavigationKey::Backspace => 51,
+            NavigationKey::Left => 123,
+            NavigationKey::Right => 124,
+        };
+        
         for _ in 0..count {
             let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState).unwrap();
-            let key_down = CGEvent::new_keyboard_event(source.clone(), 51, true).unwrap(); // 51 is backspace
-            let key_up = CGEvent::new_keyboard_event(source, 51, false).unwrap();
+            let key_down = CGEvent::new_keyboard_event(source.clone(), keycode, true).unwrap();
+            let key_up = CGEvent::new_keyboard_event(source, keycode, false).unwrap();
             
             key_down.post(CGEventTapLocation::HID);
             key_up.post(CGEventTapLocation::HID);
+            backspace_pause();
         }
     }
     
     #[cfg(target_os = "windows")]
     {
         use winapi::um::winuser::{SendInput, INPUT, INPUT_KEYBOARD, KEYEVENTF_KEYUP};
-        use winapi::um::winuser::VK_BACK;
+        use winapi::um::winuser::{VK_BACK, VK_LEFT, VK_RIGHT};
+        
+        let vk = match key {
+            NavigationKey::Backspace => VK_BACK,
+            NavigationKey::Left => VK_LEFT,
+            NavigationKey::Right => VK_RIGHT,
+        };
         
         for _ in 0..count {
             let mut inputs = [
@@ -93,16 +699,24 @@ fn send_backspaces(count: usize) {
             ];
             
             unsafe {
-                inputs[0].u.ki_mut().wVk = VK_BACK as u16;
-                inputs[1].u.ki_mut().wVk = VK_BACK as u16;
+                inputs[0].u.ki_mut().wVk = vk as u16;
+                inputs[1].u.ki_mut().wVk = vk as u16;
                 inputs[1].u.ki_mut().dwFlags = KEYEVENTF_KEYUP;
                 
                 SendInput(2, inputs.as_mut_ptr(), std::mem::size_of::<INPUT>() as i32);
             }
+            backspace_pause();
         }
     }
 }
 
+fn backspace_pause() {
+    let delay = BACKSPACE_DELAY_MS.load(Ordering::SeqCst);
+    if delay > 0 {
+        std::thread::sleep(Duration::from_millis(delay));
+    }
+}
+
 fn paste_text(text: &str) {
     // Save current clipboard
     let mut ctx = ClipboardContext::new().unwrap();
@@ -157,9 +771,278 @@ fn paste_text(text: &str) {
     }
     
     // Restore original clipboard after a short delay
+    let restore_delay = CLIPBOARD_RESTORE_DELAY_MS.load(Ordering::SeqCst);
     std::thread::spawn(move || {
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::thread::sleep(Duration::from_millis(restore_delay));
         let mut ctx = ClipboardContext::new().unwrap();
         let _ = ctx.set_contents(original_clipboard);
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expander::{ActiveApp, ActiveAppProvider};
+    use crate::matcher::BUFFER_SIZE;
+    use sqlx::types::Json;
+
+    fn snippet(id: i64, shortcut: &str, match_type: &str) -> Snippet {
+        Snippet {
+            id,
+            user_id: 1,
+            folder_id: None,
+            name: format!("snippet {}", id),
+            shortcut: shortcut.to_string(),
+            body: format!("body {}", id),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            is_active: true,
+            match_type: match_type.to_string(),
+            propagate_case: false,
+            app_include: Json(vec![]),
+            app_exclude: Json(vec![]),
+            trusted: true,
+            tags: Json(vec![]),
+        }
+    }
+
+    // The match found once all of `text` is typed, as the engine would see it
+    fn typed(index: &SnippetIndex, text: &str) -> Option<TriggerMatch> {
+        typed_in(index, text, &mut FocusedApp::new(None))
+    }
+
+    fn typed_in(index: &SnippetIndex, text: &str, focused_app: &mut FocusedApp) -> Option<TriggerMatch> {
+        let mut buffer = TypingBuffer::new();
+        for character in text.chars() {
+            buffer.push(character, &index.triggers);
+        }
+        find_match(&mut buffer, index, focused_app)
+    }
+
+    fn fired(index: &SnippetIndex, text: &str) -> Option<(String, usize)> {
+        typed(index, text).map(|trigger| (trigger.name, trigger.typed_len))
+    }
+
+    #[test]
+    fn each_match_type_fires_on_its_own_terms() {
+        let index = build_index(
+            vec![
+                snippet(1, "ex", "exact"),
+                snippet(2, "brb", "word"),
+                snippet(3, "sig", "delimiter"),
+                snippet(4, r"(?P<kg>\d+)kg", "regex"),
+            ],
+            None,
+        );
+        let fired = |text: &str| fired(&index, text);
+
+        // Exact fires even inside a word
+        assert_eq!(fired("exam"), None);
+        assert_eq!(fired("reex"), Some(("snippet 1".to_string(), 2)));
+        assert_eq!(fired("ex "), None);
+
+        // Word fires only when the trigger starts a word
+        assert_eq!(fired("brb"), Some(("snippet 2".to_string(), 3)));
+        assert_eq!(fired("ok brb"), Some(("snippet 2".to_string(), 3)));
+        assert_eq!(fired("(brb"), Some(("snippet 2".to_string(), 3)));
+        assert_eq!(fired("abrb"), None);
+        assert_eq!(fired("_brb"), None);
+
+        // Delimiter waits for the space, enter or tab and erases it too
+        assert_eq!(fired("sig"), None);
+        assert_eq!(fired("sig "), Some(("snippet 3".to_string(), 4)));
+        assert_eq!(fired("sig\t"), Some(("snippet 3".to_string(), 4)));
+        assert_eq!(typed(&index, "sig\n").unwrap().delimiter, Some('\n'));
+
+        // Regex triggers match the end of the buffer and capture groups
+        let trigger = typed(&index, "about 12kg").unwrap();
+        assert_eq!((trigger.name.as_str(), trigger.typed_len), ("snippet 4", 4));
+        assert_eq!(trigger.captures["kg"], "12");
+        assert_eq!(trigger.captures["1"], "12");
+        assert_eq!(fired("12kgs"), None);
+    }
+
+    #[test]
+    fn one_trigger_can_fire_as_different_match_types() {
+        let index = build_index(
+            vec![
+                snippet(1, "omw", "exact"),
+                snippet(2, "omw", "delimiter"),
+                snippet(3, r"\bomw!", "regex"),
+            ],
+            None,
+        );
+
+        assert_eq!(fired(&index, "omw"), Some(("snippet 1".to_string(), 3)));
+        assert_eq!(fired(&index, "omw "), Some(("snippet 2".to_string(), 4)));
+        // A literal trigger is not followed by `!`, so only the regex is left
+        assert_eq!(fired(&index, "omw!"), Some(("snippet 3".to_string(), 4)));
+    }
+
+    #[test]
+    fn triggers_are_typed_with_the_prefix() {
+        let index = build_index(vec![snippet(1, "sig", "exact"), snippet(2, ";addr", "exact")], Some(';'));
+        assert_eq!(fired(&index, "sig"), None);
+        assert_eq!(fired(&index, ";sig"), Some(("snippet 1".to_string(), 4)));
+        // A shortcut already starting with the prefix keeps a single one
+        assert_eq!(fired(&index, ";addr"), Some(("snippet 2".to_string(), 5)));
+        assert_eq!(fired(&index, ";;addr"), Some(("snippet 2".to_string(), 5)));
+    }
+
+    #[test]
+    fn inactive_and_unusable_snippets_are_left_out() {
+        let mut inactive = snippet(1, "one", "exact");
+        inactive.is_active = false;
+        let index = build_index(
+            vec![inactive, snippet(2, "two", "fuzzy"), snippet(3, "(", "regex"), snippet(4, "four", "exact")],
+            None,
+        );
+        assert_eq!(index.snippets.len(), 1);
+        assert_eq!(fired(&index, "one"), None);
+        assert_eq!(fired(&index, "two"), None);
+        assert_eq!(fired(&index, "four"), Some(("snippet 4".to_string(), 4)));
+    }
+
+    #[test]
+    fn backspace_undoes_only_the_keystroke_after_an_expansion() {
+        remember_expansion("brb ".to_string(), 12);
+        let undone = take_undo('\x08').unwrap();
+        assert_eq!((undone.typed.as_str(), undone.rendered_len), ("brb ", 12));
+        // Undone once, then backspace is an ordinary key again
+        assert!(take_undo('\x08').is_none());
+
+        // Any other key in between forgets the expansion
+        remember_expansion("brb ".to_string(), 12);
+        assert!(take_undo('x').is_none());
+        assert!(take_undo('\x08').is_none());
+
+        set_undo_enabled(false);
+        remember_expansion("brb ".to_string(), 12);
+        assert!(take_undo('\x08').is_none());
+        set_undo_enabled(true);
+    }
+
+    struct FakeProvider(ActiveApp);
+
+    impl ActiveAppProvider for FakeProvider {
+        fn active_app(&self) -> Option<ActiveApp> {
+            Some(self.0.clone())
+        }
+    }
+
+    fn app(class: &str) -> FakeProvider {
+        FakeProvider(ActiveApp {
+            window_class: Some(class.to_string()),
+            ..ActiveApp::default()
+        })
+    }
+
+    fn fired_in(index: &SnippetIndex, text: &str, provider: &FakeProvider) -> Option<String> {
+        typed_in(index, text, &mut FocusedApp::new(Some(provider))).map(|trigger| trigger.name)
+    }
+
+    #[test]
+    fn the_most_specific_app_scope_wins_a_shared_trigger() {
+        let mut in_code = snippet(1, "sig", "exact");
+        in_code.app_include = Json(vec!["code".to_string()]);
+        let mut not_in_terminal = snippet(2, "sig", "exact");
+        not_in_terminal.app_exclude = Json(vec!["*term*".to_string()]);
+        let everywhere = snippet(3, "sig", "exact");
+        let index = build_index(vec![everywhere, not_in_terminal, in_code], None);
+
+        assert_eq!(fired_in(&index, "sig", &app("Code")).as_deref(), Some("snippet 1"));
+        assert_eq!(fired_in(&index, "sig", &app("firefox")).as_deref(), Some("snippet 2"));
+        assert_eq!(fired_in(&index, "sig", &app("xterm")).as_deref(), Some("snippet 3"));
+        // Nothing is known about the app, so only an include cannot apply
+        assert_eq!(fired(&index, "sig").map(|(name, _)| name).as_deref(), Some("snippet 2"));
+    }
+
+    #[test]
+    fn equally_specific_snippets_tie_on_creation_order() {
+        // Loaded in any order, the snippet created first wins
+        let index = build_index(
+            vec![snippet(7, "sig", "exact"), snippet(3, "sig", "exact"), snippet(5, "sig", "exact")],
+            None,
+        );
+        assert_eq!(fired_in(&index, "sig", &app("code")).as_deref(), Some("snippet 3"));
+
+        let index = build_index(vec![snippet(9, "s.g", "regex"), snippet(4, "si.", "regex")], None);
+        assert_eq!(fired_in(&index, "sig", &app("code")).as_deref(), Some("snippet 4"));
+    }
+
+    #[test]
+    fn excluded_apps_fall_back_to_a_less_specific_snippet_or_none() {
+        let mut not_in_code = snippet(1, "sig", "exact");
+        not_in_code.app_exclude = Json(vec!["code".to_string()]);
+        let mut only_in_mail = snippet(2, "sig", "exact");
+        only_in_mail.app_include = Json(vec!["thunderbird".to_string()]);
+        let index = build_index(vec![not_in_code, only_in_mail], None);
+
+        assert_eq!(fired_in(&index, "sig", &app("code")), None);
+        assert_eq!(fired_in(&index, "sig", &app("thunderbird")).as_deref(), Some("snippet 2"));
+        assert_eq!(fired_in(&index, "sig", &app("firefox")).as_deref(), Some("snippet 1"));
+    }
+
+    #[test]
+    fn triggers_must_fit_in_the_typing_buffer() {
+        let longest = "x".repeat(MAX_TRIGGER_LEN);
+        assert!(validate_trigger(&longest, "delimiter").is_ok());
+        assert!(validate_trigger(&format!("{}x", longest), "exact").is_err());
+        assert!(validate_trigger(&"é".repeat(MAX_TRIGGER_LEN), "word").is_ok());
+        // Regex triggers only ever match what the buffer holds
+        assert!(validate_trigger(&format!("{}x", longest), "regex").is_ok());
+
+        // The longest trigger still fires with a prefix and a delimiter
+        let index = build_index(vec![snippet(1, &longest, "delimiter")], Some(';'));
+        let typed_text = format!(";{} ", longest);
+        assert_eq!(fired(&index, &typed_text), Some(("snippet 1".to_string(), MAX_TRIGGER_LEN + 2)));
+    }
+
+    #[test]
+    fn triggers_longer_than_the_buffer_never_fire() {
+        let trigger = "x".repeat(BUFFER_SIZE);
+        let index = build_index(
+            vec![snippet(1, &trigger, "delimiter"), snippet(2, &format!("{}x", trigger), "exact")],
+            None,
+        );
+        assert_eq!(fired(&index, &format!("{} ", trigger)), None);
+        assert_eq!(fired(&index, &format!("{}x", trigger)), None);
+        // Shorter triggers ending the same way still do
+        let index = build_index(vec![snippet(1, &format!("{}x", trigger), "exact"), snippet(2, "xx", "exact")], None);
+        assert_eq!(fired(&index, &format!("{}x", trigger)), Some(("snippet 2".to_string(), 2)));
+    }
+
+    #[test]
+    fn propagates_the_case_the_trigger_was_typed_in() {
+        let mut abbr = snippet(1, "abbr", "exact");
+        abbr.propagate_case = true;
+        abbr.body = "as soon as possible".to_string();
+        let index = build_index(vec![abbr, snippet(2, "sig", "exact")], None);
+
+        let expand = |text: &str| {
+            let trigger = typed(&index, text).unwrap();
+            apply_case_style(&trigger.body, trigger.case_style)
+        };
+        assert_eq!(expand("abbr"), "as soon as possible");
+        assert_eq!(expand("Abbr"), "As soon as possible");
+        assert_eq!(expand("ABBR"), "AS SOON AS POSSIBLE");
+        // Anything else mixed keeps the capital only
+        assert_eq!(expand("AbBR"), "As soon as possible");
+        assert_eq!(expand("aBBR"), "as soon as possible");
+
+        // Without propagation only the stored case expands
+        assert!(typed(&index, "sig").is_some());
+        assert!(typed(&index, "Sig").is_none());
+        assert!(typed(&index, "SIG").is_none());
+    }
+
+    #[test]
+    fn case_styles_skip_what_is_not_a_letter() {
+        let style = |typed: &str| typed_case_style(typed.chars().collect::<Vec<_>>().iter());
+        assert_eq!(style("A"), CaseStyle::Capitalized);
+        assert_eq!(style(";Ab1"), CaseStyle::Capitalized);
+        assert_eq!(style(";AB1"), CaseStyle::Upper);
+        assert_eq!(style(";12"), CaseStyle::Unchanged);
+        assert_eq!(apply_case_style("- émile", CaseStyle::Capitalized), "- Émile");
+        assert_eq!(apply_case_style("straße", CaseStyle::Upper), "STRASSE");
+    }
+}
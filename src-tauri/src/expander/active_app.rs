@@ -0,0 +1,231 @@
+// The application that currently has keyboard focus, as far as the platform
+// can tell us
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActiveApp {
+    // Instance and class parts of WM_CLASS on X11
+    pub window_instance: Option<String>,
+    pub window_class: Option<String>,
+    pub process_name: Option<String>,
+}
+
+pub trait ActiveAppProvider: Send + Sync {
+    fn active_app(&self) -> Option<ActiveApp>;
+}
+
+impl ActiveApp {
+    // Patterns are case-insensitive and may use `*` as a wildcard, e.g.
+    // "gnome-terminal*" or "keepassxc"
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.trim().to_lowercase();
+        [&self.window_instance, &self.window_class, &self.process_name]
+            .into_iter()
+            .flatten()
+            .any(|name| glob_match(&pattern, &name.to_lowercase()))
+    }
+}
+
+// Focused application, looked up at most once and only when a snippet scoped
+// to particular apps, or the list of excluded apps, asks for it
+pub struct FocusedApp<'a> {
+    provider: Option<&'a dyn ActiveAppProvider>,
+    app: Option<Option<ActiveApp>>,
+}
+
+impl<'a> FocusedApp<'a> {
+    pub fn new(provider: Option<&'a dyn ActiveAppProvider>) -> Self {
+        FocusedApp { provider, app: None }
+    }
+
+    fn get(&mut self) -> Option<&ActiveApp> {
+        let provider = self.provider;
+        self.app
+            .get_or_insert_with(|| provider.and_then(|provider| provider.active_app()))
+            .as_ref()
+    }
+
+    pub fn is_excluded(&mut self, excluded_apps: &[String]) -> bool {
+        if excluded_apps.is_empty() {
+            return false;
+        }
+
+        match self.get() {
+            Some(app) => excluded_apps.iter().any(|pattern| app.matches(pattern)),
+            None => false,
+        }
+    }
+
+    // None when a snippet with these app lists does not apply to the focused
+    // app, otherwise 0 for unscoped snippets, 1 for exclude-only and 2 for an
+    // explicit include match
+    pub fn specificity(&mut self, include: &[String], exclude: &[String]) -> Option<u8> {
+        if include.is_empty() && exclude.is_empty() {
+            return Some(0);
+        }
+
+        let app = self.get();
+        if let Some(app) = app {
+            if exclude.iter().any(|pattern| app.matches(pattern)) {
+                return None;
+            }
+        }
+
+        if include.is_empty() {
+            return Some(1);
+        }
+        match app {
+            Some(app) if include.iter().any(|pattern| app.matches(pattern)) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FakeProvider {
+        app: Option<ActiveApp>,
+        lookups: AtomicUsize,
+    }
+
+    impl FakeProvider {
+        fn new(app: Option<ActiveApp>) -> Self {
+            FakeProvider { app, lookups: AtomicUsize::new(0) }
+        }
+
+        fn lookups(&self) -> usize {
+            self.lookups.load(Ordering::SeqCst)
+        }
+    }
+
+    impl ActiveAppProvider for FakeProvider {
+        fn active_app(&self) -> Option<ActiveApp> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.app.clone()
+        }
+    }
+
+    fn terminal() -> ActiveApp {
+        ActiveApp {
+            window_instance: Some("gnome-terminal-server".to_string()),
+            window_class: Some("Gnome-terminal".to_string()),
+            process_name: Some("gnome-terminal-server".to_string()),
+        }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn globs_match_the_whole_name() {
+        assert!(glob_match("firefox", "firefox"));
+        assert!(!glob_match("firefox", "firefox-esr"));
+        assert!(!glob_match("firefox", "fire"));
+        assert!(glob_match("firefox*", "firefox-esr"));
+        assert!(glob_match("*fox", "firefox"));
+        assert!(!glob_match("*fox", "firefox-esr"));
+        assert!(glob_match("*term*", "gnome-terminal"));
+        assert!(glob_match("g*-*-server", "gnome-terminal-server"));
+        assert!(!glob_match("g*-*-client", "gnome-terminal-server"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "x"));
+        // The suffix may not reuse characters the prefix already matched
+        assert!(!glob_match("ab*ba", "aba"));
+        assert!(glob_match("ab*ba", "abba"));
+    }
+
+    #[test]
+    fn patterns_ignore_case_and_check_every_name() {
+        let app = terminal();
+        assert!(app.matches("GNOME-TERMINAL"));
+        assert!(app.matches(" gnome-terminal-server "));
+        assert!(app.matches("*terminal*"));
+        assert!(!app.matches("konsole"));
+        assert!(!ActiveApp::default().matches("*"));
+    }
+
+    #[test]
+    fn excludes_focused_apps_matching_a_pattern() {
+        let provider = FakeProvider::new(Some(terminal()));
+        assert!(FocusedApp::new(Some(&provider)).is_excluded(&patterns(&["keepassxc", "gnome-terminal"])));
+        assert!(!FocusedApp::new(Some(&provider)).is_excluded(&patterns(&["keepassxc"])));
+
+        // Nothing to exclude without a focused app, and no lookup without
+        // exclusions
+        let lookups = provider.lookups();
+        assert!(!FocusedApp::new(Some(&provider)).is_excluded(&[]));
+        assert_eq!(provider.lookups(), lookups);
+        assert!(!FocusedApp::new(Some(&FakeProvider::new(None))).is_excluded(&patterns(&["*"])));
+        assert!(!FocusedApp::new(None).is_excluded(&patterns(&["*"])));
+    }
+
+    #[test]
+    fn ranks_snippets_by_how_specifically_they_target_the_app() {
+        let provider = FakeProvider::new(Some(terminal()));
+        let mut focused_app = FocusedApp::new(Some(&provider));
+
+        assert_eq!(focused_app.specificity(&[], &[]), Some(0));
+        assert_eq!(focused_app.specificity(&[], &patterns(&["firefox"])), Some(1));
+        assert_eq!(focused_app.specificity(&patterns(&["*terminal*"]), &[]), Some(2));
+        assert_eq!(focused_app.specificity(&patterns(&["firefox"]), &[]), None);
+        assert_eq!(focused_app.specificity(&[], &patterns(&["gnome-terminal"])), None);
+        // Exclusion wins over inclusion
+        assert_eq!(
+            focused_app.specificity(&patterns(&["*terminal*"]), &patterns(&["gnome-terminal"])),
+            None
+        );
+    }
+
+    #[test]
+    fn scoped_snippets_need_a_focused_app() {
+        let provider = FakeProvider::new(None);
+        let mut focused_app = FocusedApp::new(Some(&provider));
+        assert_eq!(focused_app.specificity(&[], &patterns(&["firefox"])), Some(1));
+        assert_eq!(focused_app.specificity(&patterns(&["firefox"]), &[]), None);
+
+        let mut no_provider = FocusedApp::new(None);
+        assert_eq!(no_provider.specificity(&[], &[]), Some(0));
+        assert_eq!(no_provider.specificity(&patterns(&["firefox"]), &[]), None);
+    }
+
+    #[test]
+    fn looks_up_the_app_once_and_only_for_scoped_snippets() {
+        let provider = FakeProvider::new(Some(terminal()));
+        let mut focused_app = FocusedApp::new(Some(&provider));
+
+        focused_app.specificity(&[], &[]);
+        assert_eq!(provider.lookups(), 0);
+
+        focused_app.specificity(&patterns(&["firefox"]), &[]);
+        focused_app.specificity(&[], &patterns(&["konsole"]));
+        focused_app.specificity(&patterns(&["*terminal*"]), &[]);
+        focused_app.is_excluded(&patterns(&["keepassxc"]));
+        assert_eq!(provider.lookups(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::Mutex;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::active_app::{ActiveApp, ActiveAppProvider};
+
+// Resolves the focused window through EWMH: `_NET_ACTIVE_WINDOW` on the root
+// window, then WM_CLASS and `_NET_WM_PID` on that window.
+pub struct X11ActiveApp {
+    conn: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    net_wm_pid: Atom,
+    // The window last resolved and its app. Triggers mostly fire in the
+    // same window, so a lookup usually costs a single round trip.
+    last: Mutex<Option<(Window, ActiveApp)>>,
+}
+
+impl X11ActiveApp {
+    pub fn connect() -> Option<Self> {
+        let (conn, screen_num) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots.get(screen_num)?.root;
+        let net_active_window = intern_atom(&conn, b"_NET_ACTIVE_WINDOW")?;
+        let net_wm_pid = intern_atom(&conn, b"_NET_WM_PID")?;
+
+        Some(X11ActiveApp {
+            conn,
+            root,
+            net_active_window,
+            net_wm_pid,
+            last: Mutex::new(None),
+        })
+    }
+
+    fn active_window(&self) -> Option<Window> {
+        let reply = self
+            .conn
+            .get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        let window = reply.value32()?.next()?;
+        (window != 0).then_some(window)
+    }
+
+    fn wm_class(&self, window: Window) -> (Option<String>, Option<String>) {
+        let reply = self
+            .conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok());
+        let Some(reply) = reply else {
+            return (None, None);
+        };
+
+        // WM_CLASS holds "instance\0class\0"
+        let mut parts = reply
+            .value
+            .split(|byte| *byte == 0)
+            .filter(|part| !part.is_empty())
+            .map(|part| String::from_utf8_lossy(part).into_owned());
+        (parts.next(), parts.next())
+    }
+
+    fn process_name(&self, window: Window) -> Option<String> {
+        let reply = self
+            .conn
+            .get_property(false, window, self.net_wm_pid, AtomEnum::CARDINAL, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        let pid = reply.value32()?.next()?;
+
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(comm.trim().to_string())
+    }
+}
+
+impl ActiveAppProvider for X11ActiveApp {
+    fn active_app(&self) -> Option<ActiveApp> {
+        let window = self.active_window()?;
+        let mut last = self.last.lock().unwrap();
+        if let Some((last_window, app)) = last.as_ref() {
+            if *last_window == window {
+                return Some(app.clone());
+            }
+        }
+
+        let (window_instance, window_class) = self.wm_class(window);
+        let app = ActiveApp {
+            window_instance,
+            window_class,
+            process_name: self.process_name(window),
+        };
+        *last = Some((window, app.clone()));
+        Some(app)
+    }
+}
+
+fn intern_atom(conn: &RustConnection, name: &[u8]) -> Option<Atom> {
+    Some(conn.intern_atom(false, name).ok()?.reply().ok()?.atom)
+}
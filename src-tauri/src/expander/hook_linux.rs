@@ -1,20 +1,62 @@
 #[cfg(target_os = "linux")]
 use rdev::{listen, Event, EventType, Key};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ConnectionExt, KeyButMask, Window};
+use x11rb::rust_connection::RustConnection;
 
 pub fn start_hook() {
+    refresh_caps_lock();
     if let Err(error) = listen(callback) {
         eprintln!("Error listening to events: {:?}", error);
     }
 }
 
-fn callback(event: Event) {
-    if !crate::expander::is_active() {
+// Shift is tracked from the key events we see
+static SHIFT_DOWN: AtomicBool = AtomicBool::new(false);
+// Caps Lock is read from the X server, since it may already be on when the
+// hook starts. Between reads a press is taken to toggle it.
+static CAPS_LOCK: AtomicBool = AtomicBool::new(false);
+static X11: OnceLock<Option<(RustConnection, Window)>> = OnceLock::new();
+
+fn refresh_caps_lock() {
+    let x11 = X11.get_or_init(|| {
+        let (conn, screen_num) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots.get(screen_num)?.root;
+        Some((conn, root))
+    });
+    let Some((conn, root)) = x11 else {
         return;
+    };
+
+    // The pointer query reports the modifiers in effect, Lock among them
+    if let Some(reply) = conn.query_pointer(*root).ok().and_then(|cookie| cookie.reply().ok()) {
+        CAPS_LOCK.store(reply.mask.contains(KeyButMask::LOCK), Ordering::SeqCst);
     }
+}
 
+fn callback(event: Event) {
     match event.event_type {
+        EventType::KeyPress(Key::ShiftLeft | Key::ShiftRight) => {
+            SHIFT_DOWN.store(true, Ordering::SeqCst);
+        }
+        EventType::KeyRelease(Key::ShiftLeft | Key::ShiftRight) => {
+            SHIFT_DOWN.store(false, Ordering::SeqCst);
+        }
+        EventType::KeyPress(Key::CapsLock) => {
+            CAPS_LOCK.fetch_xor(true, Ordering::SeqCst);
+        }
+        // The server has applied the toggle by the time the key comes up
+        EventType::KeyRelease(Key::CapsLock) => refresh_caps_lock(),
         EventType::KeyPress(key) => {
-            if let Some(character) = key_to_char(key) {
+            if !crate::expander::is_active() {
+                return;
+            }
+
+            let shift = SHIFT_DOWN.load(Ordering::SeqCst);
+            let caps = CAPS_LOCK.load(Ordering::SeqCst);
+            if let Some(character) = key_to_char(key, shift, caps) {
                 crate::engine::on_key_typed(character);
             }
         }
@@ -22,7 +64,45 @@ fn callback(event: Event) {
     }
 }
 
-fn key_to_char(key: Key) -> Option<char> {
+fn key_to_char(key: Key, shift: bool, caps: bool) -> Option<char> {
+    if let Some(letter) = key_to_letter(key) {
+        return Some(if shift != caps { letter.to_ascii_uppercase() } else { letter });
+    }
+
+    // US layout symbols
+    let (plain, shifted) = match key {
+        Key::Num0 => ('0', ')'),
+        Key::Num1 => ('1', '!'),
+        Key::Num2 => ('2', '@'),
+        Key::Num3 => ('3', '#'),
+        Key::Num4 => ('4', '$'),
+        Key::Num5 => ('5', '%'),
+        Key::Num6 => ('6', '^'),
+        Key::Num7 => ('7', '&'),
+        Key::Num8 => ('8', '*'),
+        Key::Num9 => ('9', '('),
+        Key::Slash => ('/', '?'),
+        Key::Minus => ('-', '_'),
+        Key::Dot => ('.', '>'),
+        Key::Comma => (',', '<'),
+        Key::SemiColon => (';', ':'),
+        Key::Quote => ('\'', '"'),
+        Key::Equal => ('=', '+'),
+        Key::LeftBracket => ('[', '{'),
+        Key::RightBracket => (']', '}'),
+        Key::BackSlash => ('\\', '|'),
+        Key::BackQuote => ('`', '~'),
+        Key::Space => return Some(' '),
+        Key::Return => return Some('\n'),
+        Key::Tab => return Some('\t'),
+        Key::Backspace => return Some('\x08'),
+        _ => return None,
+    };
+
+    Some(if shift { shifted } else { plain })
+}
+
+fn key_to_letter(key: Key) -> Option<char> {
     match key {
         Key::KeyA => Some('a'),
         Key::KeyB => Some('b'),
@@ -50,22 +130,6 @@ fn key_to_char(key: Key) -> Option<char> {
         Key::KeyX => Some('x'),
         Key::KeyY => Some('y'),
         Key::KeyZ => Some('z'),
-        Key::Num0 => Some('0'),
-        Key::Num1 => Some('1'),
-        Key::Num2 => Some('2'),
-        Key::Num3 => Some('3'),
-        Key::Num4 => Some('4'),
-        Key::Num5 => Some('5'),
-        Key::Num6 => Some('6'),
-        Key::Num7 => Some('7'),
-        Key::Num8 => Some('8'),
-        Key::Num9 => Some('9'),
-        Key::Space => Some(' '),
-        Key::Slash => Some('/'),
-        Key::Minus => Some('-'),
-        Key::Dot => Some('.'),
-        Key::Comma => Some(','),
-        Key::Backspace => Some('\x08'),
         _ => None,
     }
-}
\ No newline at end of file
+}
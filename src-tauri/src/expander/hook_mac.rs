@@ -41,6 +41,10 @@ pub fn start_hook() {
 }
 
 fn handle_key_event(event: CGEvent) {
+    if !crate::expander::is_active() {
+        return;
+    }
+
     if let Some(unicode_string) = event.get_string_value_field(EventField::KeyboardEventUnicodeString) {
         if let Some(character) = unicode_string.chars().next() {
             crate::engine::on_key_typed(character);
@@ -1,7 +1,7 @@
 #[cfg(target_os = "windows")]
 use winapi::um::winuser::{
-    SetWindowsHookExW, UnhookWindowsHookEx, CallNextHookEx, GetMessageW, 
-    HC_ACTION, WH_KEYBOARD_LL, WM_KEYDOWN, WM_CHAR, KBDLLHOOKSTRUCT
+    SetWindowsHookExW, UnhookWindowsHookEx, CallNextHookEx, GetMessageW, GetKeyState,
+    HC_ACTION, WH_KEYBOARD_LL, WM_KEYDOWN, WM_CHAR, KBDLLHOOKSTRUCT, VK_SHIFT, VK_CAPITAL
 };
 use winapi::um::winnt::LPCWSTR;
 use winapi::shared::windef::HHOOK;
@@ -24,12 +24,10 @@ pub fn start_hook() {
             return;
         }
 
+        // Pumps messages so the hook keeps being called; keystrokes are
+        // ignored while the expander is stopped
         let mut msg = std::mem::zeroed();
-        while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) != 0 {
-            if !crate::expander::is_active() {
-                break;
-            }
-        }
+        while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) != 0 {}
 
         UnhookWindowsHookEx(HOOK);
     }
@@ -40,12 +38,16 @@ unsafe extern "system" fn low_level_keyboard_proc(
     w_param: WPARAM,
     l_param: LPARAM,
 ) -> LRESULT {
-    if n_code == HC_ACTION && w_param == WM_KEYDOWN as usize {
+    if n_code == HC_ACTION && w_param == WM_KEYDOWN as usize && crate::expander::is_active() {
         let kb_struct = *(l_param as *const KBDLLHOOKSTRUCT);
         let vk_code = kb_struct.vkCode;
         
+        // High bit is set while shift is held, low bit while caps lock is on
+        let shift = GetKeyState(VK_SHIFT) < 0;
+        let caps = GetKeyState(VK_CAPITAL) & 1 != 0;
+        
         // Convert virtual key code to character
-        if let Some(character) = vk_to_char(vk_code) {
+        if let Some(character) = vk_to_char(vk_code, shift, caps) {
             crate::engine::on_key_typed(character);
         }
     }
@@ -53,16 +55,41 @@ unsafe extern "system" fn low_level_keyboard_proc(
     CallNextHookEx(HOOK, n_code, w_param, l_param)
 }
 
-fn vk_to_char(vk_code: u32) -> Option<char> {
-    match vk_code {
-        0x30..=0x39 => Some((vk_code - 0x30 + '0' as u32) as u8 as char), // 0-9
-        0x41..=0x5A => Some((vk_code - 0x41 + 'a' as u32) as u8 as char), // A-Z -> a-z
-        0xBF => Some('/'), // Forward slash
-        0xBD => Some('-'), // Minus
-        0xBE => Some('.'), // Period
-        0xBC => Some(','), // Comma
-        0x20 => Some(' '), // Space
-        0x08 => Some('\x08'), // Backspace
-        _ => None,
+fn vk_to_char(vk_code: u32, shift: bool, caps: bool) -> Option<char> {
+    if let 0x41..=0x5A = vk_code {
+        let letter = (vk_code - 0x41 + 'a' as u32) as u8 as char; // A-Z
+        return Some(if shift != caps { letter.to_ascii_uppercase() } else { letter });
     }
+    
+    // US layout symbols
+    let (plain, shifted) = match vk_code {
+        0x30 => ('0', ')'),
+        0x31 => ('1', '!'),
+        0x32 => ('2', '@'),
+        0x33 => ('3', '#'),
+        0x34 => ('4', '$'),
+        0x35 => ('5', '%'),
+        0x36 => ('6', '^'),
+        0x37 => ('7', '&'),
+        0x38 => ('8', '*'),
+        0x39 => ('9', '('),
+        0xBF => ('/', '?'), // Forward slash
+        0xBD => ('-', '_'), // Minus
+        0xBE => ('.', '>'), // Period
+        0xBC => (',', '<'), // Comma
+        0xBA => (';', ':'), // Semicolon
+        0xDE => ('\'', '"'), // Quote
+        0xBB => ('=', '+'), // Equals
+        0xDB => ('[', '{'), // Left bracket
+        0xDD => (']', '}'), // Right bracket
+        0xDC => ('\\', '|'), // Backslash
+        0xC0 => ('`', '~'), // Backtick
+        0x20 => return Some(' '), // Space
+        0x0D => return Some('\n'), // Enter
+        0x09 => return Some('\t'), // Tab
+        0x08 => return Some('\x08'), // Backspace
+        _ => return None,
+    };
+    
+    Some(if shift { shifted } else { plain })
 }
\ No newline at end of file
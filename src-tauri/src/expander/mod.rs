@@ -1,6 +1,9 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Once, RwLock};
 use std::thread;
 
+mod active_app;
+#[cfg(target_os = "linux")]
+mod app_linux;
 #[cfg(target_os = "macos")]
 mod hook_mac;
 #[cfg(target_os = "windows")]
@@ -8,38 +11,69 @@ mod hook_windows;
 #[cfg(target_os = "linux")]
 mod hook_linux;
 
+pub use active_app::{ActiveAppProvider, FocusedApp};
+#[cfg(test)]
+pub use active_app::ActiveApp;
+
 static EXPANDER_ACTIVE: Mutex<bool> = Mutex::new(false);
+static HOOK_STARTED: Once = Once::new();
+static EXCLUDED_APPS: RwLock<Vec<String>> = RwLock::new(Vec::new());
 
-pub fn start() {
-    let mut active = EXPANDER_ACTIVE.lock().unwrap();
-    if *active {
-        return; // Already running
+lazy_static::lazy_static! {
+    static ref APP_PROVIDER: Option<Box<dyn ActiveAppProvider>> = platform_app_provider();
+}
+
+fn platform_app_provider() -> Option<Box<dyn ActiveAppProvider>> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(provider) = app_linux::X11ActiveApp::connect() {
+            return Some(Box::new(provider));
+        }
+        eprintln!("Could not connect to X11, app exclusions are disabled");
     }
-    *active = true;
-    drop(active);
-
-    thread::spawn(|| {
-        #[cfg(target_os = "macos")]
-        hook_mac::start_hook();
-        
-        #[cfg(target_os = "windows")]
-        hook_windows::start_hook();
-        
-        #[cfg(target_os = "linux")]
-        hook_linux::start_hook();
-    });
+
+    None
 }
 
-pub fn stop() {
-    let mut active = EXPANDER_ACTIVE.lock().unwrap();
-    *active = false;
+// Turns expansion on, installing the keyboard hook the first time. The hook
+// then runs for the life of the process: rdev's `listen` never returns, so a
+// hook cannot be stopped and started again, only told to ignore keystrokes.
+pub fn start() {
+    *EXPANDER_ACTIVE.lock().unwrap() = true;
+
+    HOOK_STARTED.call_once(|| {
+        thread::spawn(|| {
+            #[cfg(target_os = "macos")]
+            hook_mac::start_hook();
+
+            #[cfg(target_os = "windows")]
+            hook_windows::start_hook();
+
+            #[cfg(target_os = "linux")]
+            hook_linux::start_hook();
+        });
+    });
 }
 
-pub fn reload() {
-    stop();
-    start();
+pub fn stop() {
+    *EXPANDER_ACTIVE.lock().unwrap() = false;
 }
 
 pub fn is_active() -> bool {
     *EXPANDER_ACTIVE.lock().unwrap()
+}
+
+pub fn set_excluded_apps(apps: Vec<String>) {
+    *EXCLUDED_APPS.write().unwrap() = apps;
+}
+
+// A fresh lookup of the focused app, made lazily through the platform provider
+pub fn focused_app() -> FocusedApp<'static> {
+    FocusedApp::new(APP_PROVIDER.as_deref())
+}
+
+// Whether the user turned expansion off in the focused app. Asked only once a
+// trigger has matched, so plain typing never waits on the platform.
+pub fn is_excluded(focused_app: &mut FocusedApp) -> bool {
+    focused_app.is_excluded(&EXCLUDED_APPS.read().unwrap())
 }
\ No newline at end of file
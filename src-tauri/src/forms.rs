@@ -0,0 +1,109 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder, WindowEvent};
+
+use crate::vars::{FormField, FormValues};
+
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+static NEXT_FORM_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static::lazy_static! {
+    // Open forms waiting for the window to submit or cancel
+    static ref PENDING: Mutex<HashMap<u64, PendingForm>> = Mutex::new(HashMap::new());
+}
+
+struct PendingForm {
+    snippet_name: String,
+    fields: Vec<FormField>,
+    reply: Sender<Option<FormValues>>,
+}
+
+// What the form window shows, fetched by id once it has loaded
+#[derive(Debug, Clone, Serialize)]
+pub struct SnippetForm {
+    pub id: u64,
+    pub snippet_name: String,
+    pub fields: Vec<FormField>,
+}
+
+pub fn init(handle: AppHandle) {
+    let _ = APP_HANDLE.set(handle);
+}
+
+// Opens a window asking for the fields and blocks until it is submitted.
+// Returns None when the form is cancelled or closed, or cannot be shown.
+pub fn ask(snippet_name: &str, fields: Vec<FormField>) -> Option<FormValues> {
+    let handle = APP_HANDLE.get()?;
+    let id = NEXT_FORM_ID.fetch_add(1, Ordering::SeqCst);
+    let (reply, answer) = mpsc::channel();
+
+    let height = 140.0 + 72.0 * fields.len() as f64;
+    PENDING.lock().unwrap().insert(id, PendingForm {
+        snippet_name: snippet_name.to_string(),
+        fields,
+        reply,
+    });
+
+    let label = format!("snippet-form-{}", id);
+    let window = WebviewWindowBuilder::new(handle, &label, WebviewUrl::App(format!("form/{}", id).into()))
+        .title(snippet_name)
+        .inner_size(420.0, height.min(640.0))
+        .resizable(false)
+        .always_on_top(true)
+        .center()
+        .focused(true)
+        .build();
+
+    match window {
+        Ok(window) => window.on_window_event(move |event| {
+            if let WindowEvent::Destroyed = event {
+                finish(id, None);
+            }
+        }),
+        Err(e) => {
+            eprintln!("Failed to open snippet form: {}", e);
+            PENDING.lock().unwrap().remove(&id);
+            return None;
+        }
+    }
+
+    let values = answer.recv().ok().flatten();
+    if let Some(window) = handle.get_webview_window(&label) {
+        let _ = window.destroy();
+    }
+    values
+}
+
+pub fn get(id: u64) -> Option<SnippetForm> {
+    PENDING.lock().unwrap().get(&id).map(|form| SnippetForm {
+        id,
+        snippet_name: form.snippet_name.clone(),
+        fields: form.fields.clone(),
+    })
+}
+
+// Answers a pending form with the user's values, unless one fails its
+// field's validation; the form then stays open
+pub fn submit(id: u64, values: FormValues) -> Result<(), String> {
+    {
+        let pending = PENDING.lock().unwrap();
+        let form = pending.get(&id).ok_or("Form is no longer open")?;
+        for field in &form.fields {
+            if let Some(value) = values.get(&field.name) {
+                field.check(value)?;
+            }
+        }
+    }
+    finish(id, Some(values));
+    Ok(())
+}
+
+// Answers a pending form; None cancels the expansion
+pub fn finish(id: u64, values: Option<FormValues>) {
+    if let Some(form) = PENDING.lock().unwrap().remove(&id) {
+        let _ = form.reply.send(values);
+    }
+}
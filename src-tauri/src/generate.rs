@@ -0,0 +1,154 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+// Longest {{lorem}} output, in words or sentences
+const MAX_LOREM_LENGTH: usize = 10_000;
+
+const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
+    incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation \
+    ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in \
+    voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non \
+    proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+pub fn uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+// Inclusive bounds written `1-100`; either may be negative, as in `-5--1`
+pub fn parse_range(text: &str) -> Result<(i64, i64), String> {
+    let text = text.trim();
+    let invalid = || format!("{:?} is not a range such as 1-100", text);
+    let separator = text.get(1..).and_then(|rest| rest.find('-')).ok_or_else(invalid)? + 1;
+    let min: i64 = text[..separator].trim().parse().map_err(|_| invalid())?;
+    let max: i64 = text[separator + 1..].trim().parse().map_err(|_| invalid())?;
+    if min > max {
+        return Err(format!("range {} is empty", text));
+    }
+    Ok((min, max))
+}
+
+pub fn random(min: i64, max: i64) -> i64 {
+    rand::thread_rng().gen_range(min..=max)
+}
+
+pub fn random_choice(choices: &[String]) -> Option<&String> {
+    choices.choose(&mut rand::thread_rng())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoremLength {
+    Words(usize),
+    Sentences(usize),
+}
+
+impl LoremLength {
+    // `words=20` or `sentences=3`; nothing at all is one sentence
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(LoremLength::Sentences(1));
+        }
+
+        let Some((unit, count)) = text.split_once('=') else {
+            return Err(format!("{:?} is not words=N or sentences=N", text));
+        };
+        let count: usize = count
+            .trim()
+            .parse()
+            .map_err(|_| format!("{:?} is not a whole number", count.trim()))?;
+        if count == 0 || count > MAX_LOREM_LENGTH {
+            return Err(format!("length must be from 1 to {}", MAX_LOREM_LENGTH));
+        }
+        match unit.trim() {
+            "words" => Ok(LoremLength::Words(count)),
+            "sentences" => Ok(LoremLength::Sentences(count)),
+            other => Err(format!("unknown lorem unit {:?}", other)),
+        }
+    }
+}
+
+// The classic placeholder text, repeated as often as needed
+pub fn lorem(length: LoremLength) -> String {
+    match length {
+        LoremLength::Words(count) => {
+            let words: Vec<&str> = LOREM.split_whitespace().cycle().take(count).collect();
+            let text = words.join(" ");
+            format!("{}.", text.trim_end_matches([',', '.']))
+        }
+        LoremLength::Sentences(count) => {
+            let sentences: Vec<&str> = LOREM
+                .split_inclusive(". ")
+                .map(str::trim)
+                .cycle()
+                .take(count)
+                .collect();
+            sentences.join(" ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("1-100"), Ok((1, 100)));
+        assert_eq!(parse_range(" 3 - 3 "), Ok((3, 3)));
+        assert_eq!(parse_range("-5--1"), Ok((-5, -1)));
+        assert_eq!(parse_range("-5-5"), Ok((-5, 5)));
+        assert_eq!(parse_range("-9223372036854775808-9223372036854775807"), Ok((i64::MIN, i64::MAX)));
+
+        assert_eq!(parse_range("5-1"), Err("range 5-1 is empty".to_string()));
+        assert_eq!(parse_range("-1--5"), Err("range -1--5 is empty".to_string()));
+        for text in ["", "5", "-5", "a-b", "1-", "-1-", "1-2-3", "0-9223372036854775808", "-9223372036854775809-0"] {
+            assert!(parse_range(text).unwrap_err().contains("is not a range"), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn random_stays_in_range() {
+        assert_eq!(random(7, 7), 7);
+        assert!((0..100).map(|_| random(-2, 2)).all(|n| (-2..=2).contains(&n)));
+        // The widest range must not overflow
+        random(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn lorem_has_the_requested_length() {
+        let words = lorem(LoremLength::Words(5));
+        assert_eq!(words, "Lorem ipsum dolor sit amet.");
+        // Longer than the text itself, which then repeats
+        let words = lorem(LoremLength::Words(200));
+        assert_eq!(words.split_whitespace().count(), 200);
+        assert!(words.ends_with('.') && !words.ends_with(",."));
+
+        assert_eq!(lorem(LoremLength::Sentences(1)), LOREM.split_inclusive(". ").next().unwrap().trim());
+        let sentences = lorem(LoremLength::Sentences(9));
+        assert_eq!(sentences.matches(". ").count(), 8);
+        assert!(sentences.ends_with('.'));
+    }
+
+    #[test]
+    fn parses_lorem_lengths() {
+        assert_eq!(LoremLength::parse(""), Ok(LoremLength::Sentences(1)));
+        assert_eq!(LoremLength::parse("words=20"), Ok(LoremLength::Words(20)));
+        assert_eq!(LoremLength::parse(" sentences = 3 "), Ok(LoremLength::Sentences(3)));
+        assert!(LoremLength::parse("words=0").is_err());
+        assert!(LoremLength::parse("words=10001").is_err());
+        assert!(LoremLength::parse("words=-1").is_err());
+        assert!(LoremLength::parse("paragraphs=2").is_err());
+        assert!(LoremLength::parse("20").is_err());
+    }
+
+    #[test]
+    fn uuids_are_random_version_4() {
+        let id = uuid();
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(groups.iter().map(|group| group.len()).collect::<Vec<_>>(), [8, 4, 4, 4, 12]);
+        assert!(id.chars().all(|c| c == '-' || c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert!(groups[2].starts_with('4'));
+        assert!(groups[3].starts_with(['8', '9', 'a', 'b']));
+        assert_ne!(uuid(), id);
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::{Mutex, OnceLock};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+// The shortcut currently registered, so changing the setting can release it
+static REGISTERED: Mutex<Option<Shortcut>> = Mutex::new(None);
+
+pub fn init(handle: AppHandle) {
+    let _ = APP_HANDLE.set(handle);
+}
+
+// Key names joined with `+`, e.g. "Ctrl+Alt+Space" or "CmdOrCtrl+Shift+K"
+pub fn parse(hotkey: &str) -> Result<Shortcut, String> {
+    hotkey
+        .parse()
+        .map_err(|e| format!("Invalid global hotkey {:?}: {}", hotkey, e))
+}
+
+// Makes `hotkey` the one that opens the search palette, releasing the
+// previous one. Fails if another application already holds it.
+pub fn register(hotkey: &str) -> Result<(), String> {
+    let shortcut = parse(hotkey)?;
+    let handle = APP_HANDLE.get().ok_or("Global hotkeys are not available yet")?;
+    let mut registered = REGISTERED.lock().unwrap();
+    if *registered == Some(shortcut) {
+        return Ok(());
+    }
+
+    let shortcuts = handle.global_shortcut();
+    if let Some(previous) = registered.take() {
+        let _ = shortcuts.unregister(previous);
+    }
+    shortcuts
+        .on_shortcut(shortcut, |app, _, event| {
+            if event.state() == ShortcutState::Pressed {
+                open_search(app);
+            }
+        })
+        .map_err(|e| format!("Failed to register global hotkey {:?}: {}", hotkey, e))?;
+    *registered = Some(shortcut);
+    Ok(())
+}
+
+// Brings the main window forward and asks it to focus its search field
+fn open_search(app: &AppHandle) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    let _ = window.show();
+    let _ = window.set_focus();
+    let _ = window.emit("open-search", ());
+}
@@ -0,0 +1,338 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::collections::HashMap;
+
+use super::{
+    find_conflict, find_or_create_folder, insert_snippet, insert_variable, untrusted_note, ImportItem, ImportReport,
+    NewSnippet,
+};
+use crate::database::VariableDefinition;
+use crate::settings::Settings;
+
+const BACKUP_FORMAT: &str = "spark-shortcut-hub-backup";
+const BACKUP_VERSION: u32 = 1;
+
+// Self-describing snapshot of a user's library. Ids are only meaningful
+// inside one backup; restores create fresh rows and remap them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub folders: Vec<BackupFolder>,
+    pub snippets: Vec<BackupSnippet>,
+    #[serde(default)]
+    pub settings: Option<Settings>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFolder {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupSnippet {
+    pub folder_id: Option<i64>,
+    pub name: String,
+    pub shortcut: String,
+    pub body: String,
+    #[serde(default = "default_true")]
+    pub is_active: bool,
+    #[serde(default = "default_match_type")]
+    pub match_type: String,
+    #[serde(default)]
+    pub propagate_case: bool,
+    #[serde(default)]
+    pub app_include: Vec<String>,
+    #[serde(default)]
+    pub app_exclude: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub variables: Vec<VariableDefinition>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    // Keep the existing snippet and report the conflict
+    #[default]
+    Skip,
+    // Replace the existing snippet's content with the backup's
+    Overwrite,
+    // Import under a free shortcut such as "/sig-2"
+    Rename,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestoreOptions {
+    pub conflict_policy: ConflictPolicy,
+    pub restore_settings: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_match_type() -> String {
+    "exact".to_string()
+}
+
+pub async fn export(db: &SqlitePool, user_id: i64) -> Result<String, String> {
+    let folders = sqlx::query_as!(
+        BackupFolder,
+        "SELECT id, name, parent_id FROM folders WHERE user_id = ? ORDER BY id",
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    let rows = sqlx::query!(
+        r#"SELECT id, folder_id, name, shortcut, body, is_active, match_type, propagate_case, app_include as "app_include: Json<Vec<String>>", app_exclude as "app_exclude: Json<Vec<String>>", tags as "tags: Json<Vec<String>>" FROM snippets WHERE user_id = ? ORDER BY id"#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    let variable_rows = sqlx::query!(
+        r#"SELECT v.snippet_id, v.key, v.type AS var_type, v.default_value, v.options as "options: Json<Vec<String>>", v.validation FROM variables v JOIN snippets s ON s.id = v.snippet_id WHERE s.user_id = ? ORDER BY v.id"#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut variables: HashMap<i64, Vec<VariableDefinition>> = HashMap::new();
+    for row in variable_rows {
+        variables.entry(row.snippet_id).or_default().push(VariableDefinition {
+            key: row.key,
+            var_type: row.var_type,
+            default_value: row.default_value,
+            options: row.options.0,
+            validation: row.validation,
+        });
+    }
+
+    let snippets = rows
+        .into_iter()
+        .map(|row| BackupSnippet {
+            folder_id: row.folder_id,
+            name: row.name,
+            shortcut: row.shortcut,
+            body: row.body,
+            is_active: row.is_active.unwrap_or(1) != 0,
+            match_type: row.match_type.unwrap_or_else(default_match_type),
+            propagate_case: row.propagate_case.unwrap_or(0) != 0,
+            app_include: row.app_include.0,
+            app_exclude: row.app_exclude.0,
+            tags: row.tags.0,
+            variables: variables.remove(&row.id).unwrap_or_default(),
+        })
+        .collect();
+
+    let backup = Backup {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        exported_at: Utc::now(),
+        folders,
+        snippets,
+        settings: Some(crate::settings::current()),
+    };
+
+    serde_json::to_string_pretty(&backup).map_err(|e| format!("Failed to write backup: {}", e))
+}
+
+pub async fn restore(
+    db: &SqlitePool,
+    user_id: i64,
+    json_data: &str,
+    options: &RestoreOptions,
+) -> Result<ImportReport, String> {
+    let backup: Backup = serde_json::from_str(json_data)
+        .map_err(|e| format!("Invalid backup: {}", e))?;
+
+    if backup.format != BACKUP_FORMAT {
+        return Err(format!("Not a backup file: format is {:?}", backup.format));
+    }
+    if backup.version > BACKUP_VERSION {
+        return Err(format!(
+            "Backup version {} is newer than this app supports ({})",
+            backup.version, BACKUP_VERSION
+        ));
+    }
+
+    let mut report = ImportReport::default();
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let folder_ids = restore_folders(&mut tx, user_id, &backup.folders, &mut report)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    for snippet in &backup.snippets {
+        restore_snippet(&mut tx, user_id, snippet, &folder_ids, options.conflict_policy, &mut report)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+    }
+
+    if options.restore_settings {
+        if let Some(settings) = &backup.settings {
+            crate::settings::validate(settings)?;
+        }
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    if options.restore_settings {
+        if let Some(settings) = backup.settings {
+            crate::settings::save(db, &settings).await?;
+            crate::settings::apply(settings);
+        }
+    }
+
+    Ok(report)
+}
+
+// Recreates the folder tree parents first, merging into folders with the
+// same name and parent. Returns backup folder id -> database folder id.
+async fn restore_folders(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    folders: &[BackupFolder],
+    report: &mut ImportReport,
+) -> Result<HashMap<i64, i64>, sqlx::Error> {
+    let mut folder_ids = HashMap::new();
+    let mut remaining: Vec<&BackupFolder> = folders.iter().collect();
+
+    while !remaining.is_empty() {
+        let before = remaining.len();
+        let mut deferred = Vec::new();
+
+        for folder in remaining {
+            let parent_id = match folder.parent_id {
+                None => None,
+                Some(parent) => match folder_ids.get(&parent) {
+                    Some(&id) => Some(id),
+                    None => {
+                        deferred.push(folder);
+                        continue;
+                    }
+                },
+            };
+            let id = find_or_create_folder(tx, user_id, &folder.name, parent_id, report).await?;
+            folder_ids.insert(folder.id, id);
+        }
+
+        // Parents missing from the backup or in a cycle: restore at the top
+        if deferred.len() == before {
+            for folder in deferred {
+                let id = find_or_create_folder(tx, user_id, &folder.name, None, report).await?;
+                folder_ids.insert(folder.id, id);
+            }
+            break;
+        }
+        remaining = deferred;
+    }
+
+    Ok(folder_ids)
+}
+
+async fn restore_snippet(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    backup: &BackupSnippet,
+    folder_ids: &HashMap<i64, i64>,
+    policy: ConflictPolicy,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    let folder_id = backup.folder_id.and_then(|id| folder_ids.get(&id).copied());
+    let mut snippet = NewSnippet::new(backup.name.clone(), backup.shortcut.clone(), backup.body.clone(), folder_id);
+    snippet.is_active = backup.is_active;
+    snippet.match_type = backup.match_type.clone();
+    snippet.propagate_case = backup.propagate_case;
+    snippet.app_include = Json(backup.app_include.clone());
+    snippet.app_exclude = Json(backup.app_exclude.clone());
+    snippet.tags = Json(backup.tags.clone());
+
+    let mut notes = Vec::new();
+    if let Some(existing_id) = find_conflict(tx, user_id, &snippet).await? {
+        match policy {
+            ConflictPolicy::Skip => {}
+            ConflictPolicy::Overwrite => {
+                overwrite_snippet(tx, existing_id, &snippet, &backup.variables).await?;
+                let mut notes = vec![format!("Overwrote snippet {}", existing_id)];
+                notes.extend(untrusted_note(&snippet.body));
+                report.imported.push(ImportItem {
+                    name: snippet.name,
+                    shortcut: snippet.shortcut,
+                    notes,
+                });
+                return Ok(());
+            }
+            ConflictPolicy::Rename => {
+                let original = snippet.shortcut.clone();
+                for suffix in 2.. {
+                    snippet.shortcut = format!("{}-{}", original, suffix);
+                    if find_conflict(tx, user_id, &snippet).await?.is_none() {
+                        break;
+                    }
+                }
+                notes.push(format!("Renamed from {}", original));
+            }
+        }
+    }
+
+    // Skipped conflicts are recorded by insert_snippet
+    if let Some(snippet_id) = insert_snippet(tx, user_id, snippet, notes, report).await? {
+        for variable in &backup.variables {
+            insert_variable(tx, snippet_id, variable).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn overwrite_snippet(
+    tx: &mut Transaction<'_, Sqlite>,
+    snippet_id: i64,
+    snippet: &NewSnippet,
+    variables: &[VariableDefinition],
+) -> Result<(), sqlx::Error> {
+    // The body now comes from the backup file, so it needs approving again
+    let now = Utc::now();
+    sqlx::query!(
+        "UPDATE snippets SET folder_id = ?, name = ?, body = ?, is_active = ?, match_type = ?, propagate_case = ?, tags = ?, trusted = 0, updated_at = ? WHERE id = ?",
+        snippet.folder_id,
+        snippet.name,
+        snippet.body,
+        snippet.is_active,
+        snippet.match_type,
+        snippet.propagate_case,
+        snippet.tags,
+        now,
+        snippet_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM variables WHERE snippet_id = ?", snippet_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for variable in variables {
+        insert_variable(tx, snippet_id, variable).await?;
+    }
+
+    Ok(())
+}
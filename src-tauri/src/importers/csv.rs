@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::{find_or_create_folder_path, folder_paths, insert_snippet, ImportReport, NewSnippet};
+
+// Header names to read each field from. Matching is case-insensitive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvColumns {
+    pub name: String,
+    pub shortcut: String,
+    pub body: String,
+    // Optional slash-separated folder path such as "Work/Support"
+    pub folder: Option<String>,
+    pub match_type: Option<String>,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        CsvColumns {
+            name: "name".to_string(),
+            shortcut: "shortcut".to_string(),
+            body: "content".to_string(),
+            folder: Some("folder".to_string()),
+            match_type: Some("match_type".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvImportOptions {
+    pub columns: CsvColumns,
+    // Defaults to ','
+    pub delimiter: Option<char>,
+    // Report what would be imported and roll back instead of committing
+    pub dry_run: bool,
+}
+
+struct ColumnIndexes {
+    name: usize,
+    shortcut: usize,
+    body: usize,
+    folder: Option<usize>,
+    match_type: Option<usize>,
+}
+
+pub async fn import(
+    db: &SqlitePool,
+    user_id: i64,
+    csv_data: &str,
+    options: &CsvImportOptions,
+) -> Result<ImportReport, String> {
+    let delimiter = delimiter_byte(options.delimiter)?;
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(csv_data.as_bytes());
+
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Invalid CSV header: {}", e))?
+        .clone();
+    let columns = resolve_columns(&headers, &options.columns)?;
+
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        ..ImportReport::default()
+    };
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Invalid CSV on record {}: {}", line + 1, e))?;
+        let field = |index: usize| record.get(index).unwrap_or("").to_string();
+
+        let name = field(columns.name);
+        let shortcut = field(columns.shortcut).trim().to_string();
+        let body = field(columns.body);
+        if body.is_empty() {
+            report.skip(&name, &shortcut, "Empty content");
+            continue;
+        }
+
+        let folder_id = match columns.folder.map(field) {
+            Some(path) if !path.trim().is_empty() => {
+                find_or_create_folder_path(&mut tx, user_id, &path, &mut report)
+                    .await
+                    .map_err(|e| format!("Database error: {}", e))?
+            }
+            _ => None,
+        };
+
+        let name = if name.trim().is_empty() { shortcut.clone() } else { name };
+        let mut snippet = NewSnippet::new(name, shortcut, body, folder_id);
+        if let Some(match_type) = columns.match_type.map(field).filter(|value| !value.trim().is_empty()) {
+            snippet.match_type = match_type.trim().to_string();
+        }
+
+        insert_snippet(&mut tx, user_id, snippet, vec![], &mut report)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+    }
+
+    if options.dry_run {
+        tx.rollback()
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+    } else {
+        tx.commit()
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+    }
+
+    Ok(report)
+}
+
+pub async fn export(
+    db: &SqlitePool,
+    user_id: i64,
+    columns: &CsvColumns,
+    delimiter: Option<char>,
+) -> Result<String, String> {
+    let snippets = sqlx::query!(
+        "SELECT name, shortcut, body, folder_id, match_type FROM snippets WHERE user_id = ? ORDER BY id",
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    let paths = folder_paths(db, user_id)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut writer = ::csv::WriterBuilder::new()
+        .delimiter(delimiter_byte(delimiter)?)
+        .from_writer(Vec::new());
+
+    let mut header = vec![columns.name.as_str(), columns.shortcut.as_str(), columns.body.as_str()];
+    header.extend(columns.folder.as_deref());
+    header.extend(columns.match_type.as_deref());
+    writer
+        .write_record(&header)
+        .map_err(|e| format!("Failed to write CSV: {}", e))?;
+
+    for snippet in snippets {
+        let mut record = vec![snippet.name, snippet.shortcut, snippet.body];
+        if columns.folder.is_some() {
+            let path = snippet
+                .folder_id
+                .and_then(|id| paths.get(&id).cloned())
+                .unwrap_or_default();
+            record.push(path);
+        }
+        if columns.match_type.is_some() {
+            record.push(snippet.match_type.unwrap_or_else(|| "exact".to_string()));
+        }
+
+        writer
+            .write_record(&record)
+            .map_err(|e| format!("Failed to write CSV: {}", e))?;
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| format!("Failed to write CSV: {}", e))?;
+    String::from_utf8(bytes).map_err(|e| format!("Failed to write CSV: {}", e))
+}
+
+fn delimiter_byte(delimiter: Option<char>) -> Result<u8, String> {
+    match delimiter.unwrap_or(',') {
+        c if c.is_ascii() && c != '"' && c != '\n' && c != '\r' => Ok(c as u8),
+        c => Err(format!("Unsupported CSV delimiter: {:?}", c)),
+    }
+}
+
+fn resolve_columns(headers: &::csv::StringRecord, columns: &CsvColumns) -> Result<ColumnIndexes, String> {
+    let find = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+    };
+    let required = |name: &str| find(name).ok_or_else(|| format!("CSV has no {:?} column", name));
+
+    Ok(ColumnIndexes {
+        name: required(&columns.name)?,
+        shortcut: required(&columns.shortcut)?,
+        body: required(&columns.body)?,
+        // Optional columns are simply ignored when absent
+        folder: columns.folder.as_deref().and_then(find),
+        match_type: columns.match_type.as_deref().and_then(find),
+    })
+}
@@ -0,0 +1,452 @@
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+use super::{find_or_create_folder_path, insert_snippet, insert_variable, ImportItem, ImportReport, NewSnippet};
+use crate::database::VariableDefinition;
+use crate::template::quote_argument;
+
+lazy_static::lazy_static! {
+    // `{{name}}` or `{{form.field}}` references in an espanso replacement
+    static ref ESPANSO_VAR_REGEX: Regex = Regex::new(r"\{\{\s*([A-Za-z0-9_\-]+)(?:\.([A-Za-z0-9_\-]+))?\s*\}\}").unwrap();
+    // `[[field]]` slots in an espanso form layout
+    static ref FORM_FIELD_REGEX: Regex = Regex::new(r"\[\[\s*([A-Za-z0-9_\-]+)\s*\]\]").unwrap();
+    // Any of our own `{{...}}` placeholders
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{\{([^}]*)\}\}").unwrap();
+}
+
+// espanso's marker for where the caret goes after expansion
+const ESPANSO_CURSOR: &str = "$|$";
+
+#[derive(Debug, Deserialize)]
+pub struct EspansoFile {
+    // File name such as "match/work.yml"; its stem becomes the folder name
+    pub name: Option<String>,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EspansoExport {
+    pub yaml: String,
+    // Snippets exported with placeholders espanso has no equivalent for
+    pub untranslated: Vec<ImportItem>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MatchFile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    global_vars: Vec<EspansoVar>,
+    #[serde(default)]
+    matches: Vec<EspansoMatch>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct EspansoMatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    triggers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    form: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    form_fields: Option<Mapping>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    vars: Vec<EspansoVar>,
+    #[serde(skip_serializing_if = "is_false")]
+    word: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    propagate_case: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EspansoVar {
+    name: String,
+    #[serde(rename = "type")]
+    var_type: String,
+    #[serde(default, skip_serializing_if = "Mapping::is_empty")]
+    params: Mapping,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+pub async fn import(db: &SqlitePool, user_id: i64, files: &[EspansoFile]) -> Result<ImportReport, String> {
+    let mut report = ImportReport::default();
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    for file in files {
+        let file_label = file.name.as_deref().unwrap_or("espanso");
+        let parsed: MatchFile = serde_yaml::from_str(&file.content)
+            .map_err(|e| format!("Invalid espanso file {}: {}", file_label, e))?;
+
+        let folder_id = match file.name.as_deref().and_then(file_stem) {
+            Some(stem) => find_or_create_folder_path(&mut tx, user_id, stem, &mut report)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?,
+            None => None,
+        };
+
+        for espanso_match in &parsed.matches {
+            import_match(&mut tx, user_id, espanso_match, &parsed.global_vars, folder_id, &mut report)
+                .await
+                .map_err(|e| format!("Database error: {}", e))?;
+        }
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(report)
+}
+
+async fn import_match(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: i64,
+    espanso_match: &EspansoMatch,
+    global_vars: &[EspansoVar],
+    folder_id: Option<i64>,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    let mut triggers: Vec<String> = espanso_match.trigger.iter().cloned().collect();
+    triggers.extend(espanso_match.triggers.iter().flatten().cloned());
+    let is_regex = espanso_match.regex.is_some();
+    if let Some(regex) = &espanso_match.regex {
+        triggers.push(regex.clone());
+    }
+
+    let label = espanso_match.label.clone().unwrap_or_else(|| triggers.join(", "));
+    if triggers.is_empty() {
+        report.skip(&label, "", "Match has no trigger");
+        return Ok(());
+    }
+
+    let mut notes = Vec::new();
+    let body = if let Some(layout) = &espanso_match.form {
+        translate_form_layout(layout, espanso_match.form_fields.as_ref(), &mut notes)
+    } else if let Some(replace) = &espanso_match.replace {
+        let vars: Vec<&EspansoVar> = global_vars.iter().chain(&espanso_match.vars).collect();
+        translate_replace(replace, &vars, is_regex, &mut notes)
+    } else {
+        report.skip(&label, &triggers[0], "Only replace and form matches can be imported");
+        return Ok(());
+    };
+
+    for trigger in triggers {
+        let mut snippet = NewSnippet::new(label.clone(), trigger, body.clone(), folder_id);
+        snippet.propagate_case = espanso_match.propagate_case;
+        snippet.match_type = if is_regex {
+            "regex".to_string()
+        } else if espanso_match.word {
+            // espanso word triggers wait for the separator typed after them
+            "delimiter".to_string()
+        } else {
+            "exact".to_string()
+        };
+
+        let Some(snippet_id) = insert_snippet(tx, user_id, snippet, notes.clone(), report).await? else {
+            continue;
+        };
+
+        for var in &espanso_match.vars {
+            // Kept for reference; espanso types are not form field types, so
+            // these never change how the snippet renders
+            let variable = VariableDefinition {
+                key: var.name.clone(),
+                var_type: var.var_type.clone(),
+                default_value: serde_json::to_string(&var.params).ok(),
+                options: Vec::new(),
+                validation: None,
+            };
+            insert_variable(tx, snippet_id, &variable).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_stem(name: &str) -> Option<&str> {
+    let file = name.rsplit(['/', '\\']).next()?;
+    let stem = file
+        .strip_suffix(".yml")
+        .or_else(|| file.strip_suffix(".yaml"))
+        .unwrap_or(file);
+    (!stem.is_empty()).then_some(stem)
+}
+
+// Rewrites espanso `{{var}}` references using the variable definitions
+fn translate_replace(replace: &str, vars: &[&EspansoVar], is_regex: bool, notes: &mut Vec<String>) -> String {
+    let by_name: HashMap<&str, &EspansoVar> = vars.iter().map(|var| (var.name.as_str(), *var)).collect();
+
+    let translated = ESPANSO_VAR_REGEX.replace_all(replace, |caps: &Captures| {
+        let original = caps[0].to_string();
+        let name = &caps[1];
+        let field = caps.get(2).map(|field| field.as_str());
+
+        let Some(var) = by_name.get(name) else {
+            if is_regex && field.is_none() {
+                return format!("{{{{capture:{}}}}}", name);
+            }
+            notes.push(format!("Unknown variable {}", original));
+            return original;
+        };
+
+        match translate_var(var, field) {
+            Ok(placeholder) => placeholder,
+            Err(note) => {
+                notes.push(note);
+                original
+            }
+        }
+    });
+
+    translated.replace(ESPANSO_CURSOR, "{{cursor}}")
+}
+
+fn translate_var(var: &EspansoVar, field: Option<&str>) -> Result<String, String> {
+    let param = |key: &str| var.params.get(key);
+    let string_param = |key: &str| param(key).and_then(Value::as_str);
+
+    match var.var_type.as_str() {
+        "date" => {
+            let mut spec = string_param("format").unwrap_or("%x").to_string();
+            // espanso offsets are in seconds
+            match param("offset") {
+                None => {}
+                Some(offset) => match offset.as_i64() {
+                    Some(seconds) => spec.push_str(&format!("|{:+}s", seconds)),
+                    None => return Err(format!("Date offset in {} is not a number of seconds", var.name)),
+                },
+            }
+            Ok(format!("{{{{date:{}}}}}", quote_argument(&spec)))
+        }
+        "clipboard" => Ok("{{clipboard}}".to_string()),
+        "shell" => {
+            let Some(cmd) = string_param("cmd") else {
+                return Err(format!("Shell variable {} has no cmd", var.name));
+            };
+            Ok(command_placeholder("shell", cmd, param("trim")))
+        }
+        "script" => {
+            let args: Option<Vec<&str>> = param("args")
+                .and_then(Value::as_sequence)
+                .map(|args| args.iter().filter_map(Value::as_str).collect());
+            let Some(args) = args.filter(|args| !args.is_empty()) else {
+                return Err(format!("Script variable {} has no args", var.name));
+            };
+            let command_line: Vec<String> = args
+                .iter()
+                .map(|arg| if arg.contains(char::is_whitespace) { format!("'{}'", arg) } else { arg.to_string() })
+                .collect();
+            Ok(command_placeholder("script", &command_line.join(" "), param("trim")))
+        }
+        "echo" => Ok(string_param("echo").unwrap_or_default().to_string()),
+        "form" => {
+            let Some(field) = field else {
+                return Err(format!("Form {} is referenced without a field", var.name));
+            };
+            let fields = param("fields").and_then(Value::as_mapping);
+            form_field_placeholder(field, fields.and_then(|fields| fields.get(field)))
+        }
+        other => Err(format!("Variable type {} ({}) is not supported", other, var.name)),
+    }
+}
+
+// espanso trims command output by default, as we do
+fn command_placeholder(name: &str, command_line: &str, trim: Option<&Value>) -> String {
+    match trim.and_then(Value::as_bool) {
+        Some(false) => format!("{{{{{}:{}:trim=false}}}}", name, quote_argument(command_line)),
+        _ => format!("{{{{{}:{}}}}}", name, quote_argument(command_line)),
+    }
+}
+
+fn translate_form_layout(layout: &str, fields: Option<&Mapping>, notes: &mut Vec<String>) -> String {
+    let translated = FORM_FIELD_REGEX.replace_all(layout, |caps: &Captures| {
+        let field = &caps[1];
+        match form_field_placeholder(field, fields.and_then(|fields| fields.get(field))) {
+            Ok(placeholder) => placeholder,
+            Err(note) => {
+                notes.push(note);
+                caps[0].to_string()
+            }
+        }
+    });
+
+    translated.replace(ESPANSO_CURSOR, "{{cursor}}")
+}
+
+fn form_field_placeholder(field: &str, spec: Option<&Value>) -> Result<String, String> {
+    let spec_value = |key: &str| spec.and_then(|spec| spec.get(key));
+    let default = spec_value("default").and_then(Value::as_str).unwrap_or_default();
+
+    match spec_value("type").and_then(Value::as_str) {
+        Some("choice") | Some("list") => {
+            let mut values: Vec<String> = spec_value("values")
+                .and_then(Value::as_sequence)
+                .map(|values| values.iter().filter_map(Value::as_str).map(str::to_string).collect())
+                .unwrap_or_default();
+            // Our select preselects the first option
+            if let Some(position) = values.iter().position(|value| value == default) {
+                let value = values.remove(position);
+                values.insert(0, value);
+            }
+            if values.is_empty() {
+                return Err(format!("Choice field {} has no values", field));
+            }
+            let values: Vec<String> = values.iter().map(|value| quote_argument(value)).collect();
+            Ok(format!("{{{{select:{}:{}}}}}", quote_argument(field), values.join(",")))
+        }
+        _ => Ok(format!("{{{{input:{}:{}}}}}", quote_argument(field), quote_argument(default))),
+    }
+}
+
+pub async fn export(db: &SqlitePool, user_id: i64) -> Result<EspansoExport, String> {
+    let snippets = sqlx::query!(
+        "SELECT name, shortcut, body, match_type, propagate_case FROM snippets WHERE user_id = ? ORDER BY id",
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut file = MatchFile::default();
+    let mut untranslated = Vec::new();
+
+    for snippet in snippets {
+        let match_type = snippet.match_type.as_deref().unwrap_or("exact");
+        let mut notes = Vec::new();
+        let (replace, vars) = export_body(&snippet.body, match_type == "regex", &mut notes);
+
+        let mut espanso_match = EspansoMatch {
+            label: Some(snippet.name.clone()),
+            replace: Some(replace),
+            vars,
+            word: matches!(match_type, "word" | "delimiter"),
+            propagate_case: snippet.propagate_case.unwrap_or(0) != 0,
+            ..EspansoMatch::default()
+        };
+        if match_type == "regex" {
+            espanso_match.regex = Some(snippet.shortcut.clone());
+        } else {
+            espanso_match.trigger = Some(snippet.shortcut.clone());
+        }
+        file.matches.push(espanso_match);
+
+        if !notes.is_empty() {
+            untranslated.push(ImportItem {
+                name: snippet.name,
+                shortcut: snippet.shortcut,
+                notes,
+            });
+        }
+    }
+
+    let yaml = serde_yaml::to_string(&file).map_err(|e| format!("Failed to write YAML: {}", e))?;
+    Ok(EspansoExport { yaml, untranslated })
+}
+
+// Turns our placeholders into espanso variables. Anything without an
+// equivalent is left in the text and noted.
+fn export_body(body: &str, is_regex: bool, notes: &mut Vec<String>) -> (String, Vec<EspansoVar>) {
+    let mut vars: Vec<EspansoVar> = Vec::new();
+    let mut form_fields = Mapping::new();
+    let mut form_layout = Vec::new();
+
+    let replace = PLACEHOLDER_REGEX
+        .replace_all(body, |caps: &Captures| {
+            let original = caps[0].to_string();
+            let inner = caps[1].trim();
+            let (name, args) = match inner.split_once(':') {
+                Some((name, args)) => (name.trim(), args),
+                None => (inner, ""),
+            };
+
+            match name {
+                "cursor" => ESPANSO_CURSOR.to_string(),
+                "clipboard" => {
+                    if !vars.iter().any(|var| var.name == "clipboard") {
+                        vars.push(new_var("clipboard", "clipboard", Mapping::new()));
+                    }
+                    "{{clipboard}}".to_string()
+                }
+                // Modifiers after `|` have no espanso equivalent
+                "date" | "time" if !args.contains('|') => {
+                    let var_name = format!("{}{}", name, vars.len() + 1);
+                    let mut params = Mapping::new();
+                    params.insert("format".into(), args.into());
+                    vars.push(new_var(&var_name, "date", params));
+                    format!("{{{{{}}}}}", var_name)
+                }
+                "env" => {
+                    let var_name = format!("env{}", vars.len() + 1);
+                    let mut params = Mapping::new();
+                    params.insert("cmd".into(), format!("echo ${}", args.trim()).into());
+                    vars.push(new_var(&var_name, "shell", params));
+                    format!("{{{{{}}}}}", var_name)
+                }
+                // Only plain command lines, without options or quoting
+                "shell" | "script" if !args.contains([':', '"', '\'']) => {
+                    let var_name = format!("{}{}", name, vars.len() + 1);
+                    let mut params = Mapping::new();
+                    if name == "shell" {
+                        params.insert("cmd".into(), args.trim().into());
+                    } else {
+                        let words: Vec<Value> = args.split_whitespace().map(Value::from).collect();
+                        params.insert("args".into(), Value::Sequence(words));
+                    }
+                    vars.push(new_var(&var_name, name, params));
+                    format!("{{{{{}}}}}", var_name)
+                }
+                "capture" if is_regex => format!("{{{{{}}}}}", args.trim()),
+                "input" | "select" => {
+                    let (field, rest) = args.split_once(':').unwrap_or((args, ""));
+                    let mut spec = Mapping::new();
+                    if name == "select" {
+                        let values: Vec<Value> = rest.split(',').map(|value| value.trim().into()).collect();
+                        spec.insert("type".into(), "choice".into());
+                        spec.insert("values".into(), Value::Sequence(values));
+                    } else if !rest.is_empty() {
+                        spec.insert("default".into(), rest.into());
+                    }
+                    form_fields.insert(field.into(), Value::Mapping(spec));
+                    form_layout.push(format!("{}: [[{}]]", field, field));
+                    format!("{{{{form1.{}}}}}", field)
+                }
+                _ => {
+                    notes.push(format!("No espanso equivalent for {}", original));
+                    original
+                }
+            }
+        })
+        .to_string();
+
+    if !form_fields.is_empty() {
+        let mut params = Mapping::new();
+        params.insert("layout".into(), form_layout.join("\n").into());
+        params.insert("fields".into(), Value::Mapping(form_fields));
+        vars.push(new_var("form1", "form", params));
+    }
+
+    (replace, vars)
+}
+
+fn new_var(name: &str, var_type: &str, params: Mapping) -> EspansoVar {
+    EspansoVar {
+        name: name.to_string(),
+        var_type: var_type.to_string(),
+        params,
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Serialize;
+use sqlx::types::Json;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::collections::HashMap;
+
+use crate::database::VariableDefinition;
+
+pub mod backup;
+pub mod csv;
+pub mod espanso;
+pub mod textblaze;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    // Nothing was written; the report shows what an import would do
+    pub dry_run: bool,
+    pub imported: Vec<ImportItem>,
+    pub skipped: Vec<ImportItem>,
+    pub conflicts: Vec<ImportItem>,
+    pub folders_created: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportItem {
+    pub name: String,
+    pub shortcut: String,
+    // Why the item was skipped or conflicted, or what could not be translated
+    pub notes: Vec<String>,
+}
+
+pub struct NewSnippet {
+    pub name: String,
+    pub shortcut: String,
+    pub body: String,
+    pub folder_id: Option<i64>,
+    pub match_type: String,
+    pub propagate_case: bool,
+    pub is_active: bool,
+    pub app_include: Json<Vec<String>>,
+    pub app_exclude: Json<Vec<String>>,
+    pub tags: Json<Vec<String>>,
+}
+
+impl NewSnippet {
+    pub fn new(name: String, shortcut: String, body: String, folder_id: Option<i64>) -> Self {
+        NewSnippet {
+            name,
+            shortcut,
+            body,
+            folder_id,
+            match_type: "exact".to_string(),
+            propagate_case: false,
+            is_active: true,
+            app_include: Json(vec![]),
+            app_exclude: Json(vec![]),
+            tags: Json(vec![]),
+        }
+    }
+
+    fn item(&self, notes: Vec<String>) -> ImportItem {
+        ImportItem {
+            name: self.name.clone(),
+            shortcut: self.shortcut.clone(),
+            notes,
+        }
+    }
+}
+
+impl ImportReport {
+    pub fn skip(&mut self, name: &str, shortcut: &str, reason: &str) {
+        self.skipped.push(ImportItem {
+            name: name.to_string(),
+            shortcut: shortcut.to_string(),
+            notes: vec![reason.to_string()],
+        });
+    }
+}
+
+// Reuses a folder with the same name under the same parent
+pub async fn find_or_create_folder(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    name: &str,
+    parent_id: Option<i64>,
+    report: &mut ImportReport,
+) -> Result<i64, sqlx::Error> {
+    let existing = sqlx::query_scalar!(
+        "SELECT id FROM folders WHERE user_id = ? AND name = ? AND parent_id IS ?",
+        user_id,
+        name,
+        parent_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+
+    let result = sqlx::query!(
+        "INSERT INTO folders (user_id, name, parent_id) VALUES (?, ?, ?)",
+        user_id,
+        name,
+        parent_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    report.folders_created += 1;
+    Ok(result.last_insert_rowid())
+}
+
+// Creates each level of a slash-separated path such as "Work/Support"
+pub async fn find_or_create_folder_path(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    path: &str,
+    report: &mut ImportReport,
+) -> Result<Option<i64>, sqlx::Error> {
+    let mut parent_id = None;
+    for name in path.split('/').map(str::trim).filter(|name| !name.is_empty()) {
+        parent_id = Some(find_or_create_folder(tx, user_id, name, parent_id, report).await?);
+    }
+    Ok(parent_id)
+}
+
+// Slash-separated path of every folder the user owns, keyed by folder id
+pub async fn folder_paths(db: &SqlitePool, user_id: i64) -> Result<HashMap<i64, String>, sqlx::Error> {
+    let folders = sqlx::query!(
+        "SELECT id, name, parent_id FROM folders WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    let by_id: HashMap<i64, (String, Option<i64>)> = folders
+        .into_iter()
+        .map(|folder| (folder.id, (folder.name, folder.parent_id)))
+        .collect();
+
+    let mut paths = HashMap::new();
+    for &id in by_id.keys() {
+        let mut names = Vec::new();
+        let mut current = Some(id);
+        // Bounded walk so a parent cycle cannot loop forever
+        while let Some(folder_id) = current.filter(|_| names.len() <= by_id.len()) {
+            let Some((name, parent_id)) = by_id.get(&folder_id) else {
+                break;
+            };
+            names.push(name.as_str());
+            current = *parent_id;
+        }
+        names.reverse();
+        paths.insert(id, names.join("/"));
+    }
+
+    Ok(paths)
+}
+
+// Id of the user's snippet with the same shortcut and app scope, which the
+// imported snippet would collide with
+pub async fn find_conflict(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    snippet: &NewSnippet,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT id FROM snippets WHERE user_id = ? AND shortcut = ? AND app_include = ? AND app_exclude = ?",
+        user_id,
+        snippet.shortcut,
+        snippet.app_include,
+        snippet.app_exclude
+    )
+    .fetch_optional(&mut **tx)
+    .await
+}
+
+// Inserts the snippet unless its shortcut is missing or already taken, and
+// records the outcome in the report
+pub async fn insert_snippet(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    snippet: NewSnippet,
+    mut notes: Vec<String>,
+    report: &mut ImportReport,
+) -> Result<Option<i64>, sqlx::Error> {
+    if snippet.shortcut.trim().is_empty() {
+        report.skip(&snippet.name, &snippet.shortcut, "Missing shortcut");
+        return Ok(None);
+    }
+
+    if let Err(e) = crate::engine::validate_trigger(&snippet.shortcut, &snippet.match_type) {
+        report.skipped.push(snippet.item(vec![e]));
+        return Ok(None);
+    }
+
+    if let Some(existing_id) = find_conflict(tx, user_id, &snippet).await? {
+        report.conflicts.push(snippet.item(vec![format!(
+            "Shortcut already used by snippet {}",
+            existing_id
+        )]));
+        return Ok(None);
+    }
+
+    // Imported bodies may hold placeholders we cannot render; keep them so
+    // the user can fix them, but say so
+    if let Err(e) = crate::vars::validate(&snippet.body) {
+        notes.push(format!("Body will not expand until fixed: {}", e));
+    }
+    notes.extend(untrusted_note(&snippet.body));
+
+    let now = chrono::Utc::now();
+    let result = sqlx::query!(
+        "INSERT INTO snippets (user_id, folder_id, name, shortcut, body, created_at, updated_at, is_active, match_type, propagate_case, app_include, app_exclude, tags) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        user_id,
+        snippet.folder_id,
+        snippet.name,
+        snippet.shortcut,
+        snippet.body,
+        now,
+        now,
+        snippet.is_active,
+        snippet.match_type,
+        snippet.propagate_case,
+        snippet.app_include,
+        snippet.app_exclude,
+        snippet.tags
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    report.imported.push(snippet.item(notes));
+    Ok(Some(result.last_insert_rowid()))
+}
+
+// Imported snippets start untrusted, so any commands in them fail until the
+// user approves the snippet
+pub fn untrusted_note(body: &str) -> Option<String> {
+    crate::vars::runs_commands(body)
+        .then(|| "Runs commands, which are blocked until the snippet is marked as trusted".to_string())
+}
+
+pub async fn insert_variable(
+    tx: &mut Transaction<'_, Sqlite>,
+    snippet_id: i64,
+    variable: &VariableDefinition,
+) -> Result<(), sqlx::Error> {
+    let options = Json(&variable.options);
+    sqlx::query!(
+        "INSERT INTO variables (snippet_id, key, type, default_value, options, validation) VALUES (?, ?, ?, ?, ?, ?)",
+        snippet_id,
+        variable.key,
+        variable.var_type,
+        variable.default_value,
+        options,
+        variable.validation
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
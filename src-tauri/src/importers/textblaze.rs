@@ -0,0 +1,444 @@
+use regex::Regex;
+use serde_json::Value;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use super::{find_or_create_folder, insert_snippet, ImportReport, NewSnippet};
+use crate::template::quote_argument;
+
+lazy_static::lazy_static! {
+    static ref LINE_BREAK_REGEX: Regex = Regex::new(r"(?i)<br\s*/?>|</(p|div|li|h[1-6])>").unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
+
+// Moment.js tokens used by Text Blaze's {time} command with an exact
+// strftime equivalent
+const MOMENT_TOKENS: &[(&str, &str)] = &[
+    ("YYYY", "%Y"),
+    ("YY", "%y"),
+    ("MMMM", "%B"),
+    ("MMM", "%b"),
+    ("MM", "%m"),
+    ("M", "%-m"),
+    ("DDDD", "%j"),
+    ("DDD", "%-j"),
+    ("DD", "%d"),
+    ("D", "%-d"),
+    ("dddd", "%A"),
+    ("ddd", "%a"),
+    ("d", "%w"),
+    ("E", "%u"),
+    ("WW", "%V"),
+    ("W", "%-V"),
+    ("HH", "%H"),
+    ("H", "%-H"),
+    ("hh", "%I"),
+    ("h", "%-I"),
+    ("mm", "%M"),
+    ("m", "%-M"),
+    ("ss", "%S"),
+    ("s", "%-S"),
+    ("A", "%p"),
+    ("a", "%P"),
+    ("X", "%s"),
+    ("ZZ", "%z"),
+    ("Z", "%:z"),
+    ("LTS", "%-I:%M:%S %p"),
+    ("LT", "%-I:%M %p"),
+    ("LLLL", "%A, %B %-d, %Y %-I:%M %p"),
+    ("LLL", "%B %-d, %Y %-I:%M %p"),
+    ("LL", "%B %-d, %Y"),
+    ("L", "%m/%d/%Y"),
+];
+
+// Tokens strftime can only come close to, with what the import report says
+// about them
+const APPROXIMATED_MOMENT_TOKENS: &[(&str, &str, &str)] = &[
+    ("Do", "%-d", "shows 4 instead of 4th"),
+    ("Mo", "%-m", "shows 9 instead of 9th"),
+    ("DDDo", "%-j", "shows 32 instead of 32nd"),
+    ("Wo", "%-V", "shows 5 instead of 5th"),
+    ("dd", "%a", "shows Mon instead of Mo"),
+    ("ww", "%U", "counts weeks from the first Sunday of the year"),
+    ("w", "%-U", "counts weeks from the first Sunday of the year"),
+    ("kk", "%H", "shows 00 instead of 24 at midnight"),
+    ("k", "%-H", "shows 0 instead of 24 at midnight"),
+];
+
+// Tokens with nothing close in strftime; they stay in the format as text
+const UNSUPPORTED_MOMENT_TOKENS: &[&str] = &["Qo", "Q", "x"];
+
+enum MomentToken {
+    Exact(&'static str),
+    Approximated(&'static str, &'static str),
+    Unsupported,
+}
+
+// Text Blaze exports a tree of folders holding snippets. Accepts either
+// `{"folders": [...]}`, a bare array of folders, or a single folder object.
+pub async fn import(db: &SqlitePool, user_id: i64, json_data: &str) -> Result<ImportReport, String> {
+    let root: Value = serde_json::from_str(json_data)
+        .map_err(|e| format!("Invalid Text Blaze JSON: {}", e))?;
+
+    let folders: Vec<&Value> = match &root {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(fields) => match fields.get("folders") {
+            Some(Value::Array(items)) => items.iter().collect(),
+            _ => vec![&root],
+        },
+        _ => return Err("Text Blaze export must be a JSON object or array".to_string()),
+    };
+
+    let mut report = ImportReport::default();
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    // Walk the folder tree without recursion, parents before children
+    let mut pending: Vec<(&Value, Option<i64>)> = folders.into_iter().rev().map(|folder| (folder, None)).collect();
+    while let Some((folder, parent_id)) = pending.pop() {
+        let folder_id = import_folder(&mut tx, user_id, folder, parent_id, &mut report)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+
+        if let Some(Value::Array(children)) = folder.get("folders") {
+            pending.extend(children.iter().rev().map(|child| (child, folder_id)));
+        }
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(report)
+}
+
+// Imports the folder's own snippets and returns the id its subfolders go in
+async fn import_folder(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    folder: &Value,
+    parent_id: Option<i64>,
+    report: &mut ImportReport,
+) -> Result<Option<i64>, sqlx::Error> {
+    // Snippets listed at the top level of an export have no folder
+    let folder_id = match string_field(folder, &["name", "title"]) {
+        Some(name) if folder.get("snippets").is_some() || folder.get("folders").is_some() => {
+            Some(find_or_create_folder(tx, user_id, &name, parent_id, report).await?)
+        }
+        _ => parent_id,
+    };
+
+    if let Some(Value::Array(snippets)) = folder.get("snippets") {
+        for snippet in snippets {
+            import_snippet(tx, user_id, snippet, folder_id, report).await?;
+        }
+    }
+
+    Ok(folder_id)
+}
+
+async fn import_snippet(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: i64,
+    snippet: &Value,
+    folder_id: Option<i64>,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    let name = string_field(snippet, &["name", "title"]).unwrap_or_default();
+    let shortcut = string_field(snippet, &["shortcut"]).unwrap_or_default();
+
+    let body = match string_field(snippet, &["text", "content", "plain"]) {
+        Some(text) => text,
+        None => match string_field(snippet, &["html"]) {
+            Some(html) => html_to_text(&html),
+            None => {
+                report.skip(&name, &shortcut, "Snippet has no text or HTML body");
+                return Ok(());
+            }
+        },
+    };
+
+    let (body, notes) = translate_commands(&body);
+    let name = if name.is_empty() { shortcut.clone() } else { name };
+
+    insert_snippet(tx, user_id, NewSnippet::new(name, shortcut, body, folder_id), notes, report).await?;
+    Ok(())
+}
+
+fn string_field(value: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| value.get(*key).and_then(Value::as_str))
+        .map(|text| text.to_string())
+}
+
+fn html_to_text(html: &str) -> String {
+    let text = LINE_BREAK_REGEX.replace_all(html, "\n");
+    let text = TAG_REGEX.replace_all(&text, "");
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.trim_end_matches('\n').to_string()
+}
+
+// Rewrites Text Blaze `{command: args}` blocks into our `{{...}}` placeholders.
+// Returns notes for anything left untranslated.
+pub fn translate_commands(body: &str) -> (String, Vec<String>) {
+    let mut output = String::with_capacity(body.len());
+    let mut notes = Vec::new();
+    let mut field_count = 0;
+    let mut rest = body;
+
+    while let Some(open) = rest.find('{') {
+        output.push_str(&escape_braces(&rest[..open]));
+        let Some(len) = command_len(&rest[open..]) else {
+            output.push_str(&escape_braces(&rest[open..]));
+            rest = "";
+            break;
+        };
+
+        let command = &rest[open..open + len];
+        match translate_command(&command[1..len - 1], &mut field_count, &mut notes) {
+            Ok(placeholder) => output.push_str(&placeholder),
+            Err(note) => {
+                output.push_str(&escape_braces(command));
+                notes.push(note);
+            }
+        }
+        rest = &rest[open + len..];
+    }
+    output.push_str(&escape_braces(rest));
+
+    (output, notes)
+}
+
+// Text Blaze text may contain `{{` or `}}`, which would otherwise start or
+// end one of our placeholders
+fn escape_braces(text: &str) -> String {
+    text.replace("{{", "\\{{").replace("}}", "\\}}")
+}
+
+// Length of the balanced `{...}` block at the start of `text`
+fn command_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, character) in text.char_indices() {
+        match character {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Err is a note for a command left untranslated; notes about a translation
+// that is only close go in `notes`
+fn translate_command(command: &str, field_count: &mut usize, notes: &mut Vec<String>) -> Result<String, String> {
+    let (name, args) = match command.split_once(':') {
+        Some((name, args)) => (name.trim().to_lowercase(), args.trim()),
+        None => (command.trim().to_lowercase(), ""),
+    };
+
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    for arg in args.split(';').map(str::trim).filter(|arg| !arg.is_empty()) {
+        match arg.split_once('=') {
+            Some((key, value)) => named.push((key.trim().to_lowercase(), value.trim().to_string())),
+            None => positional.push(arg.to_string()),
+        }
+    }
+    let named_arg = |key: &str| {
+        named
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+    };
+
+    match name.as_str() {
+        "clipboard" => Ok("{{clipboard}}".to_string()),
+        "cursor" => Ok("{{cursor}}".to_string()),
+        "time" => {
+            let format = positional.first().map(String::as_str).unwrap_or("LT");
+            let (mut spec, token_notes) = moment_to_strftime(format);
+            notes.extend(token_notes.into_iter().map(|note| format!("{}: {{{}}}", note, command)));
+            if let Some(shift) = named_arg("shift") {
+                let Some(modifier) = shift_modifier(&shift) else {
+                    return Err(format!("Time shift {} is not supported: {{{}}}", shift, command));
+                };
+                spec.push('|');
+                spec.push_str(&modifier);
+            }
+            Ok(format!("{{{{time:{}}}}}", quote_argument(&spec)))
+        }
+        "formtext" | "formparagraph" => {
+            *field_count += 1;
+            let label = named_arg("name").unwrap_or_else(|| format!("field{}", field_count));
+            let default = named_arg("default").unwrap_or_default();
+            Ok(format!("{{{{input:{}:{}}}}}", quote_argument(&label), quote_argument(&default)))
+        }
+        "formmenu" => {
+            *field_count += 1;
+            let label = named_arg("name").unwrap_or_else(|| format!("field{}", field_count));
+            // The default option goes first so it is preselected
+            let mut options = positional.clone();
+            if let Some(default) = named_arg("default") {
+                options.retain(|option| *option != default);
+                options.insert(0, default);
+            }
+            if options.is_empty() {
+                return Err(format!("Menu has no options: {{{}}}", command));
+            }
+            let options: Vec<String> = options.iter().map(|option| quote_argument(option)).collect();
+            Ok(format!("{{{{select:{}:{}}}}}", quote_argument(&label), options.join(",")))
+        }
+        _ => Err(format!("Unsupported Text Blaze command: {{{}}}", command)),
+    }
+}
+
+// Text Blaze shifts such as `+3D` or `-1M` as date modifiers
+fn shift_modifier(shift: &str) -> Option<String> {
+    let shift = shift.trim();
+    let (amount, unit) = shift.split_at(shift.find(|c: char| c.is_ascii_alphabetic())?);
+    let amount = amount.trim();
+    if !amount.starts_with(['+', '-']) || amount[1..].parse::<u32>().is_err() {
+        return None;
+    }
+    let unit = match unit {
+        "Y" | "y" => "y",
+        "M" => "mo",
+        "W" | "w" => "w",
+        "D" | "d" => "d",
+        "h" | "H" => "h",
+        "m" => "min",
+        "s" => "s",
+        _ => return None,
+    };
+    Some(format!("{}{}", amount, unit))
+}
+
+// Also returns a note for every token that could not be translated exactly
+fn moment_to_strftime(format: &str) -> (String, Vec<String>) {
+    let mut output = String::new();
+    let mut notes = Vec::new();
+    let mut rest = format;
+
+    while !rest.is_empty() {
+        // Text in square brackets is literal in moment.js
+        if let Some(stripped) = rest.strip_prefix('[') {
+            if let Some(end) = stripped.find(']') {
+                output.push_str(&stripped[..end].replace('%', "%%"));
+                rest = &stripped[end + 1..];
+                continue;
+            }
+        }
+
+        if let Some((token, translation)) = moment_token(rest) {
+            let note = match translation {
+                MomentToken::Exact(replacement) => {
+                    output.push_str(replacement);
+                    None
+                }
+                MomentToken::Approximated(replacement, difference) => {
+                    output.push_str(replacement);
+                    Some(format!("Time token {} {}", token, difference))
+                }
+                MomentToken::Unsupported => {
+                    output.push_str(token);
+                    Some(format!("Time token {} is not supported and was kept as text", token))
+                }
+            };
+            if let Some(note) = note {
+                if !notes.contains(&note) {
+                    notes.push(note);
+                }
+            }
+            rest = &rest[token.len()..];
+            continue;
+        }
+
+        let character = rest.chars().next().unwrap();
+        if character == '%' {
+            output.push_str("%%");
+        } else {
+            output.push(character);
+        }
+        rest = &rest[character.len_utf8()..];
+    }
+
+    (output, notes)
+}
+
+// The longest token `text` starts with, as moment.js reads them
+fn moment_token(text: &str) -> Option<(&'static str, MomentToken)> {
+    let exact = MOMENT_TOKENS
+        .iter()
+        .map(|(token, replacement)| (*token, MomentToken::Exact(replacement)));
+    let approximated = APPROXIMATED_MOMENT_TOKENS
+        .iter()
+        .map(|(token, replacement, difference)| (*token, MomentToken::Approximated(replacement, difference)));
+    let unsupported = UNSUPPORTED_MOMENT_TOKENS
+        .iter()
+        .map(|token| (*token, MomentToken::Unsupported));
+
+    exact
+        .chain(approximated)
+        .chain(unsupported)
+        .filter(|(token, _)| text.starts_with(token))
+        .max_by_key(|(token, _)| token.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_literal_double_braces() {
+        let (body, notes) = translate_commands("Use {{name}} and }} here {clipboard}");
+        assert_eq!(body, "Use \\{{name\\}} and \\}} here {{clipboard}}");
+        assert_eq!(notes, ["Unsupported Text Blaze command: {{name}}"]);
+
+        let nodes = crate::template::parse(&body).unwrap();
+        assert!(matches!(&nodes[0], crate::template::Node::Text(text) if text == "Use {{name}} and }} here "));
+
+        let (body, _) = translate_commands("unclosed {{ brace");
+        assert_eq!(body, "unclosed \\{{ brace");
+    }
+
+    #[test]
+    fn translates_moment_tokens() {
+        let translate = |format: &str| moment_to_strftime(format).0;
+        assert_eq!(translate("YYYY-MM-DD HH:mm:ss"), "%Y-%m-%d %H:%M:%S");
+        assert_eq!(translate("dddd, MMMM D"), "%A, %B %-d");
+        assert_eq!(translate("ddd d E"), "%a %w %u");
+        assert_eq!(translate("DDDD [day] 100%"), "%j day 100%%");
+        assert_eq!(translate("LLLL"), "%A, %B %-d, %Y %-I:%M %p");
+        assert!(moment_to_strftime("YYYY-MM-DD dddd d").1.is_empty());
+    }
+
+    #[test]
+    fn notes_approximated_and_unsupported_tokens() {
+        let (spec, notes) = moment_to_strftime("MMMM Do, dd [Q]Q Do");
+        assert_eq!(spec, "%B %-d, %a QQ %-d");
+        assert_eq!(
+            notes,
+            [
+                "Time token Do shows 4 instead of 4th",
+                "Time token dd shows Mon instead of Mo",
+                "Time token Q is not supported and was kept as text",
+            ]
+        );
+
+        let (body, notes) = translate_commands("Due {time: MMMM Do}");
+        assert_eq!(body, "Due {{time:%B %-d}}");
+        assert_eq!(notes, ["Time token Do shows 4 instead of 4th: {time: MMMM Do}"]);
+    }
+}
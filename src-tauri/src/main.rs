@@ -1,7 +1,19 @@
+mod commands;
+mod counters;
 mod database;
+mod dates;
 mod expander;
 mod engine;
+mod forms;
+mod generate;
+mod hotkey;
+mod importers;
+mod matcher;
+mod migrations;
+mod template;
+mod search;
 mod vars;
+mod settings;
 mod api;
 
 use tauri::{
@@ -31,15 +43,33 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .setup(|app| {
             let handle = app.handle().clone();
+            forms::init(handle.clone());
+            hotkey::init(handle);
+            let tray_state = state.clone();
             
             // Initialize database
             tauri::async_runtime::spawn(async move {
-                if let Ok(db) = database::init_db().await {
-                    if let Ok(mut state_lock) = state.lock() {
-                        state_lock.db = Some(db);
+                // A failed migration leaves the database as it was
+                let db = match database::init_db().await {
+                    Ok(db) => db,
+                    Err(e) => {
+                        eprintln!("Failed to open database: {:?}", e);
+                        return;
                     }
+                };
+                match settings::load(&db).await {
+                    Ok(loaded) => settings::apply(loaded),
+                    Err(e) => eprintln!("Failed to load settings: {:?}", e),
+                }
+                if let Err(e) = engine::reload_snippets(&db).await {
+                    eprintln!("Failed to load snippets: {:?}", e);
+                }
+                counters::init(db.clone());
+                if let Ok(mut state_lock) = state.lock() {
+                    state_lock.db = Some(db);
                 }
             });
 
@@ -61,10 +91,10 @@ pub fn run() {
                         }
                     }
                     "pause" => {
-                        expander::stop();
+                        set_expand_enabled(&tray_state, false);
                     }
                     "resume" => {
-                        expander::start();
+                        set_expand_enabled(&tray_state, true);
                     }
                     "quit" => {
                         expander::stop();
@@ -85,10 +115,24 @@ pub fn run() {
             api::list_folders,
             api::create_folder,
             api::list_snippets,
+            api::search_snippets,
             api::create_snippet,
             api::update_snippet,
+            api::approve_snippet,
             api::delete_snippet,
+            api::list_variables,
+            api::create_variable,
+            api::update_variable,
+            api::delete_variable,
             api::import_textblaze,
+            api::import_csv,
+            api::export_csv,
+            api::import_espanso,
+            api::export_espanso,
+            api::export_backup,
+            api::import_backup,
+            api::get_snippet_form,
+            api::submit_snippet_form,
             api::get_settings,
             api::update_settings,
             api::reload_engine
@@ -97,6 +141,21 @@ pub fn run() {
         .expect("error while running tauri application");
 }
 
+// Pausing from the tray is remembered across restarts, like the setting it
+// flips in the settings window
+fn set_expand_enabled(state: &AppState, enabled: bool) {
+    settings::set_expand_enabled(enabled);
+
+    let Some(db) = state.lock().ok().and_then(|state| state.db.clone()) else {
+        return;
+    };
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = settings::save(&db, &settings::current()).await {
+            eprintln!("Failed to save settings: {}", e);
+        }
+    });
+}
+
 fn main() {
     run();
 }
\ No newline at end of file
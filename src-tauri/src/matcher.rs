@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub const ROOT: usize = 0;
+
+// Typed characters kept for matching, which caps how long a trigger can be
+pub const BUFFER_SIZE: usize = 100;
+
+// Longest literal trigger in characters, leaving room in the buffer for a
+// trigger prefix and the delimiter typed after it
+pub const MAX_TRIGGER_LEN: usize = BUFFER_SIZE - 2;
+
+// Aho-Corasick automaton over all literal triggers. The engine keeps one
+// state per keystroke and advances it with `next_state`, so matching never
+// rescans or copies the typed text.
+pub struct TriggerAutomaton {
+    nodes: Vec<Node>,
+    alphabet: HashSet<char>,
+}
+
+#[derive(Default)]
+struct Node {
+    children: HashMap<char, usize>,
+    fail: usize,
+    depth: usize,
+    // Value of the trigger ending exactly at this node
+    value: Option<usize>,
+    // Closest node on the fail chain that ends a trigger
+    output: Option<usize>,
+}
+
+impl TriggerAutomaton {
+    pub fn new() -> Self {
+        TriggerAutomaton {
+            nodes: vec![Node::default()],
+            alphabet: HashSet::new(),
+        }
+    }
+
+    pub fn build<'a, I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, usize)>,
+    {
+        let mut automaton = TriggerAutomaton::new();
+        for (key, value) in keys {
+            automaton.insert(key, value);
+        }
+        automaton.link();
+        automaton
+    }
+
+    fn insert(&mut self, key: &str, value: usize) {
+        let mut current = ROOT;
+        for character in key.chars() {
+            self.alphabet.insert(character);
+            current = match self.nodes[current].children.get(&character) {
+                Some(&next) => next,
+                None => {
+                    let depth = self.nodes[current].depth + 1;
+                    self.nodes.push(Node {
+                        depth,
+                        ..Node::default()
+                    });
+                    let next = self.nodes.len() - 1;
+                    self.nodes[current].children.insert(character, next);
+                    next
+                }
+            };
+        }
+        if current != ROOT {
+            self.nodes[current].value = Some(value);
+        }
+    }
+
+    // Breadth-first pass filling in fail and output links
+    fn link(&mut self) {
+        let mut queue: VecDeque<usize> = self.nodes[ROOT].children.values().copied().collect();
+
+        while let Some(current) = queue.pop_front() {
+            let children: Vec<(char, usize)> = self.nodes[current]
+                .children
+                .iter()
+                .map(|(&character, &child)| (character, child))
+                .collect();
+
+            for (character, child) in children {
+                let mut fallback = self.nodes[current].fail;
+                let fail = loop {
+                    match self.nodes[fallback].children.get(&character) {
+                        Some(&next) if next != child => break next,
+                        _ if fallback == ROOT => break ROOT,
+                        _ => fallback = self.nodes[fallback].fail,
+                    }
+                };
+
+                self.nodes[child].fail = fail;
+                self.nodes[child].output = if self.nodes[fail].value.is_some() {
+                    Some(fail)
+                } else {
+                    self.nodes[fail].output
+                };
+                queue.push_back(child);
+            }
+        }
+    }
+
+    pub fn next_state(&self, state: usize, character: char) -> usize {
+        // Characters no trigger uses can never continue a match
+        if !self.alphabet.contains(&character) {
+            return ROOT;
+        }
+
+        let mut current = state;
+        loop {
+            if let Some(&next) = self.nodes[current].children.get(&character) {
+                return next;
+            }
+            if current == ROOT {
+                return ROOT;
+            }
+            current = self.nodes[current].fail;
+        }
+    }
+
+    // Triggers ending at `state` as (length in chars, value), longest first
+    pub fn matches(&self, state: usize) -> Matches<'_> {
+        let next = if self.nodes[state].value.is_some() {
+            Some(state)
+        } else {
+            self.nodes[state].output
+        };
+
+        Matches {
+            automaton: self,
+            next,
+        }
+    }
+}
+
+impl Default for TriggerAutomaton {
+    fn default() -> Self {
+        TriggerAutomaton::new()
+    }
+}
+
+pub struct Matches<'a> {
+    automaton: &'a TriggerAutomaton,
+    next: Option<usize>,
+}
+
+impl Iterator for Matches<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.automaton.nodes[self.next?];
+        self.next = node.output;
+        node.value.map(|value| (node.depth, value))
+    }
+}
+
+// Recently typed characters alongside the automaton state reached after each
+// one, so backspace can step the matcher back without replaying the buffer
+pub struct TypingBuffer {
+    pub chars: VecDeque<char>,
+    states: VecDeque<usize>,
+    // Scratch text for regex triggers, reused between keystrokes
+    pub text: String,
+}
+
+impl TypingBuffer {
+    pub const fn new() -> Self {
+        TypingBuffer {
+            chars: VecDeque::new(),
+            states: VecDeque::new(),
+            text: String::new(),
+        }
+    }
+
+    // Triggers are stored case-folded, so the automaton sees typed text the
+    // same way; `chars` keeps the case as typed
+    pub fn push(&mut self, character: char, triggers: &TriggerAutomaton) {
+        if character == '\x08' { // Backspace
+            self.chars.pop_back();
+            self.states.pop_back();
+            return;
+        }
+
+        let state = triggers.next_state(self.state(0), fold_case(character));
+        self.chars.push_back(character);
+        self.states.push_back(state);
+        if self.chars.len() > BUFFER_SIZE {
+            self.chars.pop_front();
+            self.states.pop_front();
+        }
+    }
+
+    // Automaton state after all but the last `skip` characters
+    pub fn state(&self, skip: usize) -> usize {
+        self.states
+            .len()
+            .checked_sub(skip + 1)
+            .and_then(|i| self.states.get(i).copied())
+            .unwrap_or(ROOT)
+    }
+
+    pub fn clear(&mut self) {
+        self.chars.clear();
+        self.states.clear();
+    }
+}
+
+pub fn fold_case(character: char) -> char {
+    character.to_lowercase().next().unwrap_or(character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn automaton(keys: &[&str]) -> TriggerAutomaton {
+        TriggerAutomaton::build(keys.iter().enumerate().map(|(value, key)| (*key, value)))
+    }
+
+    // Triggers ending after each character of `text`, as (length, value)
+    fn matches_after(automaton: &TriggerAutomaton, text: &str) -> Vec<Vec<(usize, usize)>> {
+        let mut state = ROOT;
+        text.chars()
+            .map(|character| {
+                state = automaton.next_state(state, character);
+                automaton.matches(state).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_triggers_typed_anywhere() {
+        let automaton = automaton(&["/sig", "/addr"]);
+        let found = matches_after(&automaton, "see /sig");
+        assert_eq!(found.last().unwrap(), &[(4, 0)]);
+        assert!(found[..7].iter().all(Vec::is_empty));
+        assert!(automaton.matches(ROOT).next().is_none());
+    }
+
+    #[test]
+    fn follows_failure_links_into_other_triggers() {
+        // After "abc" the "x" continues no path from "abc", but "bc" is a
+        // prefix of "bcx"
+        let automaton = automaton(&["abcd", "bcx"]);
+        assert_eq!(matches_after(&automaton, "abcx").last().unwrap(), &[(3, 1)]);
+        assert_eq!(matches_after(&automaton, "abcd").last().unwrap(), &[(4, 0)]);
+    }
+
+    #[test]
+    fn reports_overlapping_triggers_longest_first() {
+        let automaton = automaton(&["btw", "tw", "w"]);
+        assert_eq!(matches_after(&automaton, "btw").last().unwrap(), &[(3, 0), (2, 1), (1, 2)]);
+        assert_eq!(matches_after(&automaton, "xtw").last().unwrap(), &[(2, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn output_links_skip_nodes_that_end_no_trigger() {
+        // "xab" fails to "ab", which only prefixes "abz"; the trigger "b"
+        // further down that chain is found through the output link
+        let automaton = automaton(&["xabc", "abz", "b"]);
+        assert_eq!(matches_after(&automaton, "xab").last().unwrap(), &[(1, 2)]);
+        assert_eq!(matches_after(&automaton, "xabz").last().unwrap(), &[(3, 1)]);
+    }
+
+    #[test]
+    fn unknown_characters_reset_to_the_root() {
+        let automaton = automaton(&["ab"]);
+        let state = automaton.next_state(ROOT, 'a');
+        assert_ne!(state, ROOT);
+        assert_eq!(automaton.next_state(state, '?'), ROOT);
+        assert!(matches_after(&automaton, "a?b").iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn buffer_folds_case_but_keeps_what_was_typed() {
+        let automaton = automaton(&["btw"]);
+        let mut buffer = TypingBuffer::new();
+        for character in "BtW".chars() {
+            buffer.push(character, &automaton);
+        }
+        assert_eq!(automaton.matches(buffer.state(0)).collect::<Vec<_>>(), [(3, 0)]);
+        assert_eq!(buffer.chars.iter().collect::<String>(), "BtW");
+        assert_eq!(fold_case('Ä'), 'ä');
+    }
+
+    #[test]
+    fn buffer_backspace_steps_the_automaton_back() {
+        let automaton = automaton(&["btw"]);
+        let mut buffer = TypingBuffer::new();
+        for character in "btx\x08w ".chars() {
+            buffer.push(character, &automaton);
+        }
+        assert_eq!(buffer.chars.iter().collect::<String>(), "btw ");
+        // A trailing delimiter is skipped to find the trigger before it
+        assert!(automaton.matches(buffer.state(0)).next().is_none());
+        assert_eq!(automaton.matches(buffer.state(1)).collect::<Vec<_>>(), [(3, 0)]);
+
+        buffer.clear();
+        assert_eq!(buffer.state(0), ROOT);
+    }
+
+    #[test]
+    fn buffer_keeps_only_the_latest_characters() {
+        let automaton = automaton(&["ab"]);
+        let mut buffer = TypingBuffer::new();
+        for _ in 0..BUFFER_SIZE {
+            buffer.push('x', &automaton);
+        }
+        buffer.push('a', &automaton);
+        buffer.push('b', &automaton);
+        assert_eq!(buffer.chars.len(), BUFFER_SIZE);
+        assert_eq!(automaton.matches(buffer.state(0)).collect::<Vec<_>>(), [(2, 0)]);
+    }
+
+    #[test]
+    fn matches_can_be_longer_than_the_buffer_keeps() {
+        let trigger = "x".repeat(BUFFER_SIZE);
+        let automaton = automaton(&[&trigger]);
+        let mut buffer = TypingBuffer::new();
+        for character in trigger.chars().chain([' ']) {
+            buffer.push(character, &automaton);
+        }
+        // The first character is gone, but the state still ends the trigger;
+        // the engine has to check the match fits before erasing it
+        assert_eq!(buffer.chars.len(), BUFFER_SIZE);
+        assert_eq!(automaton.matches(buffer.state(1)).collect::<Vec<_>>(), [(BUFFER_SIZE, 0)]);
+    }
+}
@@ -0,0 +1,505 @@
+use chrono::Utc;
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::path::Path;
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+}
+
+// Applied in order. A migration that has shipped is never edited; later
+// changes get a migration of their own.
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial", sql: include_str!("../migrations/0001_initial.sql") },
+    Migration { version: 2, name: "propagate_case", sql: include_str!("../migrations/0002_propagate_case.sql") },
+    Migration { version: 3, name: "settings", sql: include_str!("../migrations/0003_settings.sql") },
+    Migration { version: 4, name: "app_scopes", sql: include_str!("../migrations/0004_app_scopes.sql") },
+    Migration { version: 5, name: "trusted_snippets", sql: include_str!("../migrations/0005_trusted_snippets.sql") },
+    Migration { version: 6, name: "counters", sql: include_str!("../migrations/0006_counters.sql") },
+    Migration { version: 7, name: "variable_definitions", sql: include_str!("../migrations/0007_variable_definitions.sql") },
+    Migration { version: 8, name: "snippet_tags", sql: include_str!("../migrations/0008_snippet_tags.sql") },
+    Migration { version: 9, name: "snippet_search", sql: include_str!("../migrations/0009_snippet_search.sql") },
+    Migration { version: 10, name: "unique_variable_keys", sql: include_str!("../migrations/0010_unique_variable_keys.sql") },
+    Migration { version: 11, name: "user_scoped_shortcuts", sql: include_str!("../migrations/0011_user_scoped_shortcuts.sql") },
+];
+
+// Databases from before schema_version existed are recognised by the table,
+// or the column of a table, each version added. Newest first; every later
+// version was recorded in schema_version from the start.
+const LEGACY_MARKERS: &[(i64, &str, Option<&str>)] = &[
+    (7, "variables", Some("options")),
+    (6, "counters", None),
+    (5, "snippets", Some("trusted")),
+    (4, "snippets", Some("app_include")),
+    (3, "settings", None),
+    (2, "snippets", Some("propagate_case")),
+    (1, "snippets", None),
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+// Brings the database up to the latest schema in one transaction. When a
+// database file is given and it already holds data, it is first copied
+// next to itself, so a failed upgrade can always be undone by hand.
+pub async fn run(pool: &SqlitePool, db_file: Option<&Path>) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+
+    let recorded = if table_exists(&mut conn, "schema_version").await? {
+        sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_version")
+            .fetch_one(&mut *conn)
+            .await?
+    } else {
+        None
+    };
+    let current = match recorded {
+        Some(version) => version,
+        None => legacy_version(&mut conn).await?,
+    };
+
+    let latest = latest_version();
+    if current > latest {
+        return Err(sqlx::Error::Configuration(
+            format!("database schema version {} is newer than this app supports ({})", current, latest).into(),
+        ));
+    }
+    if recorded == Some(latest) {
+        return Ok(());
+    }
+
+    if current > 0 && current < latest {
+        if let Some(db_file) = db_file {
+            backup(&mut conn, db_file, current).await?;
+        }
+    }
+
+    // Rebuilding a table drops it while other tables still refer to it.
+    // Foreign keys can only be switched off outside a transaction.
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+    let result = apply(&mut conn, current, recorded.is_none()).await;
+    sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await?;
+    result
+}
+
+async fn apply(conn: &mut SqliteConnection, current: i64, record_existing: bool) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for migration in MIGRATIONS {
+        let pending = migration.version > current;
+        if pending {
+            sqlx::query(migration.sql).execute(&mut *tx).await?;
+        }
+        // A database from before schema_version gets the versions it
+        // already had recorded too
+        if pending || record_existing {
+            sqlx::query("INSERT INTO schema_version (version, name) VALUES (?, ?)")
+                .bind(migration.version)
+                .bind(migration.name)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+
+    tx.commit().await
+}
+
+// Version of a database without a schema_version table; 0 when it is empty
+async fn legacy_version(conn: &mut SqliteConnection) -> Result<i64, sqlx::Error> {
+    for &(version, table, column) in LEGACY_MARKERS {
+        let found = match column {
+            Some(column) => column_exists(conn, table, column).await?,
+            None => table_exists(conn, table).await?,
+        };
+        if found {
+            return Ok(version);
+        }
+    }
+    Ok(0)
+}
+
+async fn table_exists(conn: &mut SqliteConnection, table: &str) -> Result<bool, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(table)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(count > 0)
+}
+
+async fn column_exists(conn: &mut SqliteConnection, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(count > 0)
+}
+
+// VACUUM INTO writes a consistent copy even while other connections are
+// open, e.g. data.db.v4-20240101120000.bak
+async fn backup(conn: &mut SqliteConnection, db_file: &Path, version: i64) -> Result<(), sqlx::Error> {
+    let mut backup_file = db_file.as_os_str().to_owned();
+    backup_file.push(format!(".v{}-{}.bak", version, Utc::now().format("%Y%m%d%H%M%S")));
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(backup_file.to_string_lossy().into_owned())
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    // A database as each version of the app left it, with data in every table
+    const FIXTURES: &[(i64, &str)] = &[
+        (1, include_str!("../tests/fixtures/schema_v1.sql")),
+        (2, include_str!("../tests/fixtures/schema_v2.sql")),
+        (3, include_str!("../tests/fixtures/schema_v3.sql")),
+        (4, include_str!("../tests/fixtures/schema_v4.sql")),
+        (5, include_str!("../tests/fixtures/schema_v5.sql")),
+        (6, include_str!("../tests/fixtures/schema_v6.sql")),
+        (7, include_str!("../tests/fixtures/schema_v7.sql")),
+    ];
+
+    // One connection, since every connection to :memory: is its own database
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn tables(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != 'schema_version' ORDER BY name",
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    // Columns, foreign keys and unique indexes of every table, described
+    // without names SQLite makes up, so schemas reached by different
+    // routes compare equal
+    async fn schema(pool: &SqlitePool) -> Vec<String> {
+        let mut schema = Vec::new();
+        for table in tables(pool).await {
+            let columns: Vec<(String, String, i64, Option<String>, i64)> = sqlx::query_as(
+                r#"SELECT name, type, "notnull", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid"#,
+            )
+            .bind(&table)
+            .fetch_all(pool)
+            .await
+            .unwrap();
+            schema.extend(columns.into_iter().map(|column| format!("{} column {:?}", table, column)));
+
+            let foreign_keys: Vec<(String, String, String)> = sqlx::query_as(
+                r#"SELECT "from", "table", "to" FROM pragma_foreign_key_list(?) ORDER BY "from""#,
+            )
+            .bind(&table)
+            .fetch_all(pool)
+            .await
+            .unwrap();
+            schema.extend(foreign_keys.into_iter().map(|key| format!("{} foreign key {:?}", table, key)));
+
+            let mut unique: Vec<String> = sqlx::query_scalar(
+                r#"SELECT (SELECT group_concat(name) FROM pragma_index_info(list.name)) FROM pragma_index_list(?) AS list WHERE list."unique" = 1"#,
+            )
+            .bind(&table)
+            .fetch_all(pool)
+            .await
+            .unwrap();
+            unique.sort();
+            schema.extend(unique.into_iter().map(|columns| format!("{} unique ({})", table, columns)));
+        }
+        schema
+    }
+
+    async fn row_counts(pool: &SqlitePool) -> Vec<(String, i64)> {
+        let mut counts = Vec::new();
+        for table in tables(pool).await {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(pool)
+                .await
+                .unwrap();
+            counts.push((table, count));
+        }
+        counts
+    }
+
+    async fn recorded_versions(pool: &SqlitePool) -> Vec<i64> {
+        sqlx::query_scalar("SELECT version FROM schema_version ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn all_versions() -> Vec<i64> {
+        MIGRATIONS.iter().map(|migration| migration.version).collect()
+    }
+
+    async fn fixture_pool(sql: &str) -> SqlitePool {
+        let pool = memory_pool().await;
+        sqlx::query(sql).execute(&pool).await.unwrap();
+        pool
+    }
+
+    #[test]
+    fn migrations_are_numbered_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i64 + 1, "{}", migration.name);
+        }
+        assert_eq!(FIXTURES.len(), LEGACY_MARKERS.len());
+    }
+
+    #[tokio::test]
+    async fn creates_the_latest_schema_in_an_empty_database() {
+        let pool = memory_pool().await;
+        run(&pool, None).await.unwrap();
+
+        assert_eq!(recorded_versions(&pool).await, all_versions());
+        assert_eq!(
+            tables(&pool).await,
+            [
+                "counters",
+                "folders",
+                "settings",
+                "snippets",
+                "snippets_fts",
+                "snippets_fts_config",
+                "snippets_fts_data",
+                "snippets_fts_docsize",
+                "snippets_fts_idx",
+                "users",
+                "variables",
+            ]
+        );
+
+        // Running again changes nothing
+        run(&pool, None).await.unwrap();
+        assert_eq!(recorded_versions(&pool).await, all_versions());
+    }
+
+    #[tokio::test]
+    async fn recognises_every_historic_version() {
+        let pool = memory_pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(legacy_version(&mut conn).await.unwrap(), 0);
+        drop(conn);
+
+        for &(version, sql) in FIXTURES {
+            let pool = fixture_pool(sql).await;
+            let mut conn = pool.acquire().await.unwrap();
+            assert_eq!(legacy_version(&mut conn).await.unwrap(), version);
+        }
+    }
+
+    #[tokio::test]
+    async fn upgrades_every_historic_version() {
+        let fresh = memory_pool().await;
+        run(&fresh, None).await.unwrap();
+        let expected = schema(&fresh).await;
+
+        for &(version, sql) in FIXTURES {
+            let pool = fixture_pool(sql).await;
+            let counts = row_counts(&pool).await;
+
+            run(&pool, None).await.unwrap();
+
+            assert_eq!(schema(&pool).await, expected, "upgrading from version {}", version);
+            assert_eq!(recorded_versions(&pool).await, all_versions(), "upgrading from version {}", version);
+            for (table, count) in counts {
+                let after: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                assert_eq!(after, count, "rows of {} upgrading from version {}", table, version);
+            }
+
+            let (shortcut, body, folder_id, trusted): (String, String, Option<i64>, bool) =
+                sqlx::query_as("SELECT shortcut, body, folder_id, trusted FROM snippets WHERE id = 1")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(shortcut, "/sig");
+            assert_eq!(body, "Best,\n{{input:name}}");
+            assert_eq!(folder_id, Some(1));
+            // Only snippets approved after commands existed are trusted
+            assert_eq!(trusted, version >= 5);
+
+            let (key, default_value, options): (String, Option<String>, String) =
+                sqlx::query_as("SELECT key, default_value, options FROM variables WHERE snippet_id = 1")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(key, "name");
+            assert_eq!(default_value.as_deref(), Some("Ann"));
+            assert_eq!(options, if version >= 7 { r#"["Ann","Bob"]"# } else { "[]" });
+
+            let (app_include, propagate_case): (String, bool) =
+                sqlx::query_as("SELECT app_include, propagate_case FROM snippets WHERE id = 2")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(app_include, "[]");
+            assert_eq!(propagate_case, version >= 2);
+
+            // Existing snippets are searchable straight away
+            let found: Vec<i64> = sqlx::query_scalar(
+                "SELECT rowid FROM snippets_fts WHERE snippets_fts MATCH 'best' ORDER BY rowid",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+            assert_eq!(found, [1]);
+
+            let foreign_keys_on: bool = sqlx::query_scalar("PRAGMA foreign_keys").fetch_one(&pool).await.unwrap();
+            assert!(foreign_keys_on);
+        }
+    }
+
+    #[tokio::test]
+    async fn upgrade_allows_shortcuts_shared_between_app_scopes_and_users() {
+        let pool = fixture_pool(FIXTURES[0].1).await;
+        run(&pool, None).await.unwrap();
+
+        sqlx::query("INSERT INTO snippets (user_id, name, shortcut, body, app_include) VALUES (1, 'Mail', '/sig', 'Hi', '[\"mail\"]')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (id, email, password_hash) VALUES (2, 'bo@example.com', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO snippets (user_id, name, shortcut, body) VALUES (2, 'Theirs', '/sig', 'Hi')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let duplicate = sqlx::query("INSERT INTO snippets (user_id, name, shortcut, body) VALUES (1, 'Again', '/sig', 'Hi')")
+            .execute(&pool)
+            .await;
+        assert!(duplicate.is_err());
+
+        // The rebuilt table is still kept in the search index
+        sqlx::query("UPDATE snippets SET body = 'Cheers' WHERE name = 'Theirs'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let found: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM snippets WHERE id IN (SELECT rowid FROM snippets_fts WHERE snippets_fts MATCH 'cheers')",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(found, ["Theirs"]);
+    }
+
+    #[tokio::test]
+    async fn upgrade_keeps_the_first_of_duplicate_variable_keys() {
+        let pool = fixture_pool(FIXTURES[6].1).await;
+        sqlx::query("INSERT INTO variables (snippet_id, key, type, default_value) VALUES (1, 'name', 'input', 'Bob'), (2, 'name', 'input', NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        run(&pool, None).await.unwrap();
+
+        let defaults: Vec<(i64, Option<String>)> =
+            sqlx::query_as("SELECT snippet_id, default_value FROM variables ORDER BY snippet_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(defaults, [(1, Some("Ann".to_string())), (2, None)]);
+
+        let duplicate = sqlx::query("INSERT INTO variables (snippet_id, key, type) VALUES (2, 'name', 'select')")
+            .execute(&pool)
+            .await;
+        assert!(duplicate.is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_database_from_a_newer_app() {
+        let pool = memory_pool().await;
+        run(&pool, None).await.unwrap();
+        sqlx::query("INSERT INTO schema_version (version, name) VALUES (?, 'future')")
+            .bind(latest_version() + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(run(&pool, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_migration_leaves_the_database_untouched() {
+        // Looks like version 3, but lacks columns the version 4 rebuild copies
+        let pool = fixture_pool(
+            "CREATE TABLE snippets (id INTEGER PRIMARY KEY, shortcut TEXT UNIQUE NOT NULL, propagate_case INTEGER);
+             CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             INSERT INTO snippets (id, shortcut) VALUES (1, '/sig');",
+        )
+        .await;
+        let before = schema(&pool).await;
+
+        assert!(run(&pool, None).await.is_err());
+        assert_eq!(schema(&pool).await, before);
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(!table_exists(&mut conn, "schema_version").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn backs_up_an_existing_database_before_upgrading() {
+        let dir = std::env::temp_dir().join(format!("spark-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_file = dir.join("data.db");
+
+        let options = SqliteConnectOptions::new().filename(&db_file).create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        sqlx::query(FIXTURES[0].1).execute(&pool).await.unwrap();
+        run(&pool, Some(&db_file)).await.unwrap();
+        pool.close().await;
+
+        let backups: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path != &db_file)
+            .collect();
+        assert_eq!(backups.len(), 1);
+        let name = backups[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("data.db.v1-") && name.ends_with(".bak"), "{}", name);
+
+        // The backup is the database as it was before the upgrade
+        let options = SqliteConnectOptions::new().filename(&backups[0]);
+        let backup = SqlitePool::connect_with(options).await.unwrap();
+        let mut conn = backup.acquire().await.unwrap();
+        assert_eq!(legacy_version(&mut conn).await.unwrap(), 1);
+        assert!(!table_exists(&mut conn, "schema_version").await.unwrap());
+        drop(conn);
+        backup.close().await;
+
+        // Already up to date: no second backup
+        let options = SqliteConnectOptions::new().filename(&db_file);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        run(&pool, Some(&db_file)).await.unwrap();
+        pool.close().await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use crate::database::Snippet;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+// Name matches count most, then the shortcut, then the body
+const RANK: &str = "bm25(snippets_fts, 10.0, 5.0, 1.0)";
+
+// snippet() marks matched terms with these; no snippet body uses them
+const MATCH_START: char = '\u{1}';
+const MATCH_END: char = '\u{2}';
+
+// Words of body text to show around the matches
+const EXCERPT_WORDS: i64 = 16;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchRequest {
+    // Words to find in the name, shortcut or body, each as a prefix. Empty
+    // lists every snippet the filters allow.
+    pub query: String,
+    pub folder_id: Option<i64>,
+    pub is_active: Option<bool>,
+    // Only snippets carrying every one of these tags
+    pub tags: Vec<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub snippet: Snippet,
+    // Body text around the matches, split so matched words can be
+    // highlighted. Empty when there is no query.
+    pub excerpt: Vec<ExcerptPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExcerptPart {
+    pub text: String,
+    pub matched: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    snippet: Snippet,
+    excerpt: String,
+}
+
+// Best matches first, or by name without a query
+pub async fn search(db: &SqlitePool, user_id: i64, request: &SearchRequest) -> Result<Vec<SearchResult>, sqlx::Error> {
+    let fts_query = fts_query(&request.query);
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT s.id, s.user_id, s.folder_id, s.name, s.shortcut, s.body, s.created_at, s.updated_at, s.is_active, s.match_type, s.propagate_case, s.app_include, s.app_exclude, s.trusted, s.tags, ",
+    );
+    match &fts_query {
+        Some(fts_query) => {
+            query
+                .push(format!(
+                    "snippet(snippets_fts, 2, char({}), char({}), '…', {}) AS excerpt",
+                    MATCH_START as u32, MATCH_END as u32, EXCERPT_WORDS
+                ))
+                .push(" FROM snippets_fts JOIN snippets s ON s.id = snippets_fts.rowid WHERE snippets_fts MATCH ")
+                .push_bind(fts_query.clone())
+                .push(" AND ");
+        }
+        None => {
+            query.push("'' AS excerpt FROM snippets s WHERE ");
+        }
+    }
+
+    query.push("s.user_id = ").push_bind(user_id);
+    if let Some(folder_id) = request.folder_id {
+        query.push(" AND s.folder_id = ").push_bind(folder_id);
+    }
+    if let Some(is_active) = request.is_active {
+        query.push(" AND s.is_active = ").push_bind(is_active as i32);
+    }
+    for tag in &request.tags {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM json_each(s.tags) WHERE json_each.value = ")
+            .push_bind(tag.trim().to_string())
+            .push(")");
+    }
+
+    if fts_query.is_some() {
+        query.push(format!(" ORDER BY {}, s.id", RANK));
+    } else {
+        query.push(" ORDER BY s.name COLLATE NOCASE, s.id");
+    }
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    query.push(" LIMIT ").push_bind(limit);
+
+    let rows: Vec<SearchRow> = query.build_query_as().fetch_all(db).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| SearchResult {
+            snippet: row.snippet,
+            excerpt: parse_excerpt(&row.excerpt),
+        })
+        .collect())
+}
+
+// Turns what the user typed into an FTS5 query: every word must appear, as
+// the start of a word. Quoting each one keeps FTS5 syntax such as `-`, `:`
+// or `OR` literal. None when nothing searchable is left.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        // The tokenizer drops punctuation, so `/` alone would be an empty
+        // phrase, which FTS5 rejects
+        .filter(|term| term.chars().any(char::is_alphanumeric))
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn parse_excerpt(text: &str) -> Vec<ExcerptPart> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut matched = false;
+
+    for character in text.chars() {
+        let starts = match character {
+            MATCH_START => true,
+            MATCH_END => false,
+            _ => {
+                current.push(character);
+                continue;
+            }
+        };
+        if !current.is_empty() {
+            parts.push(ExcerptPart { text: std::mem::take(&mut current), matched });
+        }
+        matched = starts;
+    }
+    if !current.is_empty() {
+        parts.push(ExcerptPart { text: current, matched });
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn library() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::migrations::run(&pool, None).await.unwrap();
+
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, email, password_hash) VALUES (1, 'ann@example.com', 'hash'), (2, 'bob@example.com', 'hash');
+            INSERT INTO folders (id, user_id, name) VALUES (1, 1, 'Work');
+            INSERT INTO snippets (id, user_id, folder_id, name, shortcut, body, is_active, tags) VALUES
+                (1, 1, 1, 'Signature', '/sig', 'Best regards, Ann', 1, '["mail","work"]'),
+                (2, 1, NULL, 'Meeting notes', '/notes', 'Agenda, then the signature block', 1, '["work"]'),
+                (3, 1, 1, 'Old signature', '/oldsig', 'Kind regards', 0, '[]'),
+                (4, 2, NULL, 'Signature', '/bsig', 'Cheers, Bob', 1, '[]');
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn ids(pool: &SqlitePool, request: SearchRequest) -> Vec<i64> {
+        search(pool, 1, &request)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| result.snippet.id)
+            .collect()
+    }
+
+    fn query(text: &str) -> SearchRequest {
+        SearchRequest { query: text.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn quotes_every_term_as_a_prefix() {
+        assert_eq!(fts_query("sig"), Some("\"sig\"*".to_string()));
+        assert_eq!(fts_query("  best   OR -x "), Some("\"best\"* \"OR\"* \"-x\"*".to_string()));
+        assert_eq!(fts_query("say \"hi\""), Some("\"say\"* \"\"\"hi\"\"\"*".to_string()));
+        assert_eq!(fts_query(" / -- "), None);
+    }
+
+    #[test]
+    fn splits_excerpts_at_match_markers() {
+        let text = format!("Best {}regards{}, {}Ann{}", MATCH_START, MATCH_END, MATCH_START, MATCH_END);
+        let part = |text: &str, matched| ExcerptPart { text: text.to_string(), matched };
+        assert_eq!(
+            parse_excerpt(&text),
+            [part("Best ", false), part("regards", true), part(", ", false), part("Ann", true)]
+        );
+        assert!(parse_excerpt("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn ranks_name_matches_above_body_matches() {
+        let pool = library().await;
+        assert_eq!(ids(&pool, query("signature")).await, [1, 3, 2]);
+        // Prefixes match, in any column, and only the user's own snippets
+        assert_eq!(ids(&pool, query("sig")).await, [1, 3, 2]);
+        assert_eq!(ids(&pool, query("/notes")).await, [2]);
+        assert_eq!(ids(&pool, query("regards kind")).await, [3]);
+        assert!(ids(&pool, query("cheers")).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn highlights_matches_in_the_body() {
+        let pool = library().await;
+        let results = search(&pool, 1, &query("regar")).await.unwrap();
+        let excerpt = &results[0].excerpt;
+        assert_eq!(excerpt.iter().filter(|part| part.matched).count(), 1);
+        assert!(excerpt.iter().any(|part| part.matched && part.text == "regards"));
+    }
+
+    #[tokio::test]
+    async fn filters_by_folder_active_state_and_tags() {
+        let pool = library().await;
+        let folder = SearchRequest { folder_id: Some(1), ..query("sig") };
+        assert_eq!(ids(&pool, folder).await, [1, 3]);
+
+        let active = SearchRequest { is_active: Some(true), ..query("sig") };
+        assert_eq!(ids(&pool, active).await, [1, 2]);
+
+        let tagged = SearchRequest { tags: vec!["work".to_string()], ..query("") };
+        assert_eq!(ids(&pool, tagged).await, [2, 1]);
+
+        let both_tags = SearchRequest { tags: vec!["work".to_string(), "mail".to_string()], ..query("") };
+        assert_eq!(ids(&pool, both_tags).await, [1]);
+
+        let limited = SearchRequest { limit: Some(1), ..query("sig") };
+        assert_eq!(ids(&pool, limited).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn index_follows_snippet_changes() {
+        let pool = library().await;
+
+        sqlx::query("UPDATE snippets SET body = 'Warm wishes' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(&pool, query("warm")).await, [1]);
+        assert_eq!(ids(&pool, query("best")).await, Vec::<i64>::new());
+
+        sqlx::query("DELETE FROM snippets WHERE id = 1").execute(&pool).await.unwrap();
+        assert!(ids(&pool, query("warm")).await.is_empty());
+
+        sqlx::query("INSERT INTO snippets (user_id, name, shortcut, body) VALUES (1, 'Address', '/addr', 'Main Street')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(&pool, query("street")).await.len(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Row, SqlitePool};
+use std::sync::RwLock;
+
+const MAX_DELAY_MS: u64 = 2000;
+
+// Stored one row per field in the `settings` table, so new fields can be
+// added with a default and old databases keep working.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub expand_enabled: bool,
+    pub global_hotkey: String,
+    pub excluded_apps: Vec<String>,
+    pub trigger_prefix: Option<char>,
+    pub undo_backspace: bool,
+    // Pause between injected backspaces, for apps that drop fast input
+    pub backspace_delay_ms: u64,
+    // How long pasted text stays on the clipboard before it is restored
+    pub clipboard_restore_delay_ms: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            expand_enabled: true,
+            global_hotkey: "Ctrl+Alt+Space".to_string(),
+            excluded_apps: vec![],
+            trigger_prefix: None,
+            undo_backspace: true,
+            backspace_delay_ms: 0,
+            clipboard_restore_delay_ms: 100,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CURRENT: RwLock<Settings> = RwLock::new(Settings::default());
+}
+
+pub fn current() -> Settings {
+    CURRENT.read().unwrap().clone()
+}
+
+pub async fn load(db: &SqlitePool) -> Result<Settings, sqlx::Error> {
+    let rows = sqlx::query("SELECT key, value FROM settings")
+        .fetch_all(db)
+        .await?;
+
+    let defaults = match serde_json::to_value(Settings::default()) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    let mut merged = defaults.clone();
+
+    // Apply stored fields one at a time so a single bad value only resets
+    // that field instead of the whole store
+    for row in rows {
+        let key: String = row.get("key");
+        let value: String = row.get("value");
+
+        let Ok(value) = serde_json::from_str::<Value>(&value) else {
+            eprintln!("Ignoring unreadable setting {}", key);
+            continue;
+        };
+
+        let mut candidate = merged.clone();
+        candidate.insert(key.clone(), value);
+        match serde_json::from_value::<Settings>(Value::Object(candidate.clone())) {
+            Ok(_) => merged = candidate,
+            Err(e) => eprintln!("Ignoring invalid setting {}: {}", key, e),
+        }
+    }
+
+    // Values of the right type can still fail validation, e.g. a hotkey
+    // that does not parse; those fields go back to their defaults too
+    let settings: Settings = serde_json::from_value(Value::Object(merged.clone())).unwrap_or_default();
+    let invalid = invalid_fields(&settings);
+    if invalid.is_empty() {
+        return Ok(settings);
+    }
+    for (key, e) in invalid {
+        eprintln!("Ignoring invalid setting {}: {}", key, e);
+        if let Some(default) = defaults.get(key) {
+            merged.insert(key.to_string(), default.clone());
+        }
+    }
+    Ok(serde_json::from_value(Value::Object(merged)).unwrap_or_default())
+}
+
+pub async fn save(db: &SqlitePool, settings: &Settings) -> Result<(), String> {
+    validate(settings)?;
+
+    let fields = match serde_json::to_value(settings) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return Err("Failed to serialize settings".to_string()),
+    };
+
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    for (key, value) in fields {
+        sqlx::query("INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)")
+            .bind(key)
+            .bind(value.to_string())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(())
+}
+
+pub fn validate(settings: &Settings) -> Result<(), String> {
+    match invalid_fields(settings).into_iter().next() {
+        Some((_, e)) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Each field that fails its check, by key, with the reason. Every check looks
+// at one field only, so resetting that field to its default fixes it.
+fn invalid_fields(settings: &Settings) -> Vec<(&'static str, String)> {
+    let mut invalid = Vec::new();
+
+    if let Err(e) = crate::hotkey::parse(&settings.global_hotkey) {
+        invalid.push(("global_hotkey", e));
+    }
+
+    if let Some(prefix) = settings.trigger_prefix {
+        if prefix.is_whitespace() || prefix.is_control() {
+            invalid.push(("trigger_prefix", format!("Trigger prefix cannot be {:?}", prefix)));
+        }
+    }
+
+    // Entries are window class or process name patterns, see ActiveApp::matches
+    if settings.excluded_apps.iter().any(|app| app.trim().is_empty()) {
+        invalid.push(("excluded_apps", "Excluded apps cannot contain empty entries".to_string()));
+    }
+
+    for (key, name, delay) in [
+        ("backspace_delay_ms", "Backspace delay", settings.backspace_delay_ms),
+        ("clipboard_restore_delay_ms", "Clipboard restore delay", settings.clipboard_restore_delay_ms),
+    ] {
+        if delay > MAX_DELAY_MS {
+            invalid.push((key, format!("{} must be at most {} ms", name, MAX_DELAY_MS)));
+        }
+    }
+
+    invalid
+}
+
+// Makes settings take effect and notifies the engine and expander
+pub fn apply(settings: Settings) {
+    crate::engine::set_trigger_prefix(settings.trigger_prefix);
+    crate::engine::set_undo_enabled(settings.undo_backspace);
+    crate::engine::set_injection_delays(
+        settings.backspace_delay_ms,
+        settings.clipboard_restore_delay_ms,
+    );
+
+    crate::expander::set_excluded_apps(settings.excluded_apps.clone());
+
+    if let Err(e) = crate::hotkey::register(&settings.global_hotkey) {
+        eprintln!("{}", e);
+    }
+
+    if settings.expand_enabled {
+        crate::expander::start();
+    } else {
+        crate::expander::stop();
+    }
+
+    *CURRENT.write().unwrap() = settings;
+}
+
+// Turns expansion on or off without touching the other settings, as the
+// tray's pause and resume do
+pub fn set_expand_enabled(enabled: bool) {
+    if enabled {
+        crate::expander::start();
+    } else {
+        crate::expander::stop();
+    }
+    CURRENT.write().unwrap().expand_enabled = enabled;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn stored(values: &[(&str, &str)]) -> SqlitePool {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(include_str!("../migrations/0003_settings.sql"))
+            .execute(&db)
+            .await
+            .unwrap();
+        for (key, value) in values {
+            sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?)")
+                .bind(key)
+                .bind(value)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        db
+    }
+
+    #[tokio::test]
+    async fn invalid_settings_only_reset_themselves() {
+        let db = stored(&[
+            ("expand_enabled", "false"),
+            ("global_hotkey", "\"Ctrl+Nothing\""),
+            ("undo_backspace", "\"sometimes\""),
+            ("backspace_delay_ms", "50"),
+            ("clipboard_restore_delay_ms", "99999"),
+            ("trigger_prefix", "not json"),
+            ("excluded_apps", "[\"keepassxc\"]"),
+        ])
+        .await;
+        let settings = load(&db).await.unwrap();
+        let defaults = Settings::default();
+
+        assert!(!settings.expand_enabled);
+        assert_eq!(settings.global_hotkey, defaults.global_hotkey);
+        assert_eq!(settings.undo_backspace, defaults.undo_backspace);
+        assert_eq!(settings.backspace_delay_ms, 50);
+        assert_eq!(settings.clipboard_restore_delay_ms, defaults.clipboard_restore_delay_ms);
+        assert_eq!(settings.trigger_prefix, None);
+        assert_eq!(settings.excluded_apps, ["keepassxc"]);
+    }
+
+    #[tokio::test]
+    async fn saved_settings_load_back() {
+        let db = stored(&[]).await;
+        let mut settings = load(&db).await.unwrap();
+        settings.expand_enabled = false;
+        settings.trigger_prefix = Some(';');
+        save(&db, &settings).await.unwrap();
+
+        let loaded = load(&db).await.unwrap();
+        assert!(!loaded.expand_enabled);
+        assert_eq!(loaded.trigger_prefix, Some(';'));
+
+        settings.excluded_apps = vec![" ".to_string()];
+        assert_eq!(save(&db, &settings).await.unwrap_err(), "Excluded apps cannot contain empty entries");
+    }
+}
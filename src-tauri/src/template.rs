@@ -0,0 +1,1121 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::commands::CommandOptions;
+use crate::generate::{parse_range, LoremLength};
+
+// Parser for the snippet placeholder language.
+//
+//   text        plain text; `\{{` and `\}}` are literal braces
+//   {{name}}    a placeholder without arguments
+//   {{name:arg}} arguments run to the closing `}}` and may hold nested
+//               placeholders, "quoted text" and `\` escapes
+//   {{name|f:x}} filters transform the output, left to right
+//
+// Each kind declares how many `:`-separated arguments it takes; colons after
+// the last one are part of that argument, so `{{time:%H:%M}}` needs no
+// quoting.
+//
+// Blocks take an evalexpr expression instead of arguments:
+//
+//   {{if input.lang == "fr"}}...{{elif ...}}...{{else}}...{{end}}
+//   {{each tag in input.tags}}...{{var:tag}}...{{end}}
+//   {{let total = num(input.qty) * 2}}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    // Quoted or escaped text, never treated as a separator
+    Literal(String),
+    Placeholder(Placeholder),
+    If(IfBlock),
+    Each(EachBlock),
+    Let(LetBinding),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub source: String,
+    pub tree: evalexpr::Node,
+    // Byte offset of the expression in the template
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfBlock {
+    // Conditions tried in order, each with the nodes it guards
+    pub branches: Vec<(Expr, Vec<Node>)>,
+    pub otherwise: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EachBlock {
+    pub variable: String,
+    pub items: Expr,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LetBinding {
+    pub name: String,
+    pub value: Expr,
+}
+
+// What stopped a run of nodes: the end of the template or a block keyword
+enum BlockEnd {
+    Eof,
+    Else(usize),
+    Elif(Expr, usize),
+    End(usize),
+}
+
+// One argument: text and nested placeholders evaluated and joined
+pub type Arg = Vec<Node>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub kind: PlaceholderKind,
+    pub args: Vec<Arg>,
+    // Applied to the output in order
+    pub filters: Vec<Filter>,
+    // Byte range of the whole `{{...}}` in the source
+    pub span: Range<usize>,
+}
+
+// `|name` or `|name:arg:arg` at the end of a placeholder. Which names exist
+// is up to the renderer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<Arg>,
+    // Byte offset of the `|`
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    Date,
+    Time,
+    Clipboard,
+    Cursor,
+    Input,
+    Select,
+    Calc,
+    Env,
+    Capture,
+    Snippet,
+    MultiSelect,
+    Var,
+    Shell,
+    Script,
+    Uuid,
+    Random,
+    RandomChoice,
+    Lorem,
+    Counter,
+}
+
+impl PlaceholderKind {
+    pub fn parse(name: &str) -> Option<PlaceholderKind> {
+        match name {
+            "date" => Some(PlaceholderKind::Date),
+            "time" => Some(PlaceholderKind::Time),
+            "clipboard" => Some(PlaceholderKind::Clipboard),
+            "cursor" => Some(PlaceholderKind::Cursor),
+            "input" => Some(PlaceholderKind::Input),
+            "select" => Some(PlaceholderKind::Select),
+            "calc" => Some(PlaceholderKind::Calc),
+            "env" => Some(PlaceholderKind::Env),
+            "capture" => Some(PlaceholderKind::Capture),
+            "snippet" => Some(PlaceholderKind::Snippet),
+            "multiselect" => Some(PlaceholderKind::MultiSelect),
+            "var" => Some(PlaceholderKind::Var),
+            "shell" => Some(PlaceholderKind::Shell),
+            "script" => Some(PlaceholderKind::Script),
+            "uuid" => Some(PlaceholderKind::Uuid),
+            "random" => Some(PlaceholderKind::Random),
+            "random_choice" => Some(PlaceholderKind::RandomChoice),
+            "lorem" => Some(PlaceholderKind::Lorem),
+            "counter" => Some(PlaceholderKind::Counter),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PlaceholderKind::Date => "date",
+            PlaceholderKind::Time => "time",
+            PlaceholderKind::Clipboard => "clipboard",
+            PlaceholderKind::Cursor => "cursor",
+            PlaceholderKind::Input => "input",
+            PlaceholderKind::Select => "select",
+            PlaceholderKind::Calc => "calc",
+            PlaceholderKind::Env => "env",
+            PlaceholderKind::Capture => "capture",
+            PlaceholderKind::Snippet => "snippet",
+            PlaceholderKind::MultiSelect => "multiselect",
+            PlaceholderKind::Var => "var",
+            PlaceholderKind::Shell => "shell",
+            PlaceholderKind::Script => "script",
+            PlaceholderKind::Uuid => "uuid",
+            PlaceholderKind::Random => "random",
+            PlaceholderKind::RandomChoice => "random_choice",
+            PlaceholderKind::Lorem => "lorem",
+            PlaceholderKind::Counter => "counter",
+        }
+    }
+
+    // Minimum and maximum number of arguments
+    fn arity(self) -> (usize, usize) {
+        match self {
+            PlaceholderKind::Clipboard | PlaceholderKind::Uuid => (0, 0),
+            PlaceholderKind::Cursor | PlaceholderKind::Lorem => (0, 1),
+            PlaceholderKind::Input | PlaceholderKind::Shell | PlaceholderKind::Script => (1, 2),
+            PlaceholderKind::Select | PlaceholderKind::MultiSelect => (2, 2),
+            PlaceholderKind::Date
+            | PlaceholderKind::Time
+            | PlaceholderKind::Calc
+            | PlaceholderKind::Env
+            | PlaceholderKind::Capture
+            | PlaceholderKind::Snippet
+            | PlaceholderKind::Var
+            | PlaceholderKind::Random
+            | PlaceholderKind::RandomChoice
+            | PlaceholderKind::Counter => (1, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    // Byte offset into the source, and the 1-based line and column there
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateErrorKind {
+    Unclosed,
+    UnterminatedQuote,
+    MissingName,
+    UnknownPlaceholder(String),
+    UnexpectedCharacter(char),
+    MissingArgument(&'static str),
+    UnexpectedArgument(&'static str),
+    InvalidArgument { placeholder: &'static str, reason: String },
+    SnippetNotFound(String),
+    // The chain of snippet references that leads back to itself
+    IncludeCycle(Vec<String>),
+    IncludeTooDeep(usize),
+    // An error inside a snippet pulled in with {{snippet:...}}; its position
+    // is within that snippet's body
+    InIncludedSnippet { reference: String, error: Box<TemplateError> },
+    // An if or each without its {{end}}
+    UnclosedBlock(&'static str),
+    // else, elif or end with no block to close
+    UnexpectedBlockEnd(&'static str),
+    InvalidBlock { block: &'static str, reason: String },
+    InvalidExpression(String),
+    // A `|` with no filter name after it
+    MissingFilterName,
+    UnknownFilter(String),
+    InvalidFilter { filter: String, reason: String },
+    // A command placeholder in a snippet that has not been approved
+    Untrusted(&'static str),
+    CommandFailed { placeholder: &'static str, reason: String },
+}
+
+impl TemplateError {
+    pub fn new(kind: TemplateErrorKind, source: &str, offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        TemplateError { kind, offset, line, column }
+    }
+
+    pub fn invalid_argument(placeholder: PlaceholderKind, reason: String, source: &str, offset: usize) -> Self {
+        TemplateError::new(
+            TemplateErrorKind::InvalidArgument { placeholder: placeholder.name(), reason },
+            source,
+            offset,
+        )
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            TemplateErrorKind::Unclosed => write!(f, "placeholder is missing its closing }}}}"),
+            TemplateErrorKind::UnterminatedQuote => write!(f, "quoted text is missing its closing quote"),
+            TemplateErrorKind::MissingName => write!(f, "placeholder has no name"),
+            TemplateErrorKind::UnknownPlaceholder(name) => write!(f, "unknown placeholder {{{{{}}}}}", name),
+            TemplateErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected {:?} after placeholder name", c),
+            TemplateErrorKind::MissingArgument(name) => write!(f, "{{{{{}}}}} is missing an argument", name),
+            TemplateErrorKind::UnexpectedArgument(name) => write!(f, "{{{{{}}}}} takes fewer arguments", name),
+            TemplateErrorKind::InvalidArgument { placeholder, reason } => write!(f, "{{{{{}}}}}: {}", placeholder, reason),
+            TemplateErrorKind::SnippetNotFound(reference) => write!(f, "no active snippet {}", reference),
+            TemplateErrorKind::IncludeCycle(chain) => write!(f, "snippets include each other: {}", chain.join(" -> ")),
+            TemplateErrorKind::IncludeTooDeep(limit) => write!(f, "snippets are nested more than {} deep", limit),
+            TemplateErrorKind::InIncludedSnippet { reference, error } => write!(f, "in snippet {}, {}", reference, error),
+            TemplateErrorKind::UnclosedBlock(block) => write!(f, "{{{{{}}}}} is missing its {{{{end}}}}", block),
+            TemplateErrorKind::UnexpectedBlockEnd(keyword) => write!(f, "{{{{{}}}}} without a matching block", keyword),
+            TemplateErrorKind::InvalidBlock { block, reason } => write!(f, "{{{{{}}}}}: {}", block, reason),
+            TemplateErrorKind::InvalidExpression(reason) => write!(f, "invalid expression: {}", reason),
+            TemplateErrorKind::MissingFilterName => write!(f, "filter has no name"),
+            TemplateErrorKind::UnknownFilter(name) => write!(f, "unknown filter |{}", name),
+            TemplateErrorKind::InvalidFilter { filter, reason } => write!(f, "|{}: {}", filter, reason),
+            TemplateErrorKind::Untrusted(name) => {
+                write!(f, "{{{{{}}}}} only runs in snippets marked as trusted", name)
+            }
+            TemplateErrorKind::CommandFailed { placeholder, reason } => {
+                write!(f, "{{{{{}}}}}: command {}", placeholder, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+pub fn parse(source: &str) -> Result<Vec<Node>, TemplateError> {
+    let mut parser = Parser { source, position: 0 };
+    parser.parse_text()
+}
+
+// Quotes text, when needed, so it reads back as a single literal argument
+pub fn quote_argument(text: &str) -> String {
+    if !text.contains(['"', '\\', '{', '}', ':', ',', '|']) {
+        return text.to_string();
+    }
+
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for character in text.chars() {
+        if character == '"' || character == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(character);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Splits an argument on unquoted, unescaped separators
+pub fn split_arg(arg: &[Node], separator: char, max_parts: usize) -> Vec<Arg> {
+    let mut parts: Vec<Arg> = vec![Vec::new()];
+
+    for node in arg {
+        let Node::Text(text) = node else {
+            parts.last_mut().unwrap().push(node.clone());
+            continue;
+        };
+
+        let mut rest = text.as_str();
+        while parts.len() < max_parts {
+            let Some(index) = rest.find(separator) else {
+                break;
+            };
+            if index > 0 {
+                parts.last_mut().unwrap().push(Node::Text(rest[..index].to_string()));
+            }
+            parts.push(Vec::new());
+            rest = &rest[index + separator.len_utf8()..];
+        }
+        if !rest.is_empty() {
+            parts.last_mut().unwrap().push(Node::Text(rest.to_string()));
+        }
+    }
+
+    parts
+}
+
+// Text of an argument without placeholders, or None if it has any
+pub fn literal_arg(arg: &[Node]) -> Option<String> {
+    let mut text = String::new();
+    for node in arg {
+        match node {
+            Node::Text(part) | Node::Literal(part) => text.push_str(part),
+            _ => return None,
+        }
+    }
+    Some(text)
+}
+
+// Every placeholder in the template, including nested ones, in source order
+pub fn walk<'a>(nodes: &'a [Node], visit: &mut dyn FnMut(&'a Placeholder)) {
+    for node in nodes {
+        match node {
+            Node::Placeholder(placeholder) => {
+                visit(placeholder);
+                let filter_args = placeholder.filters.iter().flat_map(|filter| &filter.args);
+                for arg in placeholder.args.iter().chain(filter_args) {
+                    walk(arg, visit);
+                }
+            }
+            Node::If(block) => {
+                for (_, body) in &block.branches {
+                    walk(body, visit);
+                }
+                walk(&block.otherwise, visit);
+            }
+            Node::Each(block) => walk(&block.body, visit),
+            Node::Text(_) | Node::Literal(_) | Node::Let(_) => {}
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn error(&self, kind: TemplateErrorKind, offset: usize) -> TemplateError {
+        TemplateError::new(kind, self.source, offset)
+    }
+
+    // Top-level text. A stray `}}` here is ordinary text.
+    fn parse_text(&mut self) -> Result<Vec<Node>, TemplateError> {
+        let (nodes, end) = self.parse_nodes()?;
+        match end {
+            BlockEnd::Eof => Ok(nodes),
+            BlockEnd::Else(at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("else"), at)),
+            BlockEnd::Elif(_, at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("elif"), at)),
+            BlockEnd::End(at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("end"), at)),
+        }
+    }
+
+    // Nodes up to the end of the template or the next else, elif or end
+    fn parse_nodes(&mut self) -> Result<(Vec<Node>, BlockEnd), TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        while let Some(character) = self.rest().chars().next() {
+            let rest = self.rest();
+            if rest.starts_with("\\{{") || rest.starts_with("\\}}") {
+                text.push_str(&rest[1..3]);
+                self.position += 3;
+            } else if rest.starts_with("{{") {
+                push_text(&mut nodes, &mut text);
+                let start = self.position;
+                match self.peek_keyword() {
+                    "if" => nodes.push(Node::If(self.parse_if()?)),
+                    "each" => nodes.push(Node::Each(self.parse_each()?)),
+                    "let" => nodes.push(Node::Let(self.parse_let()?)),
+                    "else" => {
+                        self.expect_bare_keyword("else")?;
+                        return Ok((nodes, BlockEnd::Else(start)));
+                    }
+                    "end" => {
+                        self.expect_bare_keyword("end")?;
+                        return Ok((nodes, BlockEnd::End(start)));
+                    }
+                    "elif" => {
+                        let condition = self.parse_header("elif")?;
+                        return Ok((nodes, BlockEnd::Elif(condition, start)));
+                    }
+                    _ => nodes.push(Node::Placeholder(self.parse_placeholder()?)),
+                }
+            } else {
+                text.push(character);
+                self.position += character.len_utf8();
+            }
+        }
+
+        push_text(&mut nodes, &mut text);
+        Ok((nodes, BlockEnd::Eof))
+    }
+
+    // The word after the `{{` at the current position
+    fn peek_keyword(&self) -> &'a str {
+        let inner = self.rest()[2..].trim_start();
+        let len = inner
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(inner.len());
+        &inner[..len]
+    }
+
+    // Consumes `{{keyword` and the whitespace after it
+    fn enter_block(&mut self, keyword: &str) {
+        self.position += 2;
+        self.skip_whitespace();
+        self.position += keyword.len();
+        self.skip_whitespace();
+    }
+
+    fn expect_bare_keyword(&mut self, keyword: &'static str) -> Result<(), TemplateError> {
+        let start = self.position;
+        self.enter_block(keyword);
+        if !self.rest().starts_with("}}") {
+            let reason = "takes no expression".to_string();
+            return Err(match self.rest().chars().next() {
+                None => self.error(TemplateErrorKind::Unclosed, start),
+                Some(_) => self.error(TemplateErrorKind::InvalidBlock { block: keyword, reason }, start),
+            });
+        }
+        self.position += 2;
+        Ok(())
+    }
+
+    // `{{keyword expression}}`, returning the expression
+    fn parse_header(&mut self, keyword: &'static str) -> Result<Expr, TemplateError> {
+        let start = self.position;
+        self.enter_block(keyword);
+        self.parse_expression(start)
+    }
+
+    fn parse_if(&mut self) -> Result<IfBlock, TemplateError> {
+        let start = self.position;
+        let mut condition = self.parse_header("if")?;
+        let mut branches = Vec::new();
+
+        loop {
+            let (body, end) = self.parse_nodes()?;
+            branches.push((condition, body));
+            match end {
+                BlockEnd::Elif(next, _) => condition = next,
+                BlockEnd::Else(_) => {
+                    let (otherwise, end) = self.parse_nodes()?;
+                    return match end {
+                        BlockEnd::End(_) => Ok(IfBlock { branches, otherwise }),
+                        BlockEnd::Else(at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("else"), at)),
+                        BlockEnd::Elif(_, at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("elif"), at)),
+                        BlockEnd::Eof => Err(self.error(TemplateErrorKind::UnclosedBlock("if"), start)),
+                    };
+                }
+                BlockEnd::End(_) => return Ok(IfBlock { branches, otherwise: Vec::new() }),
+                BlockEnd::Eof => return Err(self.error(TemplateErrorKind::UnclosedBlock("if"), start)),
+            }
+        }
+    }
+
+    fn parse_each(&mut self) -> Result<EachBlock, TemplateError> {
+        let start = self.position;
+        self.enter_block("each");
+        let variable = self.parse_identifier("each", start)?;
+        self.skip_whitespace();
+        if !self.rest().starts_with("in ") {
+            let reason = "expected `each name in expression`".to_string();
+            return Err(self.error(TemplateErrorKind::InvalidBlock { block: "each", reason }, start));
+        }
+        self.position += 2;
+        let items = self.parse_expression(start)?;
+
+        let (body, end) = self.parse_nodes()?;
+        match end {
+            BlockEnd::End(_) => Ok(EachBlock { variable, items, body }),
+            BlockEnd::Else(at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("else"), at)),
+            BlockEnd::Elif(_, at) => Err(self.error(TemplateErrorKind::UnexpectedBlockEnd("elif"), at)),
+            BlockEnd::Eof => Err(self.error(TemplateErrorKind::UnclosedBlock("each"), start)),
+        }
+    }
+
+    fn parse_let(&mut self) -> Result<LetBinding, TemplateError> {
+        let start = self.position;
+        self.enter_block("let");
+        let name = self.parse_identifier("let", start)?;
+        self.skip_whitespace();
+        // `==` would be a comparison, not an assignment
+        if !self.rest().starts_with('=') || self.rest().starts_with("==") {
+            let reason = "expected `let name = expression`".to_string();
+            return Err(self.error(TemplateErrorKind::InvalidBlock { block: "let", reason }, start));
+        }
+        self.position += 1;
+        let value = self.parse_expression(start)?;
+        Ok(LetBinding { name, value })
+    }
+
+    // Variable names are plain identifiers; `input.` and `capture.` names
+    // are reserved for form values and regex captures
+    fn parse_identifier(&mut self, block: &'static str, start: usize) -> Result<String, TemplateError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            let reason = "expected a variable name".to_string();
+            return Err(self.error(TemplateErrorKind::InvalidBlock { block, reason }, start));
+        }
+        self.position += len;
+        Ok(name.to_string())
+    }
+
+    // Expression text up to the closing `}}`, which may appear inside quoted
+    // strings
+    fn parse_expression(&mut self, start: usize) -> Result<Expr, TemplateError> {
+        self.skip_whitespace();
+        let offset = self.position;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        let end = loop {
+            let rest = self.rest();
+            let Some(character) = rest.chars().next() else {
+                return Err(self.error(TemplateErrorKind::Unclosed, start));
+            };
+            if !in_string && rest.starts_with("}}") {
+                break self.position;
+            }
+            if in_string && escaped {
+                escaped = false;
+            } else if in_string && character == '\\' {
+                escaped = true;
+            } else if character == '"' {
+                in_string = !in_string;
+            }
+            self.position += character.len_utf8();
+        };
+        self.position += 2;
+
+        let source = self.source[offset..end].trim_end().to_string();
+        if source.is_empty() {
+            return Err(self.error(TemplateErrorKind::InvalidExpression("expression is empty".to_string()), offset));
+        }
+        let tree = evalexpr::build_operator_tree(&source)
+            .map_err(|e| self.error(TemplateErrorKind::InvalidExpression(e.to_string()), offset))?;
+        Ok(Expr { source, tree, offset })
+    }
+
+    fn parse_placeholder(&mut self) -> Result<Placeholder, TemplateError> {
+        let start = self.position;
+        self.position += 2;
+        self.skip_whitespace();
+
+        let name_start = self.position;
+        let name_len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        let name = &self.source[name_start..name_start + name_len];
+        self.position += name_len;
+        self.skip_whitespace();
+
+        if name.is_empty() {
+            return Err(match self.rest().chars().next() {
+                None => self.error(TemplateErrorKind::Unclosed, start),
+                Some(_) => self.error(TemplateErrorKind::MissingName, name_start),
+            });
+        }
+        let Some(kind) = PlaceholderKind::parse(name) else {
+            return Err(self.error(TemplateErrorKind::UnknownPlaceholder(name.to_string()), start));
+        };
+
+        let arg = if self.rest().starts_with(':') {
+            self.position += 1;
+            Some(self.parse_arg(start)?)
+        } else {
+            None
+        };
+        let filters = self.parse_filters(start)?;
+        if !self.rest().starts_with("}}") {
+            return Err(match self.rest().chars().next() {
+                None => self.error(TemplateErrorKind::Unclosed, start),
+                Some(c) => self.error(TemplateErrorKind::UnexpectedCharacter(c), self.position),
+            });
+        }
+        self.position += 2;
+
+        let (min, max) = kind.arity();
+        let args = match arg {
+            None => Vec::new(),
+            Some(_) if max == 0 => {
+                return Err(self.error(TemplateErrorKind::UnexpectedArgument(kind.name()), start));
+            }
+            Some(arg) => split_arg(&arg, ':', max),
+        };
+        if args.len() < min {
+            return Err(self.error(TemplateErrorKind::MissingArgument(kind.name()), start));
+        }
+
+        let placeholder = Placeholder {
+            kind,
+            args,
+            filters,
+            span: start..self.position,
+        };
+        self.check_literal_args(&placeholder)?;
+        Ok(placeholder)
+    }
+
+    // Arguments that must be known without rendering anything
+    fn check_literal_args(&self, placeholder: &Placeholder) -> Result<(), TemplateError> {
+        let start = placeholder.span.start;
+        let invalid = |reason: &str| {
+            TemplateError::invalid_argument(placeholder.kind, reason.to_string(), self.source, start)
+        };
+
+        match placeholder.kind {
+            PlaceholderKind::Input | PlaceholderKind::Select | PlaceholderKind::MultiSelect => {
+                match literal_arg(&placeholder.args[0]) {
+                    Some(name) if !name.trim().is_empty() => {}
+                    Some(_) => return Err(invalid("field name is empty")),
+                    None => return Err(invalid("field name cannot contain placeholders")),
+                }
+                // The form lists the options before anything is rendered
+                match placeholder.args.get(1) {
+                    Some(options) if placeholder.kind != PlaceholderKind::Input && literal_arg(options).is_none() => {
+                        Err(invalid("options cannot contain placeholders"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            PlaceholderKind::Var => match literal_arg(&placeholder.args[0]) {
+                Some(name) if !name.trim().is_empty() => Ok(()),
+                Some(_) => Err(invalid("variable name is empty")),
+                None => Err(invalid("variable name cannot contain placeholders")),
+            },
+            PlaceholderKind::Cursor => match placeholder.args.first().map(|arg| literal_arg(arg)) {
+                None => Ok(()),
+                Some(Some(stop)) => match stop.trim().parse::<u32>() {
+                    Ok(1..=9999) => Ok(()),
+                    _ => Err(invalid("tab stop must be a number from 1 to 9999")),
+                },
+                Some(None) => Err(invalid("tab stop cannot contain placeholders")),
+            },
+            PlaceholderKind::Random => match literal_arg(&placeholder.args[0]) {
+                Some(range) => parse_range(&range).map(|_| ()).map_err(|e| invalid(&e)),
+                None => Ok(()),
+            },
+            PlaceholderKind::Lorem => match placeholder.args.first().map(|arg| literal_arg(arg)) {
+                Some(Some(length)) => LoremLength::parse(&length).map(|_| ()).map_err(|e| invalid(&e)),
+                _ => Ok(()),
+            },
+            PlaceholderKind::Shell if has_unquoted_placeholder(&placeholder.args[0]) => Err(invalid(
+                "placeholders in the command must be inside single quotes, e.g. '{{clipboard}}'",
+            )),
+            // Options with placeholders in them are checked once rendered
+            PlaceholderKind::Shell | PlaceholderKind::Script => match placeholder.args.get(1) {
+                Some(options) => {
+                    let options: Option<Vec<String>> = split_arg(options, ',', usize::MAX)
+                        .iter()
+                        .map(|option| literal_arg(option))
+                        .collect();
+                    match options {
+                        Some(options) => CommandOptions::parse(options.iter().map(String::as_str))
+                            .map(|_| ())
+                            .map_err(|e| invalid(&e)),
+                        None => Ok(()),
+                    }
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_filters(&mut self, start: usize) -> Result<Vec<Filter>, TemplateError> {
+        let mut filters = Vec::new();
+        while self.rest().starts_with('|') {
+            let offset = self.position;
+            self.position += 1;
+            self.skip_whitespace();
+
+            let rest = self.rest();
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(match rest.chars().next() {
+                    None => self.error(TemplateErrorKind::Unclosed, start),
+                    Some(_) => self.error(TemplateErrorKind::MissingFilterName, offset),
+                });
+            }
+            let name = rest[..len].to_string();
+            self.position += len;
+            self.skip_whitespace();
+
+            let args = if self.rest().starts_with(':') {
+                self.position += 1;
+                split_arg(&self.parse_arg(start)?, ':', usize::MAX)
+            } else {
+                Vec::new()
+            };
+            filters.push(Filter { name, args, offset });
+        }
+        Ok(filters)
+    }
+
+    // Argument text up to the placeholder's closing `}}` or its first filter
+    fn parse_arg(&mut self, start: usize) -> Result<Arg, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        loop {
+            let rest = self.rest();
+            let Some(character) = rest.chars().next() else {
+                return Err(self.error(TemplateErrorKind::Unclosed, start));
+            };
+
+            if rest.starts_with("}}") || starts_filter(rest) {
+                push_text(&mut nodes, &mut text);
+                return Ok(nodes);
+            } else if rest.starts_with("{{") {
+                push_text(&mut nodes, &mut text);
+                nodes.push(Node::Placeholder(self.parse_placeholder()?));
+            } else if character == '"' {
+                push_text(&mut nodes, &mut text);
+                nodes.push(Node::Literal(self.parse_quoted()?));
+            } else if character == '\\' {
+                self.position += 1;
+                match self.rest().chars().next() {
+                    Some(escaped) => {
+                        push_text(&mut nodes, &mut text);
+                        nodes.push(Node::Literal(escaped.to_string()));
+                        self.position += escaped.len_utf8();
+                    }
+                    None => return Err(self.error(TemplateErrorKind::Unclosed, start)),
+                }
+            } else {
+                text.push(character);
+                self.position += character.len_utf8();
+            }
+        }
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, TemplateError> {
+        let start = self.position;
+        self.position += 1;
+        let mut text = String::new();
+
+        loop {
+            let Some(character) = self.rest().chars().next() else {
+                return Err(self.error(TemplateErrorKind::UnterminatedQuote, start));
+            };
+            self.position += character.len_utf8();
+
+            match character {
+                '"' => return Ok(text),
+                '\\' => {
+                    let Some(escaped) = self.rest().chars().next() else {
+                        return Err(self.error(TemplateErrorKind::UnterminatedQuote, start));
+                    };
+                    text.push(escaped);
+                    self.position += escaped.len_utf8();
+                }
+                _ => text.push(character),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+}
+
+// Inside arguments `|` also separates date modifiers and shell pipelines, so
+// it only starts a filter when a name follows at once and is itself followed
+// by `:`, `|` or the closing `}}`: `|upper}}` is a filter, `| wc -l` and
+// `|+3d` are not. `\|` is always literal.
+fn starts_filter(text: &str) -> bool {
+    let Some(rest) = text.strip_prefix('|') else {
+        return false;
+    };
+    let len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return false;
+    }
+    let after = rest[len..].trim_start();
+    after.starts_with([':', '|']) || after.starts_with("}}")
+}
+
+// Whether a shell command has a placeholder outside single quotes, reading
+// quotes and backslashes the way sh does. Only inside single quotes can a
+// value be swapped for a reference to it without changing how the rest of
+// the command parses.
+fn has_unquoted_placeholder(command: &[Node]) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    for node in command {
+        let text = match node {
+            Node::Text(text) | Node::Literal(text) => text,
+            _ if quote == Some('\'') => continue,
+            _ => return true,
+        };
+        for character in text.chars() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match (quote, character) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => escaped = true,
+                (None, '\'' | '"') => quote = Some(character),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &mut String) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(std::mem::take(text)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholder(source: &str) -> Placeholder {
+        match parse(source).unwrap().as_slice() {
+            [Node::Placeholder(placeholder)] => placeholder.clone(),
+            nodes => panic!("expected a single placeholder, got {:?}", nodes),
+        }
+    }
+
+    fn error(source: &str) -> TemplateError {
+        parse(source).unwrap_err()
+    }
+
+    fn text(text: &str) -> Node {
+        Node::Text(text.to_string())
+    }
+
+    fn literal(text: &str) -> Node {
+        Node::Literal(text.to_string())
+    }
+
+    fn literal_args(placeholder: &Placeholder) -> Vec<String> {
+        placeholder.args.iter().map(|arg| literal_arg(arg).unwrap()).collect()
+    }
+
+    #[test]
+    fn escaped_and_stray_braces_are_text() {
+        assert_eq!(parse(r"a \{{b\}} c").unwrap(), [text("a {{b}} c")]);
+        assert_eq!(parse("a }} b").unwrap(), [text("a }} b")]);
+        assert_eq!(parse(r"\{{clipboard}}").unwrap(), [text("{{clipboard}}")]);
+        // A backslash before anything else is kept
+        assert_eq!(parse(r"C:\path \{x").unwrap(), [text(r"C:\path \{x")]);
+    }
+
+    #[test]
+    fn splits_arguments_on_unquoted_colons() {
+        let input = placeholder(r#"{{input:name:"a:b, c"}}"#);
+        assert_eq!(input.kind, PlaceholderKind::Input);
+        assert_eq!(input.args, [vec![text("name")], vec![literal("a:b, c")]]);
+        assert_eq!(input.span, 0..23);
+
+        let escaped = placeholder(r"{{input:name:a\:b}}");
+        assert_eq!(escaped.args[1], [text("a"), literal(":"), text("b")]);
+        assert_eq!(literal_args(&escaped), ["name", "a:b"]);
+
+        // Colons past the last argument belong to it
+        assert_eq!(literal_args(&placeholder("{{time:%H:%M:%S}}")), ["%H:%M:%S"]);
+        assert_eq!(literal_args(&placeholder("{{ clipboard }}")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn quoted_arguments_keep_escapes_and_separators() {
+        let input = placeholder(r#"{{input:x:"say \"hi\" \\ {{no}}"}}"#);
+        assert_eq!(input.args[1], [literal(r#"say "hi" \ {{no}}"#)]);
+
+        for value in ["plain", "a:b", "say \"hi\"", r"back\slash", "{{x}}", "a|b,c", ""] {
+            let source = format!("{{{{var:v}}}}{{{{input:v:{}}}}}", quote_argument(value));
+            let nodes = parse(&source).unwrap();
+            let Node::Placeholder(input) = &nodes[1] else {
+                panic!("expected a placeholder in {:?}", source);
+            };
+            let default = input.args.get(1).map_or(Some(String::new()), |arg| literal_arg(arg));
+            assert_eq!(default.as_deref(), Some(value), "{}", source);
+        }
+    }
+
+    #[test]
+    fn nested_placeholders_are_arguments() {
+        let snippet = placeholder("{{input:greeting:Hi {{clipboard}}!}}");
+        assert_eq!(snippet.args[1].len(), 3);
+        assert_eq!(literal_arg(&snippet.args[1]), None);
+        let Node::Placeholder(nested) = &snippet.args[1][1] else {
+            panic!("expected a nested placeholder");
+        };
+        assert_eq!(nested.kind, PlaceholderKind::Clipboard);
+        assert_eq!(nested.span, 20..33);
+
+        let mut kinds = Vec::new();
+        walk(&parse("{{input:a:{{env:X}}}} {{clipboard|replace:{{uuid}}:b}}").unwrap(), &mut |placeholder| {
+            kinds.push(placeholder.kind)
+        });
+        assert_eq!(
+            kinds,
+            [PlaceholderKind::Input, PlaceholderKind::Env, PlaceholderKind::Clipboard, PlaceholderKind::Uuid]
+        );
+    }
+
+    #[test]
+    fn parses_filter_chains() {
+        let clipboard = placeholder("{{clipboard|trim|replace:a:b|upper}}");
+        let names: Vec<&str> = clipboard.filters.iter().map(|filter| filter.name.as_str()).collect();
+        assert_eq!(names, ["trim", "replace", "upper"]);
+        assert_eq!(clipboard.filters[1].args, [vec![text("a")], vec![text("b")]]);
+        let offsets: Vec<usize> = clipboard.filters.iter().map(|filter| filter.offset).collect();
+        assert_eq!(offsets, [11, 16, 28]);
+
+        let date = placeholder("{{date:%Y | wc|upper}}");
+        assert_eq!(literal_args(&date), ["%Y | wc"]);
+        assert_eq!(date.filters.len(), 1);
+    }
+
+    #[test]
+    fn pipes_only_start_filters_before_a_name() {
+        assert!(starts_filter("|upper}}"));
+        assert!(starts_filter("|replace:a:b}}"));
+        assert!(starts_filter("|trim|upper}}"));
+        assert!(starts_filter("|upper }}"));
+        assert!(!starts_filter("| wc -l}}"));
+        assert!(!starts_filter("|+3d}}"));
+        assert!(!starts_filter("|3d}}"));
+        assert!(!starts_filter("|grep x}}"));
+        assert!(!starts_filter("upper}}"));
+
+        let shell = placeholder("{{shell:ls | wc -l}}");
+        assert_eq!(literal_args(&shell), ["ls | wc -l"]);
+        assert!(shell.filters.is_empty());
+
+        let escaped = placeholder(r"{{shell:echo a\|upper}}");
+        assert_eq!(literal_args(&escaped), ["echo a|upper"]);
+        assert!(escaped.filters.is_empty());
+    }
+
+    #[test]
+    fn parses_nested_blocks() {
+        let nodes = parse("{{if a}}{{each t in xs}}{{var:t}}{{end}}{{elif b}}B{{else}}C{{end}}").unwrap();
+        let [Node::If(block)] = nodes.as_slice() else {
+            panic!("expected an if block, got {:?}", nodes);
+        };
+        assert_eq!(block.branches.len(), 2);
+        assert_eq!(block.branches[0].0.source, "a");
+        assert!(matches!(block.branches[0].1.as_slice(), [Node::Each(each)] if each.variable == "t"));
+        assert_eq!(block.branches[1].0.source, "b");
+        assert_eq!(block.branches[1].0.offset, 47);
+        assert_eq!(block.otherwise, [text("C")]);
+
+        let nodes = parse(r#"{{let total = num(input.qty) * 2}}{{if x == "}}"}}y{{end}}"#).unwrap();
+        assert!(matches!(&nodes[0], Node::Let(binding) if binding.name == "total"));
+        assert!(matches!(&nodes[1], Node::If(block) if block.branches[0].0.source == r#"x == "}}""#));
+    }
+
+    #[test]
+    fn rejects_unbalanced_blocks() {
+        let unclosed_if = error("{{if x}}a");
+        assert_eq!(unclosed_if.kind, TemplateErrorKind::UnclosedBlock("if"));
+        assert_eq!(unclosed_if.offset, 0);
+
+        let unclosed_each = error("a\n{{each t in xs}}b{{if y}}{{end}}");
+        assert_eq!(unclosed_each.kind, TemplateErrorKind::UnclosedBlock("each"));
+        assert_eq!((unclosed_each.offset, unclosed_each.line, unclosed_each.column), (2, 2, 1));
+
+        let stray_end = error("a{{end}}");
+        assert_eq!(stray_end.kind, TemplateErrorKind::UnexpectedBlockEnd("end"));
+        assert_eq!(stray_end.offset, 1);
+
+        assert_eq!(error("{{else}}").kind, TemplateErrorKind::UnexpectedBlockEnd("else"));
+        assert_eq!(error("{{elif x}}").kind, TemplateErrorKind::UnexpectedBlockEnd("elif"));
+
+        let second_else = error("{{if a}}x{{else}}y{{else}}z{{end}}");
+        assert_eq!(second_else.kind, TemplateErrorKind::UnexpectedBlockEnd("else"));
+        assert_eq!(second_else.offset, 18);
+
+        let else_in_each = error("{{each t in xs}}{{else}}{{end}}");
+        assert_eq!(else_in_each.kind, TemplateErrorKind::UnexpectedBlockEnd("else"));
+        assert_eq!(else_in_each.offset, 16);
+
+        let end_with_expression = error("{{if a}}{{end a}}");
+        assert!(matches!(end_with_expression.kind, TemplateErrorKind::InvalidBlock { block: "end", .. }));
+        assert_eq!(end_with_expression.offset, 8);
+
+        assert!(matches!(error("{{each in xs}}{{end}}").kind, TemplateErrorKind::InvalidBlock { block: "each", .. }));
+        assert!(matches!(error("{{let x == 1}}").kind, TemplateErrorKind::InvalidBlock { block: "let", .. }));
+        assert!(matches!(error("{{if }}{{end}}").kind, TemplateErrorKind::InvalidExpression(_)));
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        let unknown = error("Hi\n  {{nope}}");
+        assert_eq!(unknown.kind, TemplateErrorKind::UnknownPlaceholder("nope".to_string()));
+        assert_eq!((unknown.offset, unknown.line, unknown.column), (5, 2, 3));
+        assert_eq!(unknown.to_string(), "line 2, column 3: unknown placeholder {{nope}}");
+
+        // Columns count characters, offsets count bytes
+        let after_accent = error("é {{nope}}");
+        assert_eq!((after_accent.offset, after_accent.column), (3, 3));
+
+        let unclosed = error("x {{clipboard");
+        assert_eq!((unclosed.kind, unclosed.offset), (TemplateErrorKind::Unclosed, 2));
+        assert_eq!(error("{{input:x").kind, TemplateErrorKind::Unclosed);
+        assert_eq!(error(r"{{input:x\").kind, TemplateErrorKind::Unclosed);
+
+        let quote = error(r#"{{input:x:"abc}}"#);
+        assert_eq!((quote.kind, quote.offset), (TemplateErrorKind::UnterminatedQuote, 10));
+
+        let no_name = error("{{ :x}}");
+        assert_eq!((no_name.kind, no_name.offset), (TemplateErrorKind::MissingName, 3));
+
+        let stray = error("{{clipboard x}}");
+        assert_eq!((stray.kind, stray.offset), (TemplateErrorKind::UnexpectedCharacter('x'), 12));
+
+        let no_filter = error("{{clipboard|}}");
+        assert_eq!((no_filter.kind, no_filter.offset), (TemplateErrorKind::MissingFilterName, 11));
+    }
+
+    #[test]
+    fn checks_argument_counts_and_literal_arguments() {
+        assert_eq!(error("{{clipboard:x}}").kind, TemplateErrorKind::UnexpectedArgument("clipboard"));
+        assert_eq!(error("{{select:size}}").kind, TemplateErrorKind::MissingArgument("select"));
+        assert_eq!(error("{{date}}").kind, TemplateErrorKind::MissingArgument("date"));
+
+        let invalid = |source: &str| match error(source).kind {
+            TemplateErrorKind::InvalidArgument { reason, .. } => reason,
+            kind => panic!("expected an invalid argument in {:?}, got {:?}", source, kind),
+        };
+        assert_eq!(invalid("{{input:{{clipboard}}}}"), "field name cannot contain placeholders");
+        assert_eq!(invalid("{{input: }}"), "field name is empty");
+        assert_eq!(invalid("{{select:size:S,{{clipboard}}}}"), "options cannot contain placeholders");
+        assert_eq!(invalid("{{multiselect:size:{{env:SIZES}}}}"), "options cannot contain placeholders");
+        assert!(parse("{{input:name:{{clipboard}}}}").is_ok());
+        assert_eq!(invalid("{{var:}}"), "variable name is empty");
+        assert_eq!(invalid("{{cursor:0}}"), "tab stop must be a number from 1 to 9999");
+        assert!(parse("{{cursor:2}} {{random:1-6}} {{shell:date:timeout=5}}").is_ok());
+    }
+
+    #[test]
+    fn command_placeholders_must_be_single_quoted() {
+        for source in [
+            "{{shell:echo '{{clipboard}}'}}",
+            "{{shell:grep -c 'x{{clipboard}}y' ~/notes | wc -l}}",
+            r#"{{shell:"echo '"{{clipboard}}"' \"it's\""}}"#,
+            r"{{shell:echo \\' '{{clipboard}}'}}",
+            "{{shell:echo hi:env=X={{clipboard}}}}",
+            "{{script:open {{clipboard}}}}",
+        ] {
+            assert!(parse(source).is_ok(), "{}", source);
+        }
+
+        for source in [
+            "{{shell:echo {{clipboard}}}}",
+            r#"{{shell:echo \"{{clipboard}}\"}}"#,
+            r"{{shell:echo \\'{{clipboard}}\\'}}",
+            "{{shell:echo 'a' {{clipboard}}}}",
+            "{{shell:echo 'it'{{clipboard}}}}",
+        ] {
+            let error = error(source);
+            assert!(
+                matches!(&error.kind, TemplateErrorKind::InvalidArgument { placeholder: "shell", reason } if reason.contains("single quotes")),
+                "{}: {:?}",
+                source,
+                error
+            );
+        }
+    }
+}
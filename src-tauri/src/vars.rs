@@ -1,71 +1,1476 @@
-use regex::Regex;
-use chrono::Local;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
 use copypasta::{ClipboardContext, ClipboardProvider};
-use evalexpr::eval;
-
-lazy_static::lazy_static! {
-    static ref DATE_REGEX: Regex = Regex::new(r"\{\{date:([^}]+)\}\}").unwrap();
-    static ref TIME_REGEX: Regex = Regex::new(r"\{\{time:([^}]+)\}\}").unwrap();
-    static ref CLIPBOARD_REGEX: Regex = Regex::new(r"\{\{clipboard\}\}").unwrap();
-    static ref CURSOR_REGEX: Regex = Regex::new(r"\{\{cursor\}\}").unwrap();
-    static ref INPUT_REGEX: Regex = Regex::new(r"\{\{input:([^:}]+):([^}]*)\}\}").unwrap();
-    static ref SELECT_REGEX: Regex = Regex::new(r"\{\{select:([^:}]+):([^}]+)\}\}").unwrap();
-    static ref CALC_REGEX: Regex = Regex::new(r"\{\{calc:\s*([^}]+)\}\}").unwrap();
-    static ref ENV_REGEX: Regex = Regex::new(r"\{\{env:([^}]+)\}\}").unwrap();
-}
-
-pub fn render_snippet(body: &str) -> String {
-    let mut result = body.to_string();
-    
-    // Replace date variables
-    result = DATE_REGEX.replace_all(&result, |caps: &regex::Captures| {
-        let format = &caps[1];
-        Local::now().format(format).to_string()
-    }).to_string();
-    
-    // Replace time variables
-    result = TIME_REGEX.replace_all(&result, |caps: &regex::Captures| {
-        let format = &caps[1];
-        Local::now().format(format).to_string()
-    }).to_string();
-    
-    // Replace clipboard
-    result = CLIPBOARD_REGEX.replace_all(&result, |_caps: &regex::Captures| {
-        let mut ctx = ClipboardContext::new().unwrap();
-        ctx.get_contents().unwrap_or_default()
-    }).to_string();
-    
-    // Replace cursor (remove for now, handle placement separately)
-    result = CURSOR_REGEX.replace_all(&result, "").to_string();
-    
-    // Replace calc expressions
-    result = CALC_REGEX.replace_all(&result, |caps: &regex::Captures| {
-        let expression = &caps[1];
-        match eval(expression) {
-            Ok(value) => value.to_string(),
-            Err(_) => format!("{{{{calc: {}}}}}", expression), // Keep original if error
-        }
-    }).to_string();
-    
-    // Replace environment variables
-    result = ENV_REGEX.replace_all(&result, |caps: &regex::Captures| {
-        let env_var = &caps[1];
-        std::env::var(env_var).unwrap_or_default()
-    }).to_string();
-    
-    // TODO: Handle input and select variables with modal dialogs
-    
+use evalexpr::{ContextWithMutableFunctions, ContextWithMutableVariables, EvalexprError, Function, HashMapContext, Value};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::commands::{self, CommandOptions, CommandPart};
+use crate::database::{Variable, VariableDefinition};
+use crate::generate::{self, LoremLength};
+use crate::template::{
+    self, literal_arg, split_arg, EachBlock, Expr, Filter, IfBlock, Node, Placeholder, PlaceholderKind, TemplateError,
+    TemplateErrorKind,
+};
+
+// Cursor markers survive rendering as private-use characters, so their final
+// position accounts for everything substituted around them. Stop n is
+// CURSOR_MARKER_BASE + n; a plain {{cursor}} is stop 0.
+const CURSOR_MARKER_BASE: u32 = 0xF0000;
+
+// How many {{snippet:...}} includes may nest inside each other
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// A value the user fills in before the snippet can be expanded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FormField {
+    pub name: String,
+    pub kind: FieldKind,
+    // For multiselect fields, the values chosen at first, comma-separated
+    pub default: String,
+    // Choices for select fields, in the order written
+    pub options: Vec<String>,
+    // Regex every submitted value must match in full, from the snippet's
+    // variable definition
+    pub validation: Option<String>,
+}
+
+impl FormField {
+    // What the field holds when the user leaves it alone
+    fn default_value(&self) -> FieldValue {
+        match self.kind {
+            FieldKind::MultiSelect => FieldValue::Many(
+                self.default
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            _ => FieldValue::One(self.default.clone()),
+        }
+    }
+
+    // Checks a submitted value against the field's validation pattern
+    pub fn check(&self, value: &FieldValue) -> Result<(), String> {
+        let Some(pattern) = &self.validation else {
+            return Ok(());
+        };
+        let regex = validation_regex(pattern).map_err(|e| format!("Invalid validation pattern: {}", e))?;
+        let values = match value {
+            FieldValue::One(value) => std::slice::from_ref(value),
+            FieldValue::Many(values) => values.as_slice(),
+        };
+        match values.iter().find(|value| !regex.is_match(value)) {
+            Some(value) => Err(format!("{}: {:?} does not match {}", self.name, value, pattern)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    Input,
+    Select,
+    MultiSelect,
+}
+
+impl FieldKind {
+    // Variable types name field kinds the way the form sees them
+    pub fn parse(value: &str) -> Option<FieldKind> {
+        match value {
+            "input" => Some(FieldKind::Input),
+            "select" => Some(FieldKind::Select),
+            "multi_select" => Some(FieldKind::MultiSelect),
+            _ => None,
+        }
+    }
+}
+
+// What the form returns for a field: one string, or every value chosen in a
+// multiselect
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl FieldValue {
+    fn to_text(&self) -> String {
+        match self {
+            FieldValue::One(value) => value.clone(),
+            FieldValue::Many(values) => values.join(", "),
+        }
+    }
+}
+
+pub type FormValues = HashMap<String, FieldValue>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderStep {
+    // Rendered text still holding cursor markers, see take_cursor_stops
+    Done(String),
+    // The snippet has form fields; render again with their values
+    NeedsInput(Vec<FormField>),
+}
+
+// Checks a snippet body without rendering it
+pub fn validate(body: &str) -> Result<(), TemplateError> {
+    let nodes = template::parse(body)?;
+    let mut result = Ok(());
+    template::walk(&nodes, &mut |placeholder| {
+        for filter in &placeholder.filters {
+            if result.is_ok() {
+                result = find_filter(filter, body).map(|_| ());
+            }
+        }
+    });
     result
 }
 
-pub fn has_cursor_marker(body: &str) -> bool {
-    CURSOR_REGEX.is_match(body)
+// Checks a variable before it is saved
+pub fn validate_variable(variable: &VariableDefinition) -> Result<(), String> {
+    if variable.key.trim().is_empty() {
+        return Err("Variable key cannot be empty".to_string());
+    }
+    if FieldKind::parse(&variable.var_type).is_none() {
+        return Err(format!("Unknown variable type: {}", variable.var_type));
+    }
+    if let Some(pattern) = &variable.validation {
+        validation_regex(pattern).map_err(|e| format!("Invalid validation pattern: {}", e))?;
+    }
+    Ok(())
+}
+
+// Validation patterns describe the whole value, not a part of it
+fn validation_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+// First step of an interactive expansion. Without values, snippets that have
+// form fields stop and ask for them instead of rendering. Only trusted
+// snippets may run {{shell}} and {{script}} commands. Variables define the
+// snippet's form fields further, see Renderer::apply_variable. Dates and
+// times are relative to `now`. `snippet_id` is the snippet being expanded,
+// which nothing it includes may include again.
+pub fn render_step(
+    snippet_id: i64,
+    body: &str,
+    trusted: bool,
+    captures: &HashMap<String, String>,
+    variables: &[Variable],
+    values: Option<&FormValues>,
+    now: DateTime<Utc>,
+) -> Result<RenderStep, TemplateError> {
+    let nodes = template::parse(body)?;
+    let no_values = HashMap::new();
+    let state = RenderState::new(snippet_id, now);
+    let renderer = Renderer {
+        source: body,
+        trusted,
+        captures,
+        variables,
+        values: &no_values,
+        state: &state,
+    };
+
+    let fields = renderer.form_fields(&nodes)?;
+    if values.is_none() && !fields.is_empty() {
+        return Ok(RenderStep::NeedsInput(fields));
+    }
+
+    // Fields missing from the values keep their defaults
+    let mut values = values.cloned().unwrap_or_default();
+    for field in &fields {
+        values.entry(field.name.clone()).or_insert_with(|| field.default_value());
+    }
+    let renderer = Renderer { values: &values, ..renderer };
+
+    renderer.bind_inputs(&fields);
+    Ok(RenderStep::Done(renderer.render(&nodes)?))
+}
+
+// Whether the body itself has {{shell}} or {{script}} placeholders
+pub fn runs_commands(body: &str) -> bool {
+    !command_sources(body).is_empty()
+}
+
+// Whether `new` has a command that `old` does not, written exactly the same.
+// Approving a snippet approves the commands it had then.
+pub fn adds_commands(old: &str, new: &str) -> bool {
+    let old = command_sources(old);
+    command_sources(new).iter().any(|source| !old.contains(source))
+}
+
+// Source text of every {{shell}} and {{script}} placeholder in the body
+fn command_sources(body: &str) -> Vec<&str> {
+    let Ok(nodes) = template::parse(body) else {
+        return Vec::new();
+    };
+    let mut sources = Vec::new();
+    template::walk(&nodes, &mut |placeholder| {
+        if matches!(placeholder.kind, PlaceholderKind::Shell | PlaceholderKind::Script) {
+            sources.push(&body[placeholder.span.clone()]);
+        }
+    });
+    sources
+}
+
+// Whether expanding can wait on a form, a command or a counter, and so should
+// not run on the keyboard hook. Broken templates report false and fail on
+// render.
+pub fn may_block(snippet_id: i64, body: &str, trusted: bool) -> bool {
+    let Ok(nodes) = template::parse(body) else {
+        return false;
+    };
+    let no_captures = HashMap::new();
+    let no_values = HashMap::new();
+    let state = RenderState::new(snippet_id, Utc::now());
+    let renderer = Renderer {
+        source: body,
+        trusted,
+        captures: &no_captures,
+        variables: &[],
+        values: &no_values,
+        state: &state,
+    };
+    renderer.may_block(&nodes).unwrap_or(false)
+}
+
+// Shared by a snippet and every snippet it includes
+struct RenderState {
+    // Ids of the snippet being expanded and of those it is including,
+    // outermost first, each with the reference that named it
+    includes: RefCell<Vec<(i64, String)>>,
+    // Expression variables: input.*, capture.*, let bindings and loop items
+    variables: RefCell<HashMapContext>,
+    // Read once so every date in a snippet agrees
+    now: DateTime<Utc>,
+    // Input defaults with placeholders in them, by field name, rendered the
+    // first time an empty field needs one
+    computed_defaults: RefCell<HashMap<String, String>>,
+}
+
+impl RenderState {
+    fn new(snippet_id: i64, now: DateTime<Utc>) -> Self {
+        let mut variables = HashMapContext::new();
+        // Form values are strings; num() lets expressions compare them as
+        // numbers, e.g. `num(input.qty) > 3`
+        let _ = variables.set_function(
+            "num".to_string(),
+            Function::new(|argument| {
+                let text = argument.as_string()?;
+                let text = text.trim();
+                text.parse::<i64>()
+                    .map(Value::Int)
+                    .or_else(|_| text.parse::<f64>().map(Value::Float))
+                    .map_err(|_| EvalexprError::CustomMessage(format!("{:?} is not a number", text)))
+            }),
+        );
+        RenderState {
+            includes: RefCell::new(vec![(snippet_id, format!("#{}", snippet_id))]),
+            variables: RefCell::new(variables),
+            now,
+            computed_defaults: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+// Evaluates a parsed template in one pass; substituted text is never parsed
+// again, so a clipboard holding `{{env:HOME}}` is pasted as is
+struct Renderer<'a> {
+    source: &'a str,
+    // False if this snippet, or any snippet including it, is not trusted
+    trusted: bool,
+    captures: &'a HashMap<String, String>,
+    // This snippet's own variable definitions
+    variables: &'a [Variable],
+    values: &'a FormValues,
+    state: &'a RenderState,
 }
 
-pub fn get_cursor_position(body: &str) -> Option<usize> {
-    if let Some(mat) = CURSOR_REGEX.find(body) {
-        Some(mat.start())
-    } else {
-        None
+impl<'a> Renderer<'a> {
+    fn render(&self, nodes: &[Node]) -> Result<String, TemplateError> {
+        let mut output = String::new();
+        for node in nodes {
+            match node {
+                Node::Text(text) | Node::Literal(text) => output.push_str(text),
+                Node::Placeholder(placeholder) => output.push_str(&self.placeholder(placeholder)?),
+                Node::If(block) => output.push_str(&self.render_if(block)?),
+                Node::Each(block) => output.push_str(&self.render_each(block)?),
+                Node::Let(binding) => {
+                    let value = self.evaluate(&binding.value)?;
+                    self.set_variable(&binding.name, value);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn render_if(&self, block: &IfBlock) -> Result<String, TemplateError> {
+        for (condition, body) in &block.branches {
+            let matched = match self.evaluate(condition)? {
+                Value::Boolean(matched) => matched,
+                other => {
+                    let reason = format!("condition `{}` is {}, not true or false", condition.source, other);
+                    return Err(self.expression_error(condition, reason));
+                }
+            };
+            if matched {
+                return self.render(body);
+            }
+        }
+        self.render(&block.otherwise)
+    }
+
+    // Repeats the body for each item of a tuple such as a multiselect's
+    // values; any other value counts as one item
+    fn render_each(&self, block: &EachBlock) -> Result<String, TemplateError> {
+        let items = match self.evaluate(&block.items)? {
+            Value::Tuple(items) => items,
+            Value::Empty => vec![],
+            item => vec![item],
+        };
+
+        let previous = self.variable(&block.variable);
+        let mut output = String::new();
+        for item in items {
+            self.set_variable(&block.variable, item);
+            output.push_str(&self.render(&block.body)?);
+        }
+        self.rebind_variable(&block.variable, previous);
+        Ok(output)
+    }
+
+    fn evaluate(&self, expr: &Expr) -> Result<Value, TemplateError> {
+        let variables = self.state.variables.borrow();
+        expr.tree
+            .eval_with_context(&*variables)
+            .map_err(|e| self.expression_error(expr, e.to_string()))
+    }
+
+    fn expression_error(&self, expr: &Expr, reason: String) -> TemplateError {
+        TemplateError::new(TemplateErrorKind::InvalidExpression(reason), self.source, expr.offset)
+    }
+
+    fn variable(&self, name: &str) -> Option<Value> {
+        use evalexpr::Context;
+        self.state.variables.borrow().get_value(name).cloned()
+    }
+
+    fn set_variable(&self, name: &str, value: Value) {
+        self.rebind_variable(name, Some(value));
     }
-}
\ No newline at end of file
+
+    // The context refuses to give a variable a value of another type and
+    // cannot forget one, so a loop item or let that changes type, or a loop
+    // variable going out of scope, rebuilds the variables without it
+    fn rebind_variable(&self, name: &str, value: Option<Value>) {
+        use evalexpr::IterateVariablesContext;
+        let mut variables = self.state.variables.borrow_mut();
+        if let Some(value) = &value {
+            if variables.set_value(name.to_string(), value.clone()).is_ok() {
+                return;
+            }
+        }
+
+        let kept: Vec<(String, Value)> = variables
+            .iter_variables()
+            .filter(|(other, _)| other != name)
+            .collect();
+        variables.clear_variables();
+        for (other, value) in kept.into_iter().chain(value.map(|value| (name.to_string(), value))) {
+            let _ = variables.set_value(other, value);
+        }
+    }
+
+    // Makes form values and regex captures visible to expressions
+    fn bind_inputs(&self, fields: &[FormField]) {
+        for (name, value) in self.captures {
+            self.set_variable(&format!("capture.{}", name), Value::String(value.clone()));
+        }
+
+        for field in fields {
+            let value = match (self.values.get(&field.name), field.kind) {
+                (Some(FieldValue::Many(values)), _) => {
+                    Value::Tuple(values.iter().cloned().map(Value::String).collect())
+                }
+                (Some(FieldValue::One(value)), FieldKind::MultiSelect) => Value::Tuple(vec![Value::String(value.clone())]),
+                (Some(FieldValue::One(value)), _) => Value::String(value.clone()),
+                (None, FieldKind::MultiSelect) => Value::Tuple(vec![]),
+                (None, _) => Value::String(field.default.clone()),
+            };
+            self.set_variable(&format!("input.{}", field.name), value);
+        }
+    }
+
+    fn arg(&self, placeholder: &Placeholder, index: usize) -> Result<String, TemplateError> {
+        match placeholder.args.get(index) {
+            Some(arg) => self.render(arg),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn placeholder(&self, placeholder: &Placeholder) -> Result<String, TemplateError> {
+        let mut output = self.placeholder_value(placeholder)?;
+        for filter in &placeholder.filters {
+            let definition = find_filter(filter, self.source)?;
+            let mut args = Vec::with_capacity(filter.args.len());
+            for arg in &filter.args {
+                args.push(self.render(arg)?);
+            }
+            output = (definition.apply)(&output, &args).map_err(|reason| {
+                let kind = TemplateErrorKind::InvalidFilter { filter: filter.name.clone(), reason };
+                TemplateError::new(kind, self.source, filter.offset)
+            })?;
+        }
+        Ok(output)
+    }
+
+    fn placeholder_value(&self, placeholder: &Placeholder) -> Result<String, TemplateError> {
+        let invalid = |reason: String| {
+            TemplateError::invalid_argument(placeholder.kind, reason, self.source, placeholder.span.start)
+        };
+
+        match placeholder.kind {
+            PlaceholderKind::Date | PlaceholderKind::Time => {
+                crate::dates::format(&self.arg(placeholder, 0)?, self.state.now).map_err(invalid)
+            }
+            PlaceholderKind::Clipboard => Ok(ClipboardContext::new()
+                .ok()
+                .and_then(|mut ctx| ctx.get_contents().ok())
+                .unwrap_or_default()),
+            PlaceholderKind::Cursor => {
+                let stop = placeholder
+                    .args
+                    .first()
+                    .and_then(|arg| literal_arg(arg))
+                    .and_then(|stop| stop.trim().parse().ok())
+                    .unwrap_or(0);
+                Ok(char::from_u32(CURSOR_MARKER_BASE + stop).unwrap().to_string())
+            }
+            PlaceholderKind::Input => self.input_value(placeholder),
+            PlaceholderKind::Select => match self.values.get(&field_name(placeholder)) {
+                Some(value) => Ok(value.to_text()),
+                None => Ok(field_options(placeholder).into_iter().next().unwrap_or_default()),
+            },
+            PlaceholderKind::MultiSelect => Ok(self
+                .values
+                .get(&field_name(placeholder))
+                .map(FieldValue::to_text)
+                .unwrap_or_default()),
+            PlaceholderKind::Calc => {
+                let expression = self.arg(placeholder, 0)?;
+                let variables = self.state.variables.borrow();
+                evalexpr::eval_with_context(expression.trim(), &*variables)
+                    .map(|value| value_to_text(&value))
+                    .map_err(|e| invalid(e.to_string()))
+            }
+            PlaceholderKind::Env => Ok(std::env::var(self.arg(placeholder, 0)?.trim()).unwrap_or_default()),
+            PlaceholderKind::Capture => {
+                let name = self.arg(placeholder, 0)?;
+                Ok(self.captures.get(name.trim()).cloned().unwrap_or_default())
+            }
+            PlaceholderKind::Snippet => {
+                let reference = self.arg(placeholder, 0)?.trim().to_string();
+                self.include(placeholder, reference, |renderer, nodes| renderer.render(nodes))
+            }
+            PlaceholderKind::Var => {
+                let name = field_name(placeholder);
+                match self.variable(&name) {
+                    Some(value) => Ok(value_to_text(&value)),
+                    None => Err(invalid(format!("no variable named {}", name))),
+                }
+            }
+            PlaceholderKind::Shell | PlaceholderKind::Script => self.run_command(placeholder),
+            PlaceholderKind::Uuid => Ok(generate::uuid()),
+            PlaceholderKind::Random => {
+                let (min, max) = generate::parse_range(&self.arg(placeholder, 0)?).map_err(invalid)?;
+                Ok(generate::random(min, max).to_string())
+            }
+            PlaceholderKind::RandomChoice => {
+                let choices = self.choices(&placeholder.args[0])?;
+                generate::random_choice(&choices)
+                    .cloned()
+                    .ok_or_else(|| invalid("no choices to pick from".to_string()))
+            }
+            PlaceholderKind::Lorem => {
+                let length = LoremLength::parse(&self.arg(placeholder, 0)?).map_err(invalid)?;
+                Ok(generate::lorem(length))
+            }
+            PlaceholderKind::Counter => {
+                let name = self.arg(placeholder, 0)?;
+                if name.trim().is_empty() {
+                    return Err(invalid("counter name is empty".to_string()));
+                }
+                // Used up even if the expansion fails later, see counters::next
+                crate::counters::next(name.trim()).map(|value| value.to_string()).map_err(invalid)
+            }
+        }
+    }
+
+    // A default with placeholders in it is left out of the form, so that
+    // nothing renders before the snippet expands. An empty field gets it
+    // rendered here instead, once, and every later use of the field reuses it.
+    fn input_value(&self, placeholder: &Placeholder) -> Result<String, TemplateError> {
+        let name = field_name(placeholder);
+        let value = self.values.get(&name).map(FieldValue::to_text);
+        if value.as_deref().map_or(true, str::is_empty) {
+            if let Some(computed) = self.state.computed_defaults.borrow().get(&name) {
+                return Ok(computed.clone());
+            }
+            if let Some(default) = placeholder.args.get(1).filter(|default| literal_arg(default).is_none()) {
+                let computed = self.render(default)?;
+                self.state
+                    .computed_defaults
+                    .borrow_mut()
+                    .insert(name, computed.clone());
+                return Ok(computed);
+            }
+        }
+
+        match value {
+            Some(value) => Ok(value),
+            None => self.arg(placeholder, 1),
+        }
+    }
+
+    fn run_command(&self, placeholder: &Placeholder) -> Result<String, TemplateError> {
+        let error = |kind| TemplateError::new(kind, self.source, placeholder.span.start);
+        if !self.trusted {
+            return Err(error(TemplateErrorKind::Untrusted(placeholder.kind.name())));
+        }
+
+        // Nested placeholders render to values the command reads as data,
+        // see commands::run_shell
+        let mut command_line = Vec::new();
+        for node in &placeholder.args[0] {
+            command_line.push(match node {
+                Node::Placeholder(nested) => CommandPart::Value(self.placeholder(nested)?),
+                node => CommandPart::Text(self.render(std::slice::from_ref(node))?),
+            });
+        }
+        let mut options = Vec::new();
+        if let Some(arg) = placeholder.args.get(1) {
+            for option in split_arg(arg, ',', usize::MAX) {
+                options.push(self.render(&option)?);
+            }
+        }
+        let options = CommandOptions::parse(options.iter().map(String::as_str)).map_err(|reason| {
+            TemplateError::invalid_argument(placeholder.kind, reason, self.source, placeholder.span.start)
+        })?;
+
+        let output = if placeholder.kind == PlaceholderKind::Shell {
+            commands::run_shell(&command_line, &options)
+        } else {
+            commands::run_script(&command_line, &options)
+        };
+        output.map_err(|reason| {
+            error(TemplateErrorKind::CommandFailed {
+                placeholder: placeholder.kind.name(),
+                reason,
+            })
+        })
+    }
+
+    fn may_block(&self, nodes: &[Node]) -> Result<bool, TemplateError> {
+        let mut placeholders = Vec::new();
+        template::walk(nodes, &mut |placeholder| placeholders.push(placeholder));
+
+        // Checked before includes, whose references could themselves run a
+        // command while being resolved
+        let blocks = placeholders.iter().any(|placeholder| match placeholder.kind {
+            PlaceholderKind::Input | PlaceholderKind::Select | PlaceholderKind::MultiSelect => true,
+            PlaceholderKind::Shell | PlaceholderKind::Script => self.trusted,
+            // Waits on the database
+            PlaceholderKind::Counter => true,
+            _ => false,
+        });
+        if blocks {
+            return Ok(true);
+        }
+
+        for placeholder in placeholders {
+            if placeholder.kind != PlaceholderKind::Snippet {
+                continue;
+            }
+            // Which snippet a reference with placeholders names is only known
+            // once rendered, so it could be any
+            let Some(reference) = literal_arg(&placeholder.args[0]) else {
+                return Ok(true);
+            };
+            if self.include(placeholder, reference.trim().to_string(), |renderer, nodes| renderer.may_block(nodes))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Runs `f` over the body of the snippet `reference` names, rendered with
+    // the same captures, form values and variables as the snippet including it
+    fn include<T>(
+        &self,
+        placeholder: &Placeholder,
+        reference: String,
+        f: impl FnOnce(&Renderer, &[Node]) -> Result<T, TemplateError>,
+    ) -> Result<T, TemplateError> {
+        let error = |kind| TemplateError::new(kind, self.source, placeholder.span.start);
+
+        let Some(included) = crate::engine::snippet_body(&reference) else {
+            return Err(error(TemplateErrorKind::SnippetNotFound(reference)));
+        };
+
+        {
+            // By id, since `#12` and a shortcut can name the same snippet
+            let includes = self.state.includes.borrow();
+            if includes.iter().any(|(id, _)| *id == included.id) {
+                let mut chain: Vec<String> = includes.iter().map(|(_, reference)| reference.clone()).collect();
+                chain.push(reference);
+                return Err(error(TemplateErrorKind::IncludeCycle(chain)));
+            }
+            // The first entry is the snippet being expanded, not an include
+            if includes.len() > MAX_INCLUDE_DEPTH {
+                return Err(error(TemplateErrorKind::IncludeTooDeep(MAX_INCLUDE_DEPTH)));
+            }
+        }
+
+        let in_included = |inner: TemplateError| {
+            error(TemplateErrorKind::InIncludedSnippet {
+                reference: reference.clone(),
+                error: Box::new(inner),
+            })
+        };
+        let nodes = template::parse(&included.body).map_err(&in_included)?;
+
+        self.state.includes.borrow_mut().push((included.id, reference.clone()));
+        let renderer = Renderer {
+            source: &included.body,
+            trusted: self.trusted && included.trusted,
+            captures: self.captures,
+            variables: &included.variables,
+            values: self.values,
+            state: self.state,
+        };
+        let result = f(&renderer, &nodes).map_err(&in_included);
+        self.state.includes.borrow_mut().pop();
+        result
+    }
+
+    // Comma-separated, trimmed and without empty entries
+    fn choices(&self, arg: &[Node]) -> Result<Vec<String>, TemplateError> {
+        let mut choices = Vec::new();
+        for choice in split_arg(arg, ',', usize::MAX) {
+            let choice = self.render(&choice)?.trim().to_string();
+            if !choice.is_empty() {
+                choices.push(choice);
+            }
+        }
+        Ok(choices)
+    }
+
+    // Form fields in order of first appearance, including those of included
+    // snippets and of every branch. A name used more than once is asked for
+    // once and fills every occurrence. Fields come from literal arguments
+    // only, so collecting them never runs a command or bumps a counter;
+    // snippets included by a reference with placeholders in it add none.
+    fn form_fields(&self, nodes: &[Node]) -> Result<Vec<FormField>, TemplateError> {
+        let mut fields = Vec::new();
+        self.collect_fields(nodes, &mut fields)?;
+        Ok(fields)
+    }
+
+    fn collect_fields(&self, nodes: &[Node], fields: &mut Vec<FormField>) -> Result<(), TemplateError> {
+        let mut placeholders = Vec::new();
+        template::walk(nodes, &mut |placeholder| {
+            if matches!(
+                placeholder.kind,
+                PlaceholderKind::Input
+                    | PlaceholderKind::Select
+                    | PlaceholderKind::MultiSelect
+                    | PlaceholderKind::Snippet
+            ) {
+                placeholders.push(placeholder);
+            }
+        });
+
+        for placeholder in placeholders {
+            if placeholder.kind == PlaceholderKind::Snippet {
+                if let Some(reference) = literal_arg(&placeholder.args[0]) {
+                    let reference = reference.trim().to_string();
+                    self.include(placeholder, reference, |renderer, nodes| renderer.collect_fields(nodes, fields))?;
+                }
+                continue;
+            }
+
+            let name = field_name(placeholder);
+            if fields.iter().any(|field| field.name == name) {
+                continue;
+            }
+
+            let mut field = match placeholder.kind {
+                PlaceholderKind::Select => {
+                    let options = field_options(placeholder);
+                    FormField {
+                        name,
+                        kind: FieldKind::Select,
+                        default: options.first().cloned().unwrap_or_default(),
+                        options,
+                        validation: None,
+                    }
+                }
+                PlaceholderKind::MultiSelect => FormField {
+                    name,
+                    kind: FieldKind::MultiSelect,
+                    default: String::new(),
+                    options: field_options(placeholder),
+                    validation: None,
+                },
+                _ => FormField {
+                    name,
+                    kind: FieldKind::Input,
+                    default: placeholder
+                        .args
+                        .get(1)
+                        .and_then(|default| literal_arg(default))
+                        .unwrap_or_default(),
+                    options: vec![],
+                    validation: None,
+                },
+            };
+            self.apply_variable(&mut field);
+            fields.push(field);
+        }
+        Ok(())
+    }
+
+    // A variable with the field's name overrides what the placeholder says:
+    // its type, options, default and validation. Variables of other types,
+    // such as those kept from an espanso import, leave the field alone.
+    fn apply_variable(&self, field: &mut FormField) {
+        let Some(variable) = self.variables.iter().find(|variable| variable.key == field.name) else {
+            return;
+        };
+        let Some(kind) = FieldKind::parse(&variable.var_type) else {
+            return;
+        };
+
+        if !variable.options.0.is_empty() {
+            field.options = variable.options.0.clone();
+        }
+        // A list field needs something to list
+        if kind == FieldKind::Input || !field.options.is_empty() {
+            field.kind = kind;
+        }
+        match &variable.default_value {
+            Some(default) => field.default = default.clone(),
+            None if field.kind == FieldKind::Select => {
+                field.default = field.options.first().cloned().unwrap_or_default();
+            }
+            None => {}
+        }
+        field.validation = variable.validation.clone();
+    }
+}
+
+struct FilterDefinition {
+    name: &'static str,
+    // Minimum and maximum number of arguments
+    arity: (usize, usize),
+    apply: fn(&str, &[String]) -> Result<String, String>,
+}
+
+// Every filter a placeholder can name, as in `{{clipboard|trim|upper}}`
+const FILTERS: &[FilterDefinition] = &[
+    FilterDefinition { name: "upper", arity: (0, 0), apply: |text, _| Ok(text.to_uppercase()) },
+    FilterDefinition { name: "lower", arity: (0, 0), apply: |text, _| Ok(text.to_lowercase()) },
+    FilterDefinition { name: "title", arity: (0, 0), apply: |text, _| Ok(title_case(text)) },
+    FilterDefinition { name: "trim", arity: (0, 0), apply: |text, _| Ok(text.trim().to_string()) },
+    FilterDefinition { name: "slug", arity: (0, 0), apply: |text, _| Ok(slug(text)) },
+    FilterDefinition { name: "urlencode", arity: (0, 0), apply: |text, _| Ok(url_encode(text)) },
+    FilterDefinition { name: "json_escape", arity: (0, 0), apply: |text, _| Ok(json_escape(text)) },
+    FilterDefinition { name: "base64", arity: (0, 0), apply: |text, _| Ok(base64(text.as_bytes())) },
+    FilterDefinition { name: "replace", arity: (2, 2), apply: replace },
+    FilterDefinition { name: "truncate", arity: (1, 2), apply: truncate },
+];
+
+fn find_filter(filter: &Filter, source: &str) -> Result<&'static FilterDefinition, TemplateError> {
+    let error = |kind| TemplateError::new(kind, source, filter.offset);
+    let Some(definition) = FILTERS.iter().find(|definition| definition.name == filter.name) else {
+        return Err(error(TemplateErrorKind::UnknownFilter(filter.name.clone())));
+    };
+
+    let (min, max) = definition.arity;
+    let count = filter.args.len();
+    if count < min || count > max {
+        let reason = match (min, max) {
+            (0, 0) => "takes no arguments".to_string(),
+            (min, max) if min == max => format!("takes {} argument{}", min, if min == 1 { "" } else { "s" }),
+            (min, max) => format!("takes {} to {} arguments", min, max),
+        };
+        return Err(error(TemplateErrorKind::InvalidFilter { filter: filter.name.clone(), reason }));
+    }
+    Ok(definition)
+}
+
+// Capitalizes the first letter of each word and lowercases the rest
+fn title_case(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut word_start = true;
+    for character in text.chars() {
+        if word_start {
+            output.extend(character.to_uppercase());
+        } else {
+            output.extend(character.to_lowercase());
+        }
+        word_start = !(character.is_alphanumeric() || character == '\'');
+    }
+    output
+}
+
+// Lowercase letters and digits joined by single dashes: "Hello, World!" is
+// "hello-world"
+fn slug(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for character in text.chars().flat_map(char::to_lowercase) {
+        if character.is_alphanumeric() {
+            slug.push(character);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// Percent-encodes everything but the characters RFC 3986 leaves unreserved
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// The text as it would appear inside a JSON string, without the quotes
+fn json_escape(text: &str) -> String {
+    let quoted = serde_json::Value::String(text.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// Standard alphabet, with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (chunk.get(1).copied().unwrap_or(0) as u32) << 8
+            | chunk.get(2).copied().unwrap_or(0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn replace(text: &str, args: &[String]) -> Result<String, String> {
+    if args[0].is_empty() {
+        return Err("text to replace is empty".to_string());
+    }
+    Ok(text.replace(&args[0], &args[1]))
+}
+
+// `truncate:40` keeps the first 40 characters; `truncate:40:…` also marks
+// where text was cut
+fn truncate(text: &str, args: &[String]) -> Result<String, String> {
+    let length: usize = args[0]
+        .trim()
+        .parse()
+        .map_err(|_| format!("length {:?} is not a whole number", args[0]))?;
+    let mut graphemes = text.grapheme_indices(true);
+    match graphemes.nth(length) {
+        Some((cut, _)) => {
+            let suffix = args.get(1).map(String::as_str).unwrap_or_default();
+            Ok(format!("{}{}", &text[..cut], suffix))
+        }
+        None => Ok(text.to_string()),
+    }
+}
+
+// evalexpr quotes strings when displaying them; snippets want the bare text
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Tuple(items) => items.iter().map(value_to_text).collect::<Vec<_>>().join(", "),
+        Value::Empty => String::new(),
+        other => other.to_string(),
+    }
+}
+
+// The parser guarantees field and variable names are literal text
+fn field_name(placeholder: &Placeholder) -> String {
+    literal_arg(&placeholder.args[0]).unwrap_or_default().trim().to_string()
+}
+
+// A select's options, which the parser also keeps literal. Comma-separated,
+// trimmed and without empty entries.
+fn field_options(placeholder: &Placeholder) -> Vec<String> {
+    split_arg(&placeholder.args[1], ',', usize::MAX)
+        .iter()
+        .filter_map(|option| literal_arg(option))
+        .map(|option| option.trim().to_string())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+// Removes cursor markers from rendered text. Returns the text and each tab
+// stop as a grapheme offset from the start, in the order Tab visits them:
+// numbered stops ascending, then a plain {{cursor}} last.
+pub fn take_cursor_stops(rendered: &str) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(rendered.len());
+    let mut markers: Vec<(u32, usize)> = Vec::new();
+
+    for character in rendered.chars() {
+        match cursor_stop(character) {
+            Some(stop) => {
+                if !markers.iter().any(|(existing, _)| *existing == stop) {
+                    markers.push((stop, text.len()));
+                }
+            }
+            None => text.push(character),
+        }
+    }
+
+    // Plain cursors sort after every numbered stop
+    markers.sort_by_key(|(stop, _)| if *stop == 0 { u32::MAX } else { *stop });
+
+    let stops = markers
+        .into_iter()
+        .map(|(_, byte_offset)| text[..byte_offset].graphemes(true).count())
+        .collect();
+
+    (text, stops)
+}
+
+fn cursor_stop(character: char) -> Option<u32> {
+    let code = character as u32;
+    (CURSOR_MARKER_BASE..=CURSOR_MARKER_BASE + 9999)
+        .contains(&code)
+        .then(|| code - CURSOR_MARKER_BASE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 9, 14, 5, 0).unwrap()
+    }
+
+    fn step(body: &str, values: Option<&FormValues>) -> Result<RenderStep, TemplateError> {
+        render_step(1, body, false, &HashMap::new(), &[], values, now())
+    }
+
+    fn fields(body: &str) -> Vec<FormField> {
+        match step(body, None).unwrap() {
+            RenderStep::NeedsInput(fields) => fields,
+            RenderStep::Done(rendered) => panic!("{:?} rendered without asking: {:?}", body, rendered),
+        }
+    }
+
+    // Renders with the given values, or with every field at its default
+    fn render(body: &str, values: &[(&str, FieldValue)]) -> Result<String, TemplateError> {
+        let values: FormValues = values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+        match step(body, Some(&values))? {
+            RenderStep::Done(rendered) => Ok(rendered),
+            RenderStep::NeedsInput(fields) => panic!("{:?} asked again for {:?}", body, fields),
+        }
+    }
+
+    fn one(value: &str) -> FieldValue {
+        FieldValue::One(value.to_string())
+    }
+
+    fn many(values: &[&str]) -> FieldValue {
+        FieldValue::Many(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    fn field(name: &str, kind: FieldKind, default: &str, options: &[&str]) -> FormField {
+        FormField {
+            name: name.to_string(),
+            kind,
+            default: default.to_string(),
+            options: options.iter().map(|option| option.to_string()).collect(),
+            validation: None,
+        }
+    }
+
+    #[test]
+    fn asks_for_fields_before_rendering() {
+        let body = "Dear {{input:name:there}}, size {{select:size:S, M,L}} {{multiselect:extras:gift,card}} \
+                    thanks, {{input:name}} {{input:note}}";
+        assert_eq!(
+            fields(body),
+            [
+                field("name", FieldKind::Input, "there", &[]),
+                field("size", FieldKind::Select, "S", &["S", "M", "L"]),
+                field("extras", FieldKind::MultiSelect, "", &["gift", "card"]),
+                field("note", FieldKind::Input, "", &[]),
+            ]
+        );
+        // Nothing to ask, nothing to wait for
+        assert_eq!(step("Plain {{date:%Y}}", None).unwrap(), RenderStep::Done("Plain 2024".to_string()));
+    }
+
+    #[test]
+    fn renders_the_submitted_values() {
+        let body = "Dear {{input:name:there}}, size {{select:size:S,M,L}}{{multiselect:extras:gift,card}}. {{input:name}}";
+        let rendered = render(body, &[("name", one("Ann")), ("size", one("M")), ("extras", many(&["gift", "card"]))]);
+        assert_eq!(rendered.unwrap(), "Dear Ann, size Mgift, card. Ann");
+
+        // Fields left out keep their defaults
+        assert_eq!(render(body, &[]).unwrap(), "Dear there, size S. there");
+        // The form shows the default, so an input submitted empty was cleared
+        assert_eq!(render(body, &[("name", one(""))]).unwrap(), "Dear , size S. ");
+    }
+
+
+    fn filter(name: &str, text: &str, args: &[&str]) -> Result<String, String> {
+        let definition = FILTERS.iter().find(|definition| definition.name == name).unwrap();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        (definition.apply)(text, &args)
+    }
+
+    #[test]
+    fn base64_pads_to_whole_groups() {
+        let encoded: Vec<String> = ["", "f", "fo", "foo", "foob", "fooba", "foobar"]
+            .iter()
+            .map(|text| filter("base64", text, &[]).unwrap())
+            .collect();
+        assert_eq!(encoded, ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"]);
+        assert_eq!(filter("base64", "é?>", &[]).unwrap(), "w6k/Pg==");
+    }
+
+    #[test]
+    fn urlencode_keeps_only_unreserved_characters() {
+        assert_eq!(filter("urlencode", "AZaz09-_.~", &[]).unwrap(), "AZaz09-_.~");
+        assert_eq!(filter("urlencode", "a b&c=d/e?f#g+h%", &[]).unwrap(), "a%20b%26c%3Dd%2Fe%3Ff%23g%2Bh%25");
+        assert_eq!(filter("urlencode", "café ✓", &[]).unwrap(), "caf%C3%A9%20%E2%9C%93");
+    }
+
+    #[test]
+    fn slug_and_title_reshape_words() {
+        assert_eq!(filter("slug", "Hello, World!", &[]).unwrap(), "hello-world");
+        assert_eq!(filter("slug", "  --Déjà vu?!  2024 -- ", &[]).unwrap(), "déjà-vu-2024");
+        assert_eq!(filter("slug", "?!", &[]).unwrap(), "");
+        assert_eq!(filter("title", "hello wORLD, o'neil-smith", &[]).unwrap(), "Hello World, O'neil-Smith");
+        assert_eq!(filter("title", "élan  vital", &[]).unwrap(), "Élan  Vital");
+    }
+
+    #[test]
+    fn truncate_counts_graphemes() {
+        // "é" written as e and a combining accent, and a thumb with a skin tone
+        let text = "he\u{301}llo👍🏽 world";
+        assert_eq!(filter("truncate", text, &["6"]).unwrap(), "he\u{301}llo👍🏽");
+        assert_eq!(filter("truncate", text, &["2", "…"]).unwrap(), "he\u{301}…");
+        assert_eq!(filter("truncate", text, &["12", "…"]).unwrap(), text);
+        assert_eq!(filter("truncate", "abc", &["0"]).unwrap(), "");
+        assert!(filter("truncate", "abc", &["-1"]).is_err());
+        assert!(filter("truncate", "abc", &["three"]).is_err());
+    }
+
+    #[test]
+    fn replace_needs_something_to_replace() {
+        assert_eq!(filter("replace", "a-b-c", &["-", " / "]).unwrap(), "a / b / c");
+        assert_eq!(filter("replace", "a-b-c", &["-", ""]).unwrap(), "abc");
+        assert!(filter("replace", "a-b-c", &["", "x"]).is_err());
+    }
+
+    #[test]
+    fn chains_filters_left_to_right() {
+        let body = r#"{{let name = "  ada LOVELACE  "}}{{var:name|trim|title|replace:Lovelace:King|truncate:5:…|upper}}"#;
+        assert_eq!(render(body, &[]).unwrap(), "ADA K…");
+        let body = r#"{{let query = "rust & tauri"}}https://x.test/?q={{var:query|urlencode}} {{var:query|slug|base64}}"#;
+        assert_eq!(render(body, &[]).unwrap(), "https://x.test/?q=rust%20%26%20tauri cnVzdC10YXVyaQ==");
+    }
+
+    #[test]
+    fn bad_filters_are_errors() {
+        let kind = |body: &str| render(body, &[]).unwrap_err().kind;
+        assert_eq!(kind("{{date:%Y|shout}}"), TemplateErrorKind::UnknownFilter("shout".to_string()));
+        assert!(matches!(kind("{{date:%Y|upper:x}}"), TemplateErrorKind::InvalidFilter { .. }));
+        assert!(matches!(kind("{{date:%Y|replace:x}}"), TemplateErrorKind::InvalidFilter { .. }));
+        assert!(matches!(kind("{{date:%Y|truncate}}"), TemplateErrorKind::InvalidFilter { .. }));
+        assert!(matches!(kind("{{date:%Y|truncate:1:2:3}}"), TemplateErrorKind::InvalidFilter { .. }));
+        assert!(matches!(kind("{{date:%Y|truncate:many}}"), TemplateErrorKind::InvalidFilter { .. }));
+
+        // Saving a snippet catches what does not depend on the values
+        assert!(validate("{{date:%Y|upper|shout}}").is_err());
+        assert!(validate("{{date:%Y|replace:x}}").is_err());
+        let error = validate("{{date:%Y|upper}}\n{{clipboard|truncate:1:2:3}}").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 12));
+        assert!(validate("{{date:%Y|upper|truncate:2}}").is_ok());
+    }
+
+    // Snippets for {{snippet:...}} to include, installed once for every test
+    fn install_snippets() {
+        static INSTALLED: std::sync::Once = std::sync::Once::new();
+        INSTALLED.call_once(|| {
+            let mut snippets = vec![
+                (101, "/self", "me, {{snippet:/self}}".to_string()),
+                (102, "/a", "a({{snippet:/b}})".to_string()),
+                (103, "/b", "b({{snippet:#102}})".to_string()),
+                (104, "/sig", "Ada".to_string()),
+                (105, "/twice", "{{snippet:/sig}} and {{snippet:#104}}".to_string()),
+                (106, "/form", "{{input:name:Ada}} in {{snippet:/city}}".to_string()),
+                (107, "/city", "{{select:city:Paris,Oslo}}".to_string()),
+                (108, "/broken", "x {{snippet:/missing}}".to_string()),
+            ];
+            // Each includes the next, ten deep
+            for id in 110..120 {
+                let body = if id < 119 { format!("{}.{{{{snippet:#{}}}}}", id, id + 1) } else { "end".to_string() };
+                snippets.push((id, "", body));
+            }
+            crate::engine::rebuild_index(
+                snippets
+                    .into_iter()
+                    .map(|(id, shortcut, body)| crate::database::Snippet {
+                        id,
+                        user_id: 1,
+                        folder_id: None,
+                        name: format!("snippet {}", id),
+                        shortcut: shortcut.to_string(),
+                        body,
+                        created_at: now(),
+                        updated_at: now(),
+                        is_active: true,
+                        match_type: "exact".to_string(),
+                        propagate_case: false,
+                        app_include: sqlx::types::Json(vec![]),
+                        app_exclude: sqlx::types::Json(vec![]),
+                        trusted: false,
+                        tags: sqlx::types::Json(vec![]),
+                    })
+                    .collect(),
+            );
+        });
+    }
+
+    // Expands an installed snippet with every field at its default
+    fn expand(id: i64) -> Result<String, TemplateError> {
+        install_snippets();
+        let body = crate::engine::snippet_body(&format!("#{}", id)).unwrap().body;
+        match render_step(id, &body, false, &HashMap::new(), &[], Some(&HashMap::new()), now())? {
+            RenderStep::Done(rendered) => Ok(rendered),
+            RenderStep::NeedsInput(fields) => panic!("snippet {} asked again for {:?}", id, fields),
+        }
+    }
+
+    // The error inside every snippet that included the failing one
+    fn innermost(mut error: TemplateError) -> TemplateErrorKind {
+        while let TemplateErrorKind::InIncludedSnippet { error: inner, .. } = error.kind {
+            error = *inner;
+        }
+        error.kind
+    }
+
+    #[test]
+    fn includes_other_snippets() {
+        assert_eq!(expand(104).unwrap(), "Ada");
+        // The same snippet twice side by side is no cycle
+        assert_eq!(expand(105).unwrap(), "Ada and Ada");
+        assert_eq!(expand(118).unwrap(), "118.end");
+
+        // Fields of included snippets are asked for with the rest
+        install_snippets();
+        let names: Vec<String> = fields("{{snippet:/form}} {{input:name}}").into_iter().map(|field| field.name).collect();
+        assert_eq!(names, ["name", "city"]);
+        assert_eq!(expand(106).unwrap(), "Ada in Paris");
+    }
+
+    #[test]
+    fn a_snippet_including_itself_is_a_cycle() {
+        let error = expand(101).unwrap_err();
+        assert_eq!(error.offset, 4);
+        assert_eq!(innermost(error), TemplateErrorKind::IncludeCycle(vec!["#101".to_string(), "/self".to_string()]));
+    }
+
+    #[test]
+    fn cycles_are_found_whatever_names_the_snippets() {
+        // `/a` includes `/b`, which includes `/a` again as `#102`
+        let chain = |chain: &[&str]| TemplateErrorKind::IncludeCycle(chain.iter().map(|r| r.to_string()).collect());
+        assert_eq!(innermost(expand(102).unwrap_err()), chain(&["#102", "/b", "#102"]));
+        assert_eq!(innermost(expand(103).unwrap_err()), chain(&["#103", "#102", "/b"]));
+    }
+
+    #[test]
+    fn includes_nest_only_so_deep() {
+        // 111 includes eight more, 110 nine
+        assert_eq!(expand(111).unwrap(), "111.112.113.114.115.116.117.118.end");
+        let error = expand(110).unwrap_err();
+        assert_eq!(innermost(error), TemplateErrorKind::IncludeTooDeep(MAX_INCLUDE_DEPTH));
+    }
+
+    #[test]
+    fn missing_includes_are_errors() {
+        install_snippets();
+        let error = render("Hi\n  {{snippet:/nope}}", &[]).unwrap_err();
+        assert_eq!(error.kind, TemplateErrorKind::SnippetNotFound("/nope".to_string()));
+        assert_eq!((error.line, error.column), (2, 3));
+
+        assert!(render("{{snippet:#999}}", &[]).is_err());
+
+        // Inside an include, the position is the included snippet's
+        let error = render("Hi {{snippet:/broken}}", &[]).unwrap_err();
+        assert_eq!(error.offset, 3);
+        let TemplateErrorKind::InIncludedSnippet { reference, error } = error.kind else {
+            panic!("not an include error");
+        };
+        assert_eq!(reference, "/broken");
+        assert_eq!(error.offset, 2);
+        assert_eq!(error.kind, TemplateErrorKind::SnippetNotFound("/missing".to_string()));
+    }
+
+    #[test]
+    fn renders_the_first_true_branch() {
+        let body = "{{if input.lang == \"fr\"}}Bonjour{{elif input.lang == \"de\"}}Hallo{{else}}Hello{{end}}, {{input:lang}}";
+        assert_eq!(render(body, &[("lang", one("fr"))]).unwrap(), "Bonjour, fr");
+        assert_eq!(render(body, &[("lang", one("de"))]).unwrap(), "Hallo, de");
+        assert_eq!(render(body, &[("lang", one("en"))]).unwrap(), "Hello, en");
+        // No else and nothing true renders nothing
+        assert_eq!(render("[{{if false}}x{{elif 1 > 2}}y{{end}}]", &[]).unwrap(), "[]");
+        // Branches not taken are never rendered, errors and all
+        assert_eq!(render("{{if true}}ok{{else}}{{var:missing}}{{end}}", &[]).unwrap(), "ok");
+    }
+
+    #[test]
+    fn compares_form_values_as_numbers_with_num() {
+        let body = "{{if num(input.qty) >= 10}}bulk{{elif num(input.qty) > 0}}some{{else}}none{{end}} of {{input:qty}}";
+        assert_eq!(render(body, &[("qty", one("12"))]).unwrap(), "bulk of 12");
+        // As text "9" sorts after "10"; as a number it does not
+        assert_eq!(render(body, &[("qty", one("9"))]).unwrap(), "some of 9");
+        assert_eq!(render(body, &[("qty", one(" 0 "))]).unwrap(), "none of  0 ");
+        assert_eq!(render("{{if num(input.x) < 1}}y{{end}}{{input:x}}", &[("x", one("0.5"))]).unwrap(), "y0.5");
+
+        let error = render(body, &[("qty", one("lots"))]).unwrap_err();
+        assert!(matches!(error.kind, TemplateErrorKind::InvalidExpression(_)));
+        assert_eq!(error.offset, 5);
+    }
+
+    #[test]
+    fn conditions_must_be_true_or_false() {
+        let error = render("ok\n{{if input.name}}hi{{end}}{{input:name}}", &[("name", one("Ada"))]).unwrap_err();
+        let TemplateErrorKind::InvalidExpression(reason) = &error.kind else {
+            panic!("not an expression error: {:?}", error);
+        };
+        assert!(reason.contains("not true or false"), "{}", reason);
+        assert_eq!((error.line, error.column), (2, 6));
+        assert!(render("{{if 1}}x{{end}}", &[]).is_err());
+    }
+
+    #[test]
+    fn repeats_each_body_per_item() {
+        let body = "{{multiselect:tags:a,b c,d}}: {{each tag in input.tags}}[{{var:tag}}]{{end}}";
+        assert_eq!(render(body, &[("tags", many(&["a", "b c", "d"]))]).unwrap(), "a, b c, d: [a][b c][d]");
+        assert_eq!(render(body, &[("tags", many(&["d"]))]).unwrap(), "d: [d]");
+        assert_eq!(render(body, &[("tags", many(&[]))]).unwrap(), ": ");
+        // Anything that is not a list is one item
+        assert_eq!(render("{{each x in 40 + 2}}<{{var:x}}>{{end}}", &[]).unwrap(), "<42>");
+        assert_eq!(render("{{each x in (1, 2)}}{{each y in (3, 4)}}{{var:x}}{{var:y}} {{end}}{{end}}", &[]).unwrap(), "13 14 23 24 ");
+    }
+
+    #[test]
+    fn scopes_loop_variables_and_lets() {
+        // A loop variable goes back to what it was once the loop is done
+        let body = "{{let item = \"outer\"}}{{each item in (\"a\", \"b\")}}{{var:item}}{{end}} {{var:item}}";
+        assert_eq!(render(body, &[]).unwrap(), "ab outer");
+        let error = render("{{each item in (1, 2)}}{{end}}{{var:item}}", &[]).unwrap_err();
+        assert!(error.to_string().contains("no variable named item"), "{}", error);
+
+        // Lets hold from where they are written on, and may be rebound
+        let body = "{{input:qty}}: {{let total = num(input.qty) * 2}}{{var:total}} {{let total = total + 1}}{{var:total}} {{calc:total * 10}}";
+        assert_eq!(render(body, &[("qty", one("4"))]).unwrap(), "4: 8 9 90");
+        let body = "{{let x = 1}}{{let x = \"one\"}}{{var:x}} {{each x in (2.5, true)}}{{var:x}} {{end}}{{var:x}}";
+        assert_eq!(render(body, &[]).unwrap(), "one 2.5 true one");
+        let error = render("{{var:later}}{{let later = 1}}", &[]).unwrap_err();
+        assert!(matches!(error.kind, TemplateErrorKind::InvalidArgument { placeholder: "var", .. }));
+        // Set inside a loop, a let stays set after it
+        assert_eq!(render("{{each x in (1, 2)}}{{let last = x}}{{end}}{{var:last}}", &[]).unwrap(), "2");
+    }
+
+    #[test]
+    fn finds_commands_added_by_an_edit() {
+        assert!(runs_commands("Branch: {{shell:git branch --show-current}}"));
+        assert!(runs_commands("{{if input.x}}{{script:~/bin/x}}{{end}}{{input:x}}"));
+        assert!(!runs_commands("{{clipboard}} {{date:%F}}"));
+
+        let approved = "On {{shell:git branch --show-current}} at {{time:%H:%M}}";
+        assert!(!adds_commands(approved, approved));
+        assert!(!adds_commands(approved, "{{shell:git branch --show-current}} only"));
+        assert!(!adds_commands(approved, "Nothing to run"));
+        assert!(adds_commands(approved, "On {{shell:git branch --show-current; rm -rf ~}}"));
+        assert!(adds_commands(approved, "{{shell:git branch --show-current}} {{script:~/bin/x}}"));
+        assert!(adds_commands("Plain text", "{{shell:date}}"));
+    }
+
+    #[test]
+    fn knows_what_may_block() {
+        assert!(may_block(1, "Invoice {{counter:invoice}}", false));
+        assert!(may_block(1, "{{if true}}{{counter:invoice}}{{end}}", false));
+        assert!(may_block(1, "Hi {{input:name}}", false));
+        assert!(may_block(1, "{{shell:date}}", true));
+        // Untrusted commands fail at once
+        assert!(!may_block(1, "{{shell:date}}", false));
+        assert!(may_block(1, "{{snippet:{{clipboard}}}}", false));
+        assert!(!may_block(1, "{{date:%F}} {{uuid}} {{random:1-6}} {{clipboard|upper}}", true));
+    }
+
+    fn cursor_stops(body: &str) -> (String, Vec<usize>) {
+        take_cursor_stops(&render(body, &[]).unwrap())
+    }
+
+    #[test]
+    fn counts_cursor_stops_in_graphemes() {
+        // A family emoji and an e with a combining accent are one grapheme
+        // each, however many code points they take
+        let (text, stops) = cursor_stops("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} e\u{301}{{cursor}}!");
+        assert_eq!(text, "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} e\u{301}!");
+        assert_eq!(stops, [3]);
+
+        // A line break is one grapheme, and so is \r\n
+        assert_eq!(cursor_stops("Dear\n{{cursor}},").1, [5]);
+        assert_eq!(cursor_stops("Dear\r\n{{cursor}},").1, [5]);
+    }
+
+    #[test]
+    fn orders_numbered_cursor_stops_before_the_plain_one() {
+        let (text, stops) = cursor_stops("{{cursor:3}}a{{cursor}}b{{cursor:1}}c{{cursor:2}}");
+        assert_eq!(text, "abc");
+        assert_eq!(stops, [2, 3, 0, 1]);
+
+        // A stop written twice is visited once, where it first appears
+        assert_eq!(cursor_stops("a{{cursor:1}}b{{cursor:1}}").1, [1]);
+
+        let (text, stops) = cursor_stops("No stops {{date:%Y}}");
+        assert_eq!(text, "No stops 2024");
+        assert!(stops.is_empty());
+    }
+
+    #[test]
+    fn pastes_placeholders_in_values_as_typed() {
+        let body = "{{input:note}} / {{input:note|upper}}";
+        assert_eq!(render(body, &[("note", one("due {{date:%F}}"))]).unwrap(), "due {{date:%F}} / DUE {{DATE:%F}}");
+
+        let captures = HashMap::from([("name".to_string(), "{{env:HOME}}".to_string())]);
+        match render_step(1, "Hi {{capture:name}}", true, &captures, &[], None, now()).unwrap() {
+            RenderStep::Done(rendered) => assert_eq!(rendered, "Hi {{env:HOME}}"),
+            RenderStep::NeedsInput(fields) => panic!("asked for {:?}", fields),
+        }
+    }
+
+    fn definition(key: &str, var_type: &str, default: Option<&str>, options: &[&str], validation: Option<&str>) -> VariableDefinition {
+        VariableDefinition {
+            key: key.to_string(),
+            var_type: var_type.to_string(),
+            default_value: default.map(str::to_string),
+            options: options.iter().map(|option| option.to_string()).collect(),
+            validation: validation.map(str::to_string),
+        }
+    }
+
+    fn fields_with(body: &str, definitions: Vec<VariableDefinition>) -> Vec<FormField> {
+        let variables: Vec<Variable> = definitions
+            .into_iter()
+            .map(|definition| Variable {
+                id: 0,
+                snippet_id: 1,
+                key: definition.key,
+                var_type: definition.var_type,
+                default_value: definition.default_value,
+                options: sqlx::types::Json(definition.options),
+                validation: definition.validation,
+            })
+            .collect();
+        match render_step(1, body, false, &HashMap::new(), &variables, None, now()).unwrap() {
+            RenderStep::NeedsInput(fields) => fields,
+            RenderStep::Done(rendered) => panic!("{:?} rendered without asking: {:?}", body, rendered),
+        }
+    }
+
+    #[test]
+    fn validates_variable_definitions() {
+        assert!(validate_variable(&definition("qty", "input", Some("1"), &[], Some("[0-9]+"))).is_ok());
+        assert!(validate_variable(&definition("size", "multi_select", None, &["S", "M"], None)).is_ok());
+
+        assert_eq!(validate_variable(&definition(" ", "input", None, &[], None)).unwrap_err(), "Variable key cannot be empty");
+        assert_eq!(validate_variable(&definition("qty", "number", None, &[], None)).unwrap_err(), "Unknown variable type: number");
+        let error = validate_variable(&definition("qty", "input", None, &[], Some("[0-9"))).unwrap_err();
+        assert!(error.starts_with("Invalid validation pattern"), "{}", error);
+    }
+
+    #[test]
+    fn variables_define_form_fields() {
+        // Options, default and validation come from the variable
+        let sizes = definition("size", "select", None, &["S", "M", "L"], Some("[SML]"));
+        let [size] = fields_with("{{input:size}}", vec![sizes.clone()]).try_into().unwrap();
+        assert_eq!(size.kind, FieldKind::Select);
+        assert_eq!(size.options, ["S", "M", "L"]);
+        // A select without a default starts on its first option
+        assert_eq!(size.default, "S");
+        assert_eq!(size.validation.as_deref(), Some("[SML]"));
+
+        let chosen = definition("size", "select", Some("M"), &[], None);
+        let [size] = fields_with("{{select:size:S,M}}", vec![chosen]).try_into().unwrap();
+        assert_eq!((size.kind, size.default.as_str()), (FieldKind::Select, "M"));
+        assert_eq!(size.options, ["S", "M"]);
+
+        let extras = definition("extras", "multi_select", Some("gift, card"), &["gift", "card", "bow"], None);
+        let [extras] = fields_with("{{input:extras}}", vec![extras]).try_into().unwrap();
+        assert_eq!(extras.kind, FieldKind::MultiSelect);
+        assert_eq!(extras.default_value(), many(&["gift", "card"]));
+
+        // A list with nothing to list stays an input
+        let empty = definition("size", "select", None, &[], None);
+        assert_eq!(fields_with("{{input:size}}", vec![empty]), [field("size", FieldKind::Input, "", &[])]);
+        // Types the form does not know, and other keys, leave the field alone
+        let imported = definition("size", "choice", Some("XL"), &["XL"], None);
+        let other = definition("colour", "input", Some("red"), &[], None);
+        assert_eq!(fields_with("{{input:size:M}}", vec![imported, other]), [field("size", FieldKind::Input, "M", &[])]);
+    }
+
+    #[test]
+    fn checks_values_against_the_whole_pattern() {
+        let mut qty = field("qty", FieldKind::Input, "", &[]);
+        assert!(qty.check(&one("anything")).is_ok());
+
+        qty.validation = Some("[0-9]+".to_string());
+        assert!(qty.check(&one("12")).is_ok());
+        assert_eq!(qty.check(&one("12a")).unwrap_err(), r#"qty: "12a" does not match [0-9]+"#);
+        assert!(qty.check(&one("")).is_err());
+
+        // Alternatives are anchored together, not just the first and last
+        qty.validation = Some("S|M".to_string());
+        assert!(qty.check(&one("M")).is_ok());
+        assert!(qty.check(&one("SM")).is_err());
+        assert!(qty.check(&one("Small")).is_err());
+
+        // Every value chosen in a multiselect must match
+        assert!(qty.check(&many(&["S", "M"])).is_ok());
+        assert_eq!(qty.check(&many(&["S", "XL"])).unwrap_err(), r#"qty: "XL" does not match S|M"#);
+        assert!(qty.check(&many(&[])).is_ok());
+
+        qty.validation = Some("(".to_string());
+        assert!(qty.check(&one("1")).unwrap_err().starts_with("Invalid validation pattern"));
+    }
+}
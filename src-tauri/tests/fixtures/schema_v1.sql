@@ -0,0 +1,49 @@
+-- A database as the app created it at schema version 1 (first release),
+-- before schema_version existed
+
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS folders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    parent_id INTEGER,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (parent_id) REFERENCES folders (id)
+);
+
+CREATE TABLE IF NOT EXISTS snippets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    folder_id INTEGER,
+    name TEXT NOT NULL,
+    shortcut TEXT UNIQUE NOT NULL,
+    body TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    is_active INTEGER DEFAULT 1,
+    match_type TEXT DEFAULT 'exact',
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (folder_id) REFERENCES folders (id)
+);
+
+CREATE TABLE IF NOT EXISTS variables (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    snippet_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    type TEXT NOT NULL,
+    default_value TEXT,
+    FOREIGN KEY (snippet_id) REFERENCES snippets (id)
+);
+
+INSERT INTO users (id, email, password_hash) VALUES (1, 'ann@example.com', 'hash');
+INSERT INTO folders (id, user_id, name, parent_id) VALUES (1, 1, 'Work', NULL);
+INSERT INTO snippets (id, user_id, folder_id, name, shortcut, body) VALUES (1, 1, 1, 'Signature', '/sig', 'Best,
+{{input:name}}');
+INSERT INTO snippets (id, user_id, folder_id, name, shortcut, body, is_active, match_type) VALUES (2, 1, NULL, 'Address', '/addr', '1 Main St', 0, 'delimiter');
+INSERT INTO variables (snippet_id, key, type, default_value) VALUES (1, 'name', 'input', 'Ann');
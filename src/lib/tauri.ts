@@ -1,4 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
 // Check if we're in a Tauri environment
 const isTauri = typeof window !== 'undefined' && '__TAURI__' in window;
@@ -15,7 +16,10 @@ let mockSnippets: any[] = [
     created_at: new Date().toISOString(),
     updated_at: new Date().toISOString(),
     is_active: true,
-    match_type: "exact"
+    match_type: "exact",
+    trusted: true,
+    tags: [],
+    variables: []
   },
   {
     id: 2,
@@ -27,7 +31,10 @@ let mockSnippets: any[] = [
     created_at: new Date().toISOString(),
     updated_at: new Date().toISOString(),
     is_active: true,
-    match_type: "exact"
+    match_type: "exact",
+    trusted: true,
+    tags: [],
+    variables: []
   }
 ];
 
@@ -61,13 +68,27 @@ const mockInvoke = async (cmd: string, args?: any): Promise<any> => {
         created_at: new Date().toISOString(),
         updated_at: new Date().toISOString(),
         is_active: true,
-        match_type: 'exact'
+        match_type: 'exact',
+        trusted: !/\{\{\s*(shell|script)\b/.test(args.request.body),
+        tags: args.request.tags ?? [],
+        variables: []
       };
       mockSnippets.push(newSnippet);
       return newSnippet;
+    case 'approve_snippet':
+      mockSnippets = mockSnippets.map(s => s.id === args.snippetId ? { ...s, trusted: args.approved } : s);
+      return null;
     case 'delete_snippet':
       mockSnippets = mockSnippets.filter(s => s.id !== args.snippetId);
       return null;
+    case 'list_variables':
+      return [];
+    case 'search_snippets': {
+      const terms: string[] = args.request.query.toLowerCase().split(/\s+/).filter(Boolean);
+      return mockSnippets
+        .filter(s => terms.every(term => `${s.name} ${s.shortcut} ${s.body}`.toLowerCase().includes(term)))
+        .map(s => ({ ...s, excerpt: [{ text: s.body, matched: false }] }));
+    }
     case 'get_settings':
       return { expand_enabled: true, global_hotkey: 'Ctrl+Shift+Space', excluded_apps: [] };
     default:
@@ -103,6 +124,112 @@ export interface Snippet {
   updated_at: string;
   is_active: boolean;
   match_type: string;
+  // Allowed to run {{shell}} and {{script}} commands; see approveSnippet
+  trusted: boolean;
+  tags: string[];
+  variables: Variable[];
+}
+
+export interface SearchRequest {
+  // Words to find in the name, shortcut or body, each as a prefix
+  query: string;
+  folder_id?: number | null;
+  is_active?: boolean | null;
+  // Only snippets carrying every one of these tags
+  tags?: string[];
+  limit?: number;
+}
+
+export interface ExcerptPart {
+  text: string;
+  matched: boolean;
+}
+
+export interface SearchResult extends Omit<Snippet, 'variables'> {
+  // Body text around the matches; matched parts are the words to highlight
+  excerpt: ExcerptPart[];
+}
+
+// Defines the form field with the same name as its key
+export interface VariableDefinition {
+  key: string;
+  type: 'input' | 'select' | 'multi_select';
+  default_value?: string | null;
+  options?: string[];
+  // Regex the whole value must match
+  validation?: string | null;
+}
+
+export interface Variable {
+  id: number;
+  snippet_id: number;
+  key: string;
+  var_type: string;
+  default_value?: string | null;
+  options: string[];
+  validation?: string | null;
+}
+
+export interface ImportItem {
+  name: string;
+  shortcut: string;
+  notes: string[];
+}
+
+export interface ImportReport {
+  imported: ImportItem[];
+  skipped: ImportItem[];
+  conflicts: ImportItem[];
+  dry_run: boolean;
+  folders_created: number;
+}
+
+export interface CsvColumns {
+  name: string;
+  shortcut: string;
+  body: string;
+  folder?: string | null;
+  match_type?: string | null;
+}
+
+export interface CsvImportOptions {
+  columns?: Partial<CsvColumns>;
+  delimiter?: string;
+  dry_run?: boolean;
+}
+
+export interface EspansoFile {
+  name?: string;
+  content: string;
+}
+
+export interface EspansoExport {
+  yaml: string;
+  untranslated: ImportItem[];
+}
+
+export interface FormField {
+  name: string;
+  kind: 'input' | 'select' | 'multi_select';
+  default: string;
+  options: string[];
+  validation?: string | null;
+}
+
+export interface SnippetForm {
+  id: number;
+  snippet_name: string;
+  fields: FormField[];
+}
+
+// Multiselect fields answer with every option chosen
+export type FormValues = Record<string, string | string[]>;
+
+export type ConflictPolicy = 'skip' | 'overwrite' | 'rename';
+
+export interface RestoreOptions {
+  conflict_policy?: ConflictPolicy;
+  restore_settings?: boolean;
 }
 
 export interface Settings {
@@ -129,25 +256,70 @@ export const createFolder = (userId: number, name: string, parentId?: number): P
 export const listSnippets = (userId: number, folderId?: number): Promise<Snippet[]> =>
   safeInvoke('list_snippets', { userId, folderId });
 
+export const searchSnippets = (userId: number, request: SearchRequest): Promise<SearchResult[]> =>
+  safeInvoke('search_snippets', { userId, request });
+
 export const createSnippet = (
   userId: number,
-  request: { name: string; shortcut: string; body: string; folder_id?: number }
+  request: { name: string; shortcut: string; body: string; folder_id?: number; tags?: string[] }
 ): Promise<Snippet> =>
   safeInvoke('create_snippet', { userId, request });
 
 export const updateSnippet = (
   snippetId: number,
-  request: Partial<{ name: string; shortcut: string; body: string; folder_id?: number; is_active: boolean }>
+  request: Partial<{ name: string; shortcut: string; body: string; folder_id?: number; is_active: boolean; tags: string[] }>
 ): Promise<void> =>
   safeInvoke('update_snippet', { snippetId, request });
 
+// Snippets with commands start unapproved, and so does an edit adding one
+export const approveSnippet = (snippetId: number, approved: boolean): Promise<void> =>
+  safeInvoke('approve_snippet', { snippetId, approved });
+
 export const deleteSnippet = (snippetId: number): Promise<void> =>
   safeInvoke('delete_snippet', { snippetId });
 
+// Variable definitions
+export const listVariables = (snippetId: number): Promise<Variable[]> =>
+  safeInvoke('list_variables', { snippetId });
+
+export const createVariable = (userId: number, snippetId: number, request: VariableDefinition): Promise<Variable> =>
+  safeInvoke('create_variable', { userId, snippetId, request });
+
+export const updateVariable = (variableId: number, request: VariableDefinition): Promise<void> =>
+  safeInvoke('update_variable', { variableId, request });
+
+export const deleteVariable = (variableId: number): Promise<void> =>
+  safeInvoke('delete_variable', { variableId });
+
 // Import/Export
-export const importTextBlaze = (userId: number, jsonData: string): Promise<string> =>
+export const importTextBlaze = (userId: number, jsonData: string): Promise<ImportReport> =>
   safeInvoke('import_textblaze', { userId, jsonData });
 
+export const importCsv = (userId: number, csvData: string, options?: CsvImportOptions): Promise<ImportReport> =>
+  safeInvoke('import_csv', { userId, csvData, options });
+
+export const exportCsv = (userId: number, columns?: CsvColumns, delimiter?: string): Promise<string> =>
+  safeInvoke('export_csv', { userId, columns, delimiter });
+
+export const importEspanso = (userId: number, files: EspansoFile[]): Promise<ImportReport> =>
+  safeInvoke('import_espanso', { userId, files });
+
+export const exportEspanso = (userId: number): Promise<EspansoExport> =>
+  safeInvoke('export_espanso', { userId });
+
+export const exportBackup = (userId: number): Promise<string> =>
+  safeInvoke('export_backup', { userId });
+
+export const importBackup = (userId: number, jsonData: string, options?: RestoreOptions): Promise<ImportReport> =>
+  safeInvoke('import_backup', { userId, jsonData, options });
+
+// Snippet forms
+export const getSnippetForm = (id: number): Promise<SnippetForm> =>
+  safeInvoke('get_snippet_form', { id });
+
+export const submitSnippetForm = (id: number, values: FormValues | null): Promise<void> =>
+  safeInvoke('submit_snippet_form', { id, values });
+
 // Settings
 export const getSettings = (): Promise<Settings> =>
   safeInvoke('get_settings');
@@ -156,4 +328,9 @@ export const updateSettings = (settings: Settings): Promise<void> =>
   safeInvoke('update_settings', { settings });
 
 export const reloadEngine = (): Promise<void> =>
-  safeInvoke('reload_engine');
\ No newline at end of file
+  safeInvoke('reload_engine');
+
+// Called when the global hotkey brings the window forward; resolves to a
+// function that stops listening
+export const onOpenSearch = (handler: () => void): Promise<() => void> =>
+  isTauri ? listen('open-search', handler) : Promise.resolve(() => {});
\ No newline at end of file
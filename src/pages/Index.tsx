@@ -1,4 +1,4 @@
-import { useState, useMemo, useEffect } from "react";
+import { useState, useMemo, useEffect, useRef } from "react";
 import { 
   Flame, 
   ChevronDown, 
@@ -15,7 +15,7 @@ import { ImportDialog } from "@/components/ImportDialog";
 import AddSnippetDialog from "@/components/AddSnippetDialog";
 import AddFolderDialog from "@/components/AddFolderDialog";
 import { Label } from "@/components/ui/label";
-import { Snippet as TauriSnippet, Folder, listSnippets, listFolders, createSnippet, deleteSnippet, createFolder } from "@/lib/tauri";
+import { Snippet as TauriSnippet, Folder, listSnippets, listFolders, createSnippet, deleteSnippet, createFolder, onOpenSearch } from "@/lib/tauri";
 import { toast } from "@/hooks/use-toast";
 
 interface DisplayCategory {
@@ -30,6 +30,7 @@ const Index = () => {
   const [folders, setFolders] = useState<Folder[]>([]);
   const [snippets, setSnippets] = useState<TauriSnippet[]>([]);
   const [searchTerm, setSearchTerm] = useState("");
+  const searchInput = useRef<HTMLInputElement>(null);
   const [testText, setTestText] = useState("");
 
   // Simple text expansion for demo purposes
@@ -55,6 +56,17 @@ const Index = () => {
     loadData();
   }, []);
 
+  // The global hotkey shows this window; jump straight to the search field
+  useEffect(() => {
+    const stopListening = onOpenSearch(() => {
+      searchInput.current?.focus();
+      searchInput.current?.select();
+    });
+    return () => {
+      stopListening.then((stop) => stop());
+    };
+  }, []);
+
   const loadData = async () => {
     try {
       const [foldersData, snippetsData] = await Promise.all([
@@ -149,6 +161,7 @@ const Index = () => {
           <div className="relative mb-4">
             <Search className="absolute left-3 top-1/2 transform -translate-y-1/2 text-muted-foreground h-4 w-4" />
             <Input
+              ref={searchInput}
               placeholder="Search..."
               value={searchTerm}
               onChange={(e) => setSearchTerm(e.target.value)}
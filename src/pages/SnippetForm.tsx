@@ -0,0 +1,137 @@
+import { useEffect, useState } from "react";
+import { useParams } from "react-router-dom";
+import { Button } from "@/components/ui/button";
+import { Checkbox } from "@/components/ui/checkbox";
+import { Input } from "@/components/ui/input";
+import { Label } from "@/components/ui/label";
+import {
+  Select,
+  SelectContent,
+  SelectItem,
+  SelectTrigger,
+  SelectValue,
+} from "@/components/ui/select";
+import { FormField, FormValues, getSnippetForm, submitSnippetForm, SnippetForm } from "@/lib/tauri";
+
+// Multiselect defaults list the options chosen at first, comma-separated
+const initialValue = (field: FormField) =>
+  field.kind === "multi_select"
+    ? field.default
+        .split(",")
+        .map((value) => value.trim())
+        .filter((value) => value !== "")
+    : field.default;
+
+// Shown in its own window while an expansion waits for form values
+const SnippetFormPage = () => {
+  const { id } = useParams();
+  const formId = Number(id);
+  const [form, setForm] = useState<SnippetForm | null>(null);
+  const [values, setValues] = useState<FormValues>({});
+  const [error, setError] = useState<string | null>(null);
+  // A value failed its field's validation; the form stays open
+  const [submitError, setSubmitError] = useState<string | null>(null);
+
+  useEffect(() => {
+    getSnippetForm(formId)
+      .then((loaded) => {
+        setForm(loaded);
+        setValues(Object.fromEntries(loaded.fields.map((field) => [field.name, initialValue(field)])));
+      })
+      .catch((e) => setError(String(e)));
+  }, [formId]);
+
+  const toggle = (name: string, option: string, checked: boolean) => {
+    const chosen = (values[name] as string[] | undefined) ?? [];
+    setValues({
+      ...values,
+      [name]: checked ? [...chosen, option] : chosen.filter((value) => value !== option),
+    });
+  };
+
+  const submit = () => {
+    setSubmitError(null);
+    submitSnippetForm(formId, values).catch((e) => setSubmitError(String(e)));
+  };
+  const cancel = () => submitSnippetForm(formId, null);
+
+  useEffect(() => {
+    const onKeyDown = (event: KeyboardEvent) => {
+      if (event.key === "Escape") {
+        cancel();
+      }
+    };
+    window.addEventListener("keydown", onKeyDown);
+    return () => window.removeEventListener("keydown", onKeyDown);
+  }, [formId]);
+
+  if (error) {
+    return <div className="p-4 text-sm text-destructive">{error}</div>;
+  }
+
+  if (!form) {
+    return null;
+  }
+
+  return (
+    <form
+      className="p-4 space-y-4"
+      onSubmit={(event) => {
+        event.preventDefault();
+        submit();
+      }}
+    >
+      <h1 className="text-lg font-semibold">{form.snippet_name}</h1>
+      {form.fields.map((field, index) => (
+        <div key={field.name} className="space-y-2">
+          <Label htmlFor={field.name}>{field.name}</Label>
+          {field.kind === "multi_select" ? (
+            <div id={field.name} className="space-y-2">
+              {field.options.map((option) => (
+                <label key={option} className="flex items-center gap-2 text-sm">
+                  <Checkbox
+                    checked={((values[field.name] as string[] | undefined) ?? []).includes(option)}
+                    onCheckedChange={(checked) => toggle(field.name, option, checked === true)}
+                  />
+                  {option}
+                </label>
+              ))}
+            </div>
+          ) : field.kind === "select" ? (
+            <Select
+              value={values[field.name] as string}
+              onValueChange={(value) => setValues({ ...values, [field.name]: value })}
+            >
+              <SelectTrigger id={field.name}>
+                <SelectValue />
+              </SelectTrigger>
+              <SelectContent>
+                {field.options.map((option) => (
+                  <SelectItem key={option} value={option}>
+                    {option}
+                  </SelectItem>
+                ))}
+              </SelectContent>
+            </Select>
+          ) : (
+            <Input
+              id={field.name}
+              autoFocus={index === 0}
+              value={(values[field.name] as string | undefined) ?? ""}
+              onChange={(event) => setValues({ ...values, [field.name]: event.target.value })}
+            />
+          )}
+        </div>
+      ))}
+      {submitError && <div className="text-sm text-destructive">{submitError}</div>}
+      <div className="flex justify-end gap-2">
+        <Button type="button" variant="outline" onClick={cancel}>
+          Cancel
+        </Button>
+        <Button type="submit">Insert</Button>
+      </div>
+    </form>
+  );
+};
+
+export default SnippetFormPage;